@@ -0,0 +1,48 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use rsesl::message::Message;
+
+const CONTENT_TYPES: [&str; 8] = [
+    "text/event-json",
+    "text/event-plain",
+    "text/disconnect-notice",
+    "command/reply",
+    "api/response",
+    "auth/request",
+    "log/data",
+    "text/event-xml",
+];
+
+// a well formed frame of every content type around a fuzzed body
+#[derive(Debug, Arbitrary)]
+struct Frame {
+    content_type: u8,
+    headers: Vec<(String, String)>,
+    // Content-Length, the body length when None
+    length: Option<u16>,
+    body: Vec<u8>,
+}
+
+fuzz_target!(|f: Frame| {
+    let ct = CONTENT_TYPES[f.content_type as usize % CONTENT_TYPES.len()];
+    let mut data = format!("Content-Type: {}\n", ct);
+    for (k, v) in &f.headers {
+        data.push_str(&format!("{}: {}\n", k, v));
+    }
+    let length = f.length.map(usize::from).unwrap_or(f.body.len());
+    data.push_str(&format!("Content-Length: {}\n\n", length));
+    let mut data = data.into_bytes();
+    data.extend(&f.body);
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        let mut r = data.as_slice();
+        while !r.is_empty() {
+            let _ = Message::parse(&mut r).await;
+        }
+    });
+});
@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rsesl::message::Message;
+
+// any bytes, parsed frame after frame until they run out
+fuzz_target!(|data: &[u8]| {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        let mut r = data;
+        while !r.is_empty() {
+            let _ = Message::parse(&mut r).await;
+        }
+    });
+});
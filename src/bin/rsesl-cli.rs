@@ -0,0 +1,265 @@
+use anyhow::Result;
+use clap::Parser;
+use rsesl::{
+    client::Client,
+    event::EventHandler,
+    message::{ContentType, FormatType, Message, MsgError},
+    session::Session,
+};
+use rustyline::{error::ReadlineError, DefaultEditor, ExternalPrinter};
+use std::{path::PathBuf, process::exit, str::FromStr, sync::Arc};
+use tokio::sync::{broadcast, mpsc, Mutex};
+
+// exit codes of -x
+const EXIT_ERR: i32 = 1;
+const EXIT_CONN: i32 = 2;
+
+const HELP: &str = "\
+Commands:
+  <api command>                 run a blocking api command
+  bgapi <api command>           run a background api command
+  /event [plain|json] <events>  subscribe to events, ALL by default
+  /noevents                     stop all events
+  /log [level]                  show logs, debug by default
+  /nolog                        stop logs
+  /filter <header> <value>      only get events with this header value
+  /filter delete <header>       remove a filter
+  /help                         this help
+  /exit, /quit, /bye, ...       leave";
+
+/// FreeSWITCH event socket command line.
+#[derive(Parser, Debug)]
+#[command(name = "rsesl-cli")]
+struct Args {
+    #[arg(short = 'H', long, default_value = "127.0.0.1")]
+    host: String,
+    #[arg(short = 'P', long, default_value_t = 8021)]
+    port: u16,
+    #[arg(short, long, default_value = "ClueCon")]
+    password: String,
+    /// Run the api command and exit, may be given more than once
+    #[arg(short = 'x', long = "execute")]
+    execute: Vec<String>,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+
+    let (tx, _) = broadcast::channel(1024);
+    // the session stops when this sender goes away
+    let (_shutdown, signal) = broadcast::channel(1);
+    let client = Client::new(format!("{}:{}", args.host, args.port), args.password);
+
+    let session = match client.connect(tx, signal).await {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("-ERR {}", e);
+            exit(EXIT_CONN);
+        }
+    };
+
+    if !args.execute.is_empty() {
+        let code = execute(&session, &args.execute).await;
+        session.close().await;
+        exit(code);
+    }
+    if let Err(e) = interactive(session, &args.host).await {
+        eprintln!("-ERR {}", e);
+        exit(EXIT_CONN);
+    }
+}
+
+// -x mode, 0 when every command answered without -ERR
+async fn execute(session: &Session, cmds: &[String]) -> i32 {
+    let mut code = 0;
+    for cmd in cmds {
+        match session.api(cmd).await {
+            Ok(body) => print_body(&body),
+            Err(e) => match e.downcast_ref::<MsgError>() {
+                Some(MsgError::ErrResponse(s)) => {
+                    println!("{}", s);
+                    code = EXIT_ERR;
+                }
+                _ => {
+                    eprintln!("-ERR {}", e);
+                    return EXIT_CONN;
+                }
+            },
+        }
+    }
+    code
+}
+
+async fn interactive(session: Session, host: &str) -> Result<()> {
+    let mut rl = DefaultEditor::new()?;
+    let history = history_path();
+    if let Some(h) = &history {
+        let _ = rl.load_history(h);
+    }
+    let printer = rl.create_external_printer()?;
+    let mut printing = tokio::spawn(print_messages(session.subscribe(), printer));
+
+    // rustyline blocks, it reads on its own thread and waits for the
+    // command to finish before showing the next prompt
+    let (line_tx, mut line_rx) = mpsc::channel::<Option<String>>(1);
+    let (ack_tx, ack_rx) = std::sync::mpsc::channel::<bool>();
+    let prompt = format!("freeswitch@{}> ", host);
+    std::thread::spawn(move || {
+        loop {
+            let line = match rl.readline(&prompt) {
+                Ok(l) => {
+                    if !l.trim().is_empty() {
+                        let _ = rl.add_history_entry(l.as_str());
+                    }
+                    Some(l)
+                }
+                Err(ReadlineError::Interrupted) => continue,
+                Err(_) => None,
+            };
+            let eof = line.is_none();
+            if line_tx.blocking_send(line).is_err() || eof {
+                break;
+            }
+            if !ack_rx.recv().unwrap_or(false) {
+                break;
+            }
+        }
+        if let Some(h) = &history {
+            let _ = rl.save_history(h);
+        }
+    });
+
+    loop {
+        tokio::select! {
+            line = line_rx.recv() => {
+                let line = match line.flatten() {
+                    Some(l) => l,
+                    None => break,
+                };
+                let go_on = run_line(&session, line.trim()).await;
+                let _ = ack_tx.send(go_on);
+                if !go_on {
+                    break;
+                }
+            }
+            _ = &mut printing => {
+                println!("Disconnected");
+                break;
+            }
+        }
+    }
+    session.close().await;
+    Ok(())
+}
+
+// false when the user wants to leave
+async fn run_line(session: &Session, line: &str) -> bool {
+    let (cmd, rest) = match line.split_once(' ') {
+        Some((c, r)) => (c, r.trim()),
+        None => (line, ""),
+    };
+
+    let r = match cmd {
+        "" => return true,
+        "/exit" | "/quit" | "/bye" | "..." => return false,
+        "/help" => Ok(HELP.to_string()),
+        "/event" => subscribe(session, rest).await,
+        "/noevents" => reply(session, "noevents").await,
+        "/log" => {
+            let level = if rest.is_empty() { "debug" } else { rest };
+            reply(session, &format!("log {}", level)).await
+        }
+        "/nolog" => reply(session, "nolog").await,
+        "/filter" if !rest.is_empty() => reply(session, &format!("filter {}", rest)).await,
+        "bgapi" if !rest.is_empty() => session
+            .bgapi(rest)
+            .await
+            .map(|job| format!("+OK Job-UUID: {}", job)),
+        c if c.starts_with('/') => Ok(format!("-ERR unknown command {}, try /help", c)),
+        _ => session.api(line).await,
+    };
+
+    match r {
+        Ok(s) => print_body(&s),
+        Err(e) => match e.downcast_ref::<MsgError>() {
+            Some(MsgError::ErrResponse(s)) => println!("{}", s),
+            _ => println!("-ERR {}", e),
+        },
+    }
+    true
+}
+
+// `/event [plain|json] [events]`
+async fn subscribe(session: &Session, rest: &str) -> Result<String> {
+    let mut words: Vec<&str> = rest.split_whitespace().collect();
+    let format = match words.first().map(|w| FormatType::from_str(w)) {
+        Some(Ok(FormatType::Xml)) => {
+            return Ok("-ERR only plain and json events can be shown".to_string())
+        }
+        Some(Ok(f)) => {
+            words.remove(0);
+            f
+        }
+        _ => FormatType::Plain,
+    };
+    if words.is_empty() {
+        words.push("ALL");
+    }
+    session.event(format, &words).await?;
+    Ok(format!("+OK event listener enabled {}", words.join(" ")))
+}
+
+async fn reply(session: &Session, cmd: &str) -> Result<String> {
+    let mut msg = session.command(cmd).await?;
+    Ok(msg.get_header("Reply-Text"))
+}
+
+async fn print_messages(
+    mut rx: broadcast::Receiver<Arc<Mutex<Message>>>,
+    mut printer: impl ExternalPrinter,
+) {
+    loop {
+        let msg = match rx.recv().await {
+            Ok(m) => m,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                let _ = printer.print(format!("-- skipped {} messages", n));
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        let msg = msg.lock().await;
+        let out = match (msg.content_type(), &msg.event_data) {
+            (Some(ContentType::LogData), _) => msg.body.clone().unwrap_or_default(),
+            (Some(ContentType::TextEventJson), Some(ed)) => {
+                serde_json::to_string_pretty(ed).unwrap_or_default()
+            }
+            (Some(ContentType::TextEventPlain | ContentType::TextEventXml), Some(ed)) => {
+                let mut out = String::from("RECV EVENT\n");
+                for k in ed.keys().filter(|k| *k != "_body") {
+                    out.push_str(&format!("{}: {}\n", k, ed.get_header(k.to_string())));
+                }
+                let body = ed.get_header("_body".to_string());
+                if !body.is_empty() {
+                    out.push_str(&format!("\n{}\n", body));
+                }
+                out
+            }
+            (Some(ContentType::TextDisconnectNotice), _) => return,
+            _ => continue,
+        };
+        let _ = printer.print(out.trim_end().to_string());
+    }
+}
+
+fn print_body(body: &str) {
+    if body.ends_with('\n') {
+        print!("{}", body);
+    } else {
+        println!("{}", body);
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".rsesl_history"))
+}
@@ -0,0 +1,400 @@
+use crate::event::{Event, EventData, EventHandler};
+use crate::message::MsgError;
+use crate::session::Session;
+use anyhow::Result;
+use std::{collections::HashMap, fmt::Display};
+use tokio::{
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+};
+use tracing::{debug, warn};
+
+pub const INFO: &str = "callcenter::info";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AgentType {
+    Callback,
+    UuidStandby,
+}
+
+impl Display for AgentType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AgentType::Callback => write!(f, "Callback"),
+            AgentType::UuidStandby => write!(f, "uuid-standby"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AgentStatus {
+    LoggedOut,
+    Available,
+    AvailableOnDemand,
+    OnBreak,
+    Unknown(String),
+}
+
+impl From<&str> for AgentStatus {
+    fn from(value: &str) -> Self {
+        match value {
+            "Logged Out" => AgentStatus::LoggedOut,
+            "Available" => AgentStatus::Available,
+            "Available (On Demand)" => AgentStatus::AvailableOnDemand,
+            "On Break" => AgentStatus::OnBreak,
+            _ => AgentStatus::Unknown(value.to_string()),
+        }
+    }
+}
+
+impl Display for AgentStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AgentStatus::LoggedOut => write!(f, "Logged Out"),
+            AgentStatus::Available => write!(f, "Available"),
+            AgentStatus::AvailableOnDemand => write!(f, "Available (On Demand)"),
+            AgentStatus::OnBreak => write!(f, "On Break"),
+            AgentStatus::Unknown(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AgentState {
+    Idle,
+    Waiting,
+    Receiving,
+    InQueueCall,
+    Unknown(String),
+}
+
+impl From<&str> for AgentState {
+    fn from(value: &str) -> Self {
+        match value {
+            "Idle" => AgentState::Idle,
+            "Waiting" => AgentState::Waiting,
+            "Receiving" => AgentState::Receiving,
+            "In a queue call" => AgentState::InQueueCall,
+            _ => AgentState::Unknown(value.to_string()),
+        }
+    }
+}
+
+impl Display for AgentState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AgentState::Idle => write!(f, "Idle"),
+            AgentState::Waiting => write!(f, "Waiting"),
+            AgentState::Receiving => write!(f, "Receiving"),
+            AgentState::InQueueCall => write!(f, "In a queue call"),
+            AgentState::Unknown(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// A tier attribute for `tier set`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TierField {
+    Level(u32),
+    Position(u32),
+    State(String),
+}
+
+/// One row of a `callcenter_config ... list` table.
+pub type Row = HashMap<String, String>;
+
+#[derive(Debug, Clone)]
+pub struct QueueMember {
+    pub queue: String,
+    pub uuid: String,
+    pub session_uuid: String,
+    pub cid_number: String,
+    pub cid_name: String,
+    pub joined_epoch: u64,
+    pub serving_agent: String,
+    pub state: String,
+    pub score: i64,
+}
+
+impl From<&Row> for QueueMember {
+    fn from(r: &Row) -> Self {
+        let text = |k: &str| r.get(k).cloned().unwrap_or_default();
+        QueueMember {
+            queue: text("queue"),
+            uuid: text("uuid"),
+            session_uuid: text("session_uuid"),
+            cid_number: text("cid_number"),
+            cid_name: text("cid_name"),
+            joined_epoch: text("joined_epoch").parse().unwrap_or_default(),
+            serving_agent: text("serving_agent"),
+            state: text("state"),
+            score: text("score").parse().unwrap_or_default(),
+        }
+    }
+}
+
+/// Typed access to `callcenter_config` of mod_callcenter.
+#[derive(Debug, Clone)]
+pub struct Callcenter {
+    session: Session,
+}
+
+impl Callcenter {
+    pub fn new(session: Session) -> Self {
+        Callcenter { session }
+    }
+
+    pub async fn agent_add(&self, agent: &str, kind: AgentType) -> Result<()> {
+        self.cmd(&format!("agent add {} {}", agent, kind)).await?;
+        Ok(())
+    }
+
+    pub async fn agent_del(&self, agent: &str) -> Result<()> {
+        self.cmd(&format!("agent del {}", agent)).await?;
+        Ok(())
+    }
+
+    pub async fn agent_set_status(&self, agent: &str, status: AgentStatus) -> Result<()> {
+        self.cmd(&format!("agent set status {} '{}'", agent, status))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn agent_set_state(&self, agent: &str, state: AgentState) -> Result<()> {
+        self.cmd(&format!("agent set state {} '{}'", agent, state))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn tier_add(
+        &self,
+        queue: &str,
+        agent: &str,
+        level: u32,
+        position: u32,
+    ) -> Result<()> {
+        self.cmd(&format!(
+            "tier add {} {} {} {}",
+            queue, agent, level, position
+        ))
+        .await?;
+        Ok(())
+    }
+
+    pub async fn tier_set(&self, queue: &str, agent: &str, field: TierField) -> Result<()> {
+        let (k, v) = match field {
+            TierField::Level(l) => ("level", l.to_string()),
+            TierField::Position(p) => ("position", p.to_string()),
+            TierField::State(s) => ("state", format!("'{}'", s)),
+        };
+        self.cmd(&format!("tier set {} {} {} {}", k, queue, agent, v))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn tier_del(&self, queue: &str, agent: &str) -> Result<()> {
+        self.cmd(&format!("tier del {} {}", queue, agent)).await?;
+        Ok(())
+    }
+
+    pub async fn queue_list(&self) -> Result<Vec<Row>> {
+        let body = self.cmd("queue list").await?;
+        Ok(parse_table(&body))
+    }
+
+    /// Number of members waiting in `queue`.
+    pub async fn queue_count(&self, queue: &str) -> Result<u64> {
+        let body = self.cmd(&format!("queue count {}", queue)).await?;
+        let count = body
+            .lines()
+            .next()
+            .and_then(|l| l.trim().parse().ok())
+            .ok_or_else(|| MsgError::ErrResponse(body.trim().to_string()))?;
+        Ok(count)
+    }
+
+    pub async fn queue_load(&self, queue: &str) -> Result<()> {
+        self.cmd(&format!("queue load {}", queue)).await?;
+        Ok(())
+    }
+
+    pub async fn queue_reload(&self, queue: &str) -> Result<()> {
+        self.cmd(&format!("queue reload {}", queue)).await?;
+        Ok(())
+    }
+
+    pub async fn member_list(&self) -> Result<Vec<QueueMember>> {
+        let body = self.cmd("member list").await?;
+        Ok(parse_table(&body).iter().map(QueueMember::from).collect())
+    }
+
+    /// Decoded `callcenter::info` events, until the handle is aborted or the
+    /// receiver dropped. The events come in the format the session already
+    /// uses, JSON if none.
+    pub async fn events(&self) -> Result<(mpsc::Receiver<CallcenterEvent>, JoinHandle<()>)> {
+        let mut rx = self.session.subscribe();
+        self.session.add_events(&["CUSTOM", INFO]).await?;
+
+        let (tx, events) = mpsc::channel(1000);
+        let task = tokio::spawn(async move {
+            loop {
+                let msg = match rx.recv().await {
+                    Ok(m) => m,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("callcenter events lagged {} messages", n);
+                        #[cfg(feature = "metrics")]
+                        crate::metrics::lagged("callcenter", n);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        debug!("session closed, stop callcenter events");
+                        return;
+                    }
+                };
+                let ed = match &msg.lock().await.event_data {
+                    Some(ed) => ed.clone(),
+                    None => continue,
+                };
+                if ed.event() != Event::Custom || ed.subclass() != INFO {
+                    continue;
+                }
+                if tx.send(CallcenterEvent::from(&ed)).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        Ok((events, task))
+    }
+
+    async fn cmd(&self, cmd: &str) -> Result<String> {
+        self.session
+            .api(&format!("callcenter_config {}", cmd))
+            .await
+    }
+}
+
+/// Parse the `a|b|c` tables printed by the list commands, ending with `+OK`.
+pub fn parse_table(body: &str) -> Vec<Row> {
+    let mut lines = body.lines().filter(|l| !l.trim().is_empty());
+    let header: Vec<&str> = match lines.next() {
+        Some(h) => h.split('|').collect(),
+        None => return vec![],
+    };
+    lines
+        .take_while(|l| !l.starts_with("+OK"))
+        .map(|l| {
+            header
+                .iter()
+                .zip(l.split('|'))
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub enum CallcenterEvent {
+    MemberQueueStart {
+        queue: String,
+        member_uuid: String,
+        member_session_uuid: String,
+        cid_name: String,
+        cid_number: String,
+    },
+    MemberQueueEnd {
+        queue: String,
+        member_uuid: String,
+        member_session_uuid: String,
+        cause: String,
+    },
+    AgentOffering {
+        queue: String,
+        agent: String,
+        member_uuid: String,
+        member_session_uuid: String,
+    },
+    BridgeAgentStart {
+        queue: String,
+        agent: String,
+        agent_uuid: String,
+        member_uuid: String,
+    },
+    BridgeAgentEnd {
+        queue: String,
+        agent: String,
+        member_uuid: String,
+        hangup_cause: String,
+    },
+    MembersCount {
+        queue: String,
+        count: u64,
+    },
+    AgentStatusChange {
+        agent: String,
+        status: AgentStatus,
+    },
+    AgentStateChange {
+        agent: String,
+        state: AgentState,
+    },
+    Other {
+        action: String,
+        data: EventData,
+    },
+}
+
+impl From<&EventData> for CallcenterEvent {
+    fn from(ed: &EventData) -> Self {
+        let h = |k: &str| ed.get_header(k.to_string());
+        match h("CC-Action").as_str() {
+            "member-queue-start" => CallcenterEvent::MemberQueueStart {
+                queue: h("CC-Queue"),
+                member_uuid: h("CC-Member-UUID"),
+                member_session_uuid: h("CC-Member-Session-UUID"),
+                cid_name: h("CC-Member-CID-Name"),
+                cid_number: h("CC-Member-CID-Number"),
+            },
+            "member-queue-end" => CallcenterEvent::MemberQueueEnd {
+                queue: h("CC-Queue"),
+                member_uuid: h("CC-Member-UUID"),
+                member_session_uuid: h("CC-Member-Session-UUID"),
+                cause: h("CC-Cause"),
+            },
+            "agent-offering" => CallcenterEvent::AgentOffering {
+                queue: h("CC-Queue"),
+                agent: h("CC-Agent"),
+                member_uuid: h("CC-Member-UUID"),
+                member_session_uuid: h("CC-Member-Session-UUID"),
+            },
+            "bridge-agent-start" => CallcenterEvent::BridgeAgentStart {
+                queue: h("CC-Queue"),
+                agent: h("CC-Agent"),
+                agent_uuid: h("CC-Agent-UUID"),
+                member_uuid: h("CC-Member-UUID"),
+            },
+            "bridge-agent-end" => CallcenterEvent::BridgeAgentEnd {
+                queue: h("CC-Queue"),
+                agent: h("CC-Agent"),
+                member_uuid: h("CC-Member-UUID"),
+                hangup_cause: h("CC-Hangup-Cause"),
+            },
+            "members-count" => CallcenterEvent::MembersCount {
+                queue: h("CC-Queue"),
+                count: h("CC-Count").parse().unwrap_or_default(),
+            },
+            "agent-status-change" => CallcenterEvent::AgentStatusChange {
+                agent: h("CC-Agent"),
+                status: AgentStatus::from(h("CC-Agent-Status").as_str()),
+            },
+            "agent-state-change" => CallcenterEvent::AgentStateChange {
+                agent: h("CC-Agent"),
+                state: AgentState::from(h("CC-Agent-State").as_str()),
+            },
+            action => CallcenterEvent::Other {
+                action: action.to_string(),
+                data: ed.clone(),
+            },
+        }
+    }
+}
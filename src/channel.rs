@@ -0,0 +1,344 @@
+use crate::event::{Event, EventData, EventHandler};
+use crate::message::{FormatType, Message, MsgError};
+use crate::session::Session;
+use anyhow::Result;
+use regex::Regex;
+use serde_json::Value;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::{broadcast, Mutex};
+use tokio::time::{timeout_at, Instant};
+use tracing::warn;
+use urlencoding::decode;
+
+/// A single call leg, addressed by its `Unique-ID`.
+#[derive(Debug, Clone)]
+pub struct ChannelHandle {
+    session: Session,
+    uuid: String,
+    // the events the helpers wait for are subscribed to, always true after
+    // `myevents`
+    subscribed: Arc<AtomicBool>,
+}
+
+// what `execute_wait`, `collect_dtmf` and the media helpers wait for
+const CHANNEL_EVENTS: [&str; 6] = [
+    "CHANNEL_EXECUTE_COMPLETE",
+    "CHANNEL_HANGUP",
+    "CHANNEL_HANGUP_COMPLETE",
+    "DTMF",
+    "PLAYBACK_STOP",
+    "RECORD_STOP",
+];
+
+impl ChannelHandle {
+    /// Inbound mode: a channel of `session`. The events its helpers wait for
+    /// are subscribed to on first use, in the format already in use.
+    pub fn new(session: Session, uuid: &str) -> Self {
+        ChannelHandle {
+            session,
+            uuid: uuid.to_string(),
+            subscribed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Outbound mode: send `connect` and `myevents` on a session accepted by
+    /// `Server` and return the handle together with the channel data.
+    pub async fn connect(session: Session) -> Result<(Self, EventData)> {
+        let msg = session.command("connect").await?;
+        let mut data = EventData::new();
+        if let Some(h) = &msg.header {
+            for (k, v) in h {
+                let v = decode(v).map(|v| v.to_string()).unwrap_or(v.to_string());
+                data.insert(k.to_string(), Value::String(v));
+            }
+        }
+        session.myevents(FormatType::Json).await?;
+
+        let uuid = data.get_header("Unique-ID".to_string());
+        let channel = ChannelHandle::new(session, &uuid);
+        channel.subscribed.store(true, Ordering::SeqCst);
+        Ok((channel, data))
+    }
+
+    pub fn uuid(&self) -> &str {
+        &self.uuid
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    // subscribe to CHANNEL_EVENTS once, `myevents` has them already
+    pub(crate) async fn channel_events(&self) -> Result<()> {
+        if self.subscribed.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        if let Err(e) = self.session.add_events(&CHANNEL_EVENTS).await {
+            self.subscribed.store(false, Ordering::SeqCst);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Execute a dialplan application, returns the `Application-UUID` used to
+    /// match its CHANNEL_EXECUTE_COMPLETE.
+    pub async fn execute(&self, app: &str, arg: &str) -> Result<String> {
+        let app_uuid = uuid::Uuid::new_v4().to_string();
+        let mut ed = EventData::new();
+        for (k, v) in [
+            ("call-command", "execute"),
+            ("execute-app-name", app),
+            ("Event-UUID", app_uuid.as_str()),
+        ] {
+            ed.insert(k.to_string(), Value::String(v.to_string()));
+        }
+        if !arg.is_empty() {
+            ed.insert(
+                "execute-app-arg".to_string(),
+                Value::String(arg.to_string()),
+            );
+        }
+        self.session.sendmsg(Some(&self.uuid), ed, None).await?;
+        Ok(app_uuid)
+    }
+
+    /// Execute an application and wait for its CHANNEL_EXECUTE_COMPLETE.
+    pub async fn execute_wait(&self, app: &str, arg: &str) -> Result<EventData> {
+        // subscribe before sending so the completion can't be missed
+        self.channel_events().await?;
+        let mut rx = self.session.subscribe();
+        let app_uuid = self.execute(app, arg).await?;
+
+        while let Some(ed) = next_event(&mut rx).await {
+            if ed.get_header("Unique-ID".to_string()) != self.uuid {
+                continue;
+            }
+            match ed.event() {
+                Event::ChannelExecuteComplete
+                    if ed.get_header("Application-UUID".to_string()) == app_uuid =>
+                {
+                    return Ok(ed);
+                }
+                Event::ChannelHangupComplete => return Err(MsgError::Hangup.into()),
+                _ => {}
+            }
+        }
+        Err(MsgError::ConnectionClosed.into())
+    }
+
+    /// Collect digits from DTMF events, or with `play_and_get_digits` when a
+    /// prompt is set.
+    pub async fn collect_dtmf(&self, opts: CollectOptions) -> Result<CollectResult> {
+        let re = match &opts.regex {
+            Some(r) => Some(Regex::new(r)?),
+            None => None,
+        };
+        let mut result = match &opts.prompt {
+            Some(prompt) => self.play_and_get_digits(&opts, prompt).await?,
+            None => self.collect_events(&opts).await?,
+        };
+        result.valid = !result.digits.is_empty()
+            && result.digits.len() >= opts.min_digits
+            && match &re {
+                Some(re) => re.is_match(&result.digits),
+                None => true,
+            };
+        Ok(result)
+    }
+
+    async fn collect_events(&self, opts: &CollectOptions) -> Result<CollectResult> {
+        self.channel_events().await?;
+        let mut rx = self.session.subscribe();
+        let mut result = CollectResult::default();
+        let mut deadline = Instant::now() + opts.first_digit_timeout;
+
+        loop {
+            let ed = match timeout_at(deadline, next_event(&mut rx)).await {
+                Ok(Some(ed)) => ed,
+                Ok(None) => return Err(MsgError::ConnectionClosed.into()),
+                Err(_) => {
+                    result.end = CollectEnd::Timeout;
+                    return Ok(result);
+                }
+            };
+            if ed.get_header("Unique-ID".to_string()) != self.uuid {
+                continue;
+            }
+            match ed.event() {
+                Event::Dtmf => {
+                    let digit = ed.get_header("DTMF-Digit".to_string());
+                    let c = match digit.chars().next() {
+                        Some(c) => c,
+                        None => continue,
+                    };
+                    if opts.terminators.contains(c) {
+                        result.terminator = Some(c);
+                        result.end = CollectEnd::Terminator;
+                        return Ok(result);
+                    }
+                    result.digits.push(c);
+                    result.source = ed.get_header("DTMF-Source".to_string());
+                    if result.digits.len() >= opts.max_digits {
+                        result.end = CollectEnd::MaxDigits;
+                        return Ok(result);
+                    }
+                    deadline = Instant::now() + opts.inter_digit_timeout;
+                }
+                Event::ChannelHangup | Event::ChannelHangupComplete => {
+                    result.end = CollectEnd::Hangup;
+                    return Ok(result);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    async fn play_and_get_digits(
+        &self,
+        opts: &CollectOptions,
+        prompt: &str,
+    ) -> Result<CollectResult> {
+        let var = "rsesl_collected_digits";
+        let arg = format!(
+            "{} {} {} {} {} {} {} {} {} {}",
+            opts.min_digits,
+            opts.max_digits,
+            opts.tries,
+            opts.first_digit_timeout.as_millis(),
+            if opts.terminators.is_empty() {
+                "none"
+            } else {
+                app_arg(&opts.terminators)?
+            },
+            app_arg(prompt)?,
+            app_arg(
+                opts.invalid_prompt
+                    .as_deref()
+                    .unwrap_or("silence_stream://250")
+            )?,
+            var,
+            app_arg(opts.regex.as_deref().unwrap_or("\\d+"))?,
+            opts.inter_digit_timeout.as_millis(),
+        );
+
+        let mut result = CollectResult::default();
+        let ed = match self.execute_wait("play_and_get_digits", &arg).await {
+            Ok(ed) => ed,
+            Err(e) => match e.downcast_ref::<MsgError>() {
+                Some(MsgError::Hangup) => {
+                    result.end = CollectEnd::Hangup;
+                    return Ok(result);
+                }
+                _ => return Err(e),
+            },
+        };
+        result.digits = ed.get_header(format!("variable_{}", var));
+        let terminator = ed.get_header("variable_read_terminator_used".to_string());
+        result.terminator = terminator.chars().next();
+        result.end = if result.terminator.is_some() {
+            CollectEnd::Terminator
+        } else if result.digits.len() >= opts.max_digits {
+            CollectEnd::MaxDigits
+        } else {
+            // nothing or a partial entry before the timeout
+            CollectEnd::Timeout
+        };
+        Ok(result)
+    }
+}
+
+// app arguments are split on spaces and quotes group them, neither can be
+// passed through
+pub(crate) fn app_arg(v: &str) -> Result<&str> {
+    if v.is_empty() || v.contains(|c: char| c.is_whitespace() || c == '\'') {
+        return Err(MsgError::InvalidArgument(v.to_string()).into());
+    }
+    Ok(v)
+}
+
+// api arguments are split on whitespace and a line break ends the command,
+// neither can be passed through
+pub(crate) fn api_arg(v: &str) -> Result<&str> {
+    if v.is_empty() || v.contains(|c: char| c.is_whitespace() || c.is_control()) {
+        return Err(MsgError::InvalidArgument(v.to_string()).into());
+    }
+    Ok(v)
+}
+
+#[derive(Debug, Clone)]
+pub struct CollectOptions {
+    pub min_digits: usize,
+    pub max_digits: usize,
+    // any of these digits ends the collection, e.g. "#"
+    pub terminators: String,
+    pub first_digit_timeout: Duration,
+    pub inter_digit_timeout: Duration,
+    // the collected digits must match, `CollectResult::valid` tells if they do
+    pub regex: Option<String>,
+    // when set, play_and_get_digits is executed with this prompt
+    pub prompt: Option<String>,
+    pub invalid_prompt: Option<String>,
+    pub tries: u32,
+}
+
+impl Default for CollectOptions {
+    fn default() -> Self {
+        CollectOptions {
+            min_digits: 1,
+            max_digits: 1,
+            terminators: "#".to_string(),
+            first_digit_timeout: Duration::from_secs(5),
+            inter_digit_timeout: Duration::from_secs(5),
+            regex: None,
+            prompt: None,
+            invalid_prompt: None,
+            tries: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum CollectEnd {
+    MaxDigits,
+    Terminator,
+    #[default]
+    Timeout,
+    Hangup,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CollectResult {
+    pub digits: String,
+    pub terminator: Option<char>,
+    pub end: CollectEnd,
+    pub valid: bool,
+    // DTMF-Source of the last digit, e.g. RTP or INBAND_AUDIO
+    pub source: String,
+}
+
+// next event read from the session, None once the session is gone
+pub(crate) async fn next_event(
+    rx: &mut broadcast::Receiver<Arc<Mutex<Message>>>,
+) -> Option<EventData> {
+    loop {
+        match rx.recv().await {
+            Ok(msg) => {
+                if let Some(ed) = &msg.lock().await.event_data {
+                    return Some(ed.clone());
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                warn!("channel lagged {} messages", n);
+                #[cfg(feature = "metrics")]
+                crate::metrics::lagged("channel", n);
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        }
+    }
+}
@@ -1,21 +1,109 @@
-use crate::{message::Message, session::Session};
+use crate::{
+    liveness::Liveness,
+    message::{Message, MsgError},
+    record::Recorder,
+    session::{CloseReason, Session},
+};
 use anyhow::Result;
+use socket2::{SockRef, TcpKeepalive};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpStream;
 use tokio::sync::broadcast;
-use tokio::sync::Mutex;
-use tracing::{debug, error, info, trace, warn};
+use tokio::sync::{watch, Mutex};
+use tracing::{info, warn};
+
+// wait between reconnection attempts, doubled after each failure
+const RECONNECT_MIN: Duration = Duration::from_millis(500);
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+
+/// Timeouts of a `Client`, `None` waits for ever. A timeout fails with
+/// `MsgError::Timeout`.
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    pub connect_timeout: Option<Duration>,
+    pub auth_timeout: Option<Duration>,
+    // of every request, `Session::with_timeout` overrides it per call
+    pub command_timeout: Option<Duration>,
+}
+
+impl Default for ClientConfig {
+    // a blocking api like originate can take long, commands wait by default
+    fn default() -> Self {
+        ClientConfig {
+            connect_timeout: Some(Duration::from_secs(10)),
+            auth_timeout: Some(Duration::from_secs(10)),
+            command_timeout: None,
+        }
+    }
+}
+
+impl ClientConfig {
+    pub fn new() -> Self {
+        ClientConfig::default()
+    }
+
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    pub fn auth_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.auth_timeout = timeout;
+        self
+    }
+
+    pub fn command_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.command_timeout = timeout;
+        self
+    }
+}
 
 #[derive(Debug)]
 pub struct Client {
     addr: String,
     pub pwd: String,
+    config: ClientConfig,
+    recorder: Option<Recorder>,
+    keepalive: Option<Duration>,
+    liveness: Option<Liveness>,
 }
 
 impl Client {
     #[tracing::instrument]
     pub fn new(addr: String, pwd: String) -> Self {
-        Client { addr, pwd }
+        Client {
+            addr,
+            pwd,
+            config: ClientConfig::default(),
+            recorder: None,
+            keepalive: None,
+            liveness: None,
+        }
+    }
+
+    pub fn config(mut self, config: ClientConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Record the frames of the sessions made from now on.
+    pub fn record(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// TCP keepalive probes after `idle` without traffic, then every `idle`.
+    pub fn keepalive(mut self, idle: Duration) -> Self {
+        self.keepalive = Some(idle);
+        self
+    }
+
+    /// Watch the sessions made by `connect` from now on with `liveness`, from
+    /// the moment auth is accepted.
+    pub fn liveness(mut self, liveness: Liveness) -> Self {
+        self.liveness = Some(liveness);
+        self
     }
 
     pub async fn new_session(
@@ -23,10 +111,95 @@ impl Client {
         tx: broadcast::Sender<Arc<Mutex<Message>>>,
         signal: broadcast::Receiver<bool>,
     ) -> Result<Session> {
-        let s = TcpStream::connect(self.addr.clone()).await?;
+        let connect = TcpStream::connect(self.addr.clone());
+        let s = match self.config.connect_timeout {
+            Some(t) => tokio::time::timeout(t, connect)
+                .await
+                .map_err(|_| MsgError::Timeout(t))??,
+            None => connect.await?,
+        };
+        if let Some(idle) = self.keepalive {
+            let ka = TcpKeepalive::new().with_time(idle).with_interval(idle);
+            SockRef::from(&s).set_tcp_keepalive(&ka)?;
+        }
+
+        let s = match &self.recorder {
+            Some(r) => Session::recorded(s, tx, signal, r.clone()).await,
+            None => Session::new(s, tx, signal).await,
+        };
+        Ok(s.with_timeout(self.config.command_timeout))
+    }
+
+    /// `new_session` and `auth`.
+    pub async fn connect(
+        &self,
+        tx: broadcast::Sender<Arc<Mutex<Message>>>,
+        signal: broadcast::Receiver<bool>,
+    ) -> Result<Session> {
+        let session = self.new_session(tx, signal).await?;
+        let auth = session
+            .with_timeout(self.config.auth_timeout)
+            .auth(&self.pwd)
+            .await;
+        if let Err(e) = auth {
+            session.abort(CloseReason::AuthFailed);
+            return Err(e);
+        }
+        // pings before auth would be refused
+        if let Some(l) = &self.liveness {
+            l.watch(&session);
+        }
+        Ok(session)
+    }
 
-        let s = Session::new(s, tx, signal).await;
+    /// Connect, and connect again whenever the session is lost. It stops
+    /// after `Session::close`, on the shutdown signal or when auth is
+    /// refused. The watch has the current session, `None` while
+    /// reconnecting; every session sends its messages to `tx`.
+    pub fn keep_connected(
+        self,
+        tx: broadcast::Sender<Arc<Mutex<Message>>>,
+        mut signal: broadcast::Receiver<bool>,
+    ) -> watch::Receiver<Option<Session>> {
+        let (sessions, rx) = watch::channel(None);
+        tokio::spawn(async move {
+            let mut delay = RECONNECT_MIN;
+            loop {
+                match self.connect(tx.clone(), signal.resubscribe()).await {
+                    Ok(session) => {
+                        delay = RECONNECT_MIN;
+                        sessions.send_replace(Some(session.clone()));
+                        let reason = session.closed().await;
+                        sessions.send_replace(None);
+                        match reason {
+                            CloseReason::Exit | CloseReason::Shutdown | CloseReason::AuthFailed => {
+                                info!("session to {} closed: {:?}", self.addr, reason);
+                                return;
+                            }
+                            r => warn!("session to {} lost: {:?}, reconnecting", self.addr, r),
+                        }
+                    }
+                    Err(e) => {
+                        if let Some(MsgError::ErrResponse(r)) = e.downcast_ref::<MsgError>() {
+                            warn!("auth to {} refused: {}", self.addr, r);
+                            return;
+                        }
+                        warn!(
+                            "connect to {} failed: {}, retry in {:?}",
+                            self.addr, e, delay
+                        );
+                    }
+                }
 
-        Ok(s)
+                #[cfg(feature = "metrics")]
+                crate::metrics::reconnect(&self.addr);
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = signal.recv() => return,
+                }
+                delay = (delay * 2).min(RECONNECT_MAX);
+            }
+        });
+        rx
     }
 }
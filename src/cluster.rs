@@ -0,0 +1,443 @@
+use crate::client::Client;
+use crate::event::{Event, EventData, EventHandler};
+use crate::message::{FormatType, Message, MsgError};
+use crate::session::Session;
+use anyhow::Result;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{broadcast, watch, Mutex};
+use tracing::{debug, warn};
+
+/// Header added to every event of the merged stream, the name of its node.
+pub const NODE_HEADER: &str = "Cluster-Node";
+
+#[derive(Debug, Clone, Default)]
+pub struct NodeHealth {
+    pub up: bool,
+    // active sessions in the last `status`
+    pub channels: Option<u32>,
+    // FreeSWITCH-Hostname and Core-UUID of its events
+    pub hostname: Option<String>,
+    pub core_uuid: Option<String>,
+    pub checked: Option<Instant>,
+    pub error: Option<String>,
+}
+
+pub type Nodes = HashMap<String, NodeHealth>;
+
+// the node of a channel
+#[derive(Debug, Clone)]
+struct Owner {
+    node: String,
+    // set when found by `uuid_exists`, no CHANNEL_DESTROY may come to forget it
+    expires: Option<Instant>,
+}
+
+// channel uuid -> node
+type Owners = Arc<Mutex<HashMap<String, Owner>>>;
+
+/// Which node an api call goes to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Route {
+    Node(String),
+    RoundRobin,
+    // fewest active sessions in the last health check
+    LeastChannels,
+    // the node the channel is on
+    Uuid(String),
+}
+
+pub struct ClusterBuilder {
+    nodes: Vec<(String, Client)>,
+    events: Option<(FormatType, Vec<String>)>,
+    interval: Duration,
+    owner_ttl: Duration,
+}
+
+impl ClusterBuilder {
+    pub fn node(mut self, name: &str, client: Client) -> Self {
+        self.nodes.push((name.to_string(), client));
+        self
+    }
+
+    /// Events to subscribe to on every node. CHANNEL_CREATE and
+    /// CHANNEL_DESTROY let `Route::Uuid` find a channel without asking.
+    pub fn events(mut self, format: FormatType, events: &[&str]) -> Self {
+        self.events = Some((format, events.iter().map(|e| e.to_string()).collect()));
+        self
+    }
+
+    /// How often `status` is asked from every node, 10s by default.
+    pub fn health_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// How long the node of a channel found by `uuid_exists` is trusted
+    /// before it is asked again, 60s by default.
+    pub fn owner_ttl(mut self, ttl: Duration) -> Self {
+        self.owner_ttl = ttl;
+        self
+    }
+
+    /// Connect to every node and keep them connected until the cluster is
+    /// dropped.
+    pub fn start(self) -> Cluster {
+        let (tx, _) = broadcast::channel(1024);
+        let (shutdown, signal) = broadcast::channel(1);
+        let (health, _) = watch::channel(
+            self.nodes
+                .iter()
+                .map(|(name, _)| (name.clone(), NodeHealth::default()))
+                .collect::<Nodes>(),
+        );
+        let health = Arc::new(health);
+        let owners: Owners = Arc::new(Mutex::new(HashMap::new()));
+
+        let mut nodes = vec![];
+        for (name, client) in self.nodes {
+            let (node_tx, node_rx) = broadcast::channel(1024);
+            let sessions = client.keep_connected(node_tx, signal.resubscribe());
+            tokio::spawn(forward(
+                name.clone(),
+                node_rx,
+                tx.clone(),
+                health.clone(),
+                owners.clone(),
+            ));
+            tokio::spawn(check(
+                name.clone(),
+                sessions.clone(),
+                self.events.clone(),
+                self.interval,
+                health.clone(),
+            ));
+            nodes.push((name, sessions));
+        }
+
+        Cluster {
+            nodes: Arc::new(nodes),
+            tx,
+            health,
+            owners,
+            owner_ttl: self.owner_ttl,
+            next: Arc::new(AtomicUsize::new(0)),
+            _shutdown: Arc::new(shutdown),
+        }
+    }
+}
+
+/// Several FreeSWITCH nodes, each with its own `Client`. Their events are
+/// merged into one stream and api calls are routed by `Route`, failing
+/// over to the next healthy node when a node doesn't answer.
+#[derive(Clone)]
+pub struct Cluster {
+    nodes: Arc<Vec<(String, watch::Receiver<Option<Session>>)>>,
+    tx: broadcast::Sender<Arc<Mutex<Message>>>,
+    health: Arc<watch::Sender<Nodes>>,
+    owners: Owners,
+    owner_ttl: Duration,
+    next: Arc<AtomicUsize>,
+    // the sessions shut down when the last clone is dropped
+    _shutdown: Arc<broadcast::Sender<bool>>,
+}
+
+impl Cluster {
+    pub fn builder() -> ClusterBuilder {
+        ClusterBuilder {
+            nodes: vec![],
+            events: None,
+            interval: Duration::from_secs(10),
+            owner_ttl: Duration::from_secs(60),
+        }
+    }
+
+    /// The events of every node, with `NODE_HEADER` set.
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Mutex<Message>>> {
+        self.tx.subscribe()
+    }
+
+    pub fn health(&self) -> watch::Receiver<Nodes> {
+        self.health.subscribe()
+    }
+
+    pub fn nodes(&self) -> Vec<String> {
+        self.nodes.iter().map(|(n, _)| n.clone()).collect()
+    }
+
+    /// The session of a healthy node picked by `route`.
+    pub async fn session(&self, route: &Route) -> Result<(String, Session)> {
+        self.candidates(route)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("no healthy node for {:?}", route))
+    }
+
+    /// Run an api command on the node picked by `route`. A node that is
+    /// down, times out or hangs up is marked down and the next one is
+    /// tried, except for `Route::Node` and `Route::Uuid`.
+    pub async fn api(&self, route: &Route, cmd: &str) -> Result<String> {
+        let mut last = None;
+        for (name, session) in self.candidates(route).await? {
+            match session.api(cmd).await {
+                Err(e)
+                    if matches!(
+                        e.downcast_ref::<MsgError>(),
+                        Some(MsgError::ConnectionClosed | MsgError::Timeout(_))
+                    ) =>
+                {
+                    warn!("node {} failed: {}, trying the next one", name, e);
+                    self.set_down(&name, &e.to_string());
+                    last = Some(e);
+                }
+                r => return r,
+            }
+        }
+        Err(last.unwrap_or_else(|| anyhow::anyhow!("no healthy node for {:?}", route)))
+    }
+
+    // healthy nodes for `route`, the first is the one to use
+    async fn candidates(&self, route: &Route) -> Result<Vec<(String, Session)>> {
+        let up = self.up();
+        let mut nodes = match route {
+            Route::Node(name) => {
+                if !self.nodes.iter().any(|(n, _)| n == name) {
+                    return Err(anyhow::anyhow!("unknown node {}", name));
+                }
+                up.into_iter().filter(|(n, _)| n == name).collect()
+            }
+            Route::RoundRobin => {
+                let mut up = up;
+                if !up.is_empty() {
+                    let n = self.next.fetch_add(1, Ordering::Relaxed) % up.len();
+                    up.rotate_left(n);
+                }
+                up
+            }
+            Route::LeastChannels => {
+                let health = self.health.borrow();
+                let mut up = up;
+                up.sort_by_key(|(n, _)| health.get(n).and_then(|h| h.channels).unwrap_or(u32::MAX));
+                up
+            }
+            Route::Uuid(uuid) => match self.owner(uuid, &up).await {
+                Some(owner) => up.into_iter().filter(|(n, _)| *n == owner).collect(),
+                None => vec![],
+            },
+        };
+        // failover only where any node will do
+        if matches!(route, Route::Node(_) | Route::Uuid(_)) {
+            nodes.truncate(1);
+        }
+        Ok(nodes)
+    }
+
+    // the node of a channel, from the events or else `uuid_exists` on each node
+    async fn owner(&self, uuid: &str, up: &[(String, Session)]) -> Option<String> {
+        {
+            let mut owners = self.owners.lock().await;
+            match owners.get(uuid) {
+                Some(o) if o.expires.is_none_or(|e| e > Instant::now()) => {
+                    return Some(o.node.clone());
+                }
+                Some(_) => {
+                    owners.remove(uuid);
+                }
+                None => {}
+            }
+        }
+        for (name, session) in up {
+            match session.api(&format!("uuid_exists {}", uuid)).await {
+                Ok(body) if body.trim() == "true" => {
+                    let owner = Owner {
+                        node: name.clone(),
+                        expires: Some(Instant::now() + self.owner_ttl),
+                    };
+                    self.owners.lock().await.insert(uuid.to_string(), owner);
+                    return Some(name.clone());
+                }
+                Ok(_) => {}
+                Err(e) => debug!("uuid_exists on {} failed: {}", name, e),
+            }
+        }
+        None
+    }
+
+    // connected nodes whose last check was fine, in the order they were added
+    fn up(&self) -> Vec<(String, Session)> {
+        let health = self.health.borrow();
+        self.nodes
+            .iter()
+            .filter(|(n, _)| health.get(n).map(|h| h.up).unwrap_or(false))
+            .filter_map(|(n, s)| s.borrow().clone().map(|s| (n.clone(), s)))
+            .collect()
+    }
+
+    fn set_down(&self, name: &str, error: &str) {
+        self.health.send_modify(|nodes| {
+            if let Some(h) = nodes.get_mut(name) {
+                h.up = false;
+                h.error = Some(error.to_string());
+            }
+        });
+    }
+}
+
+// tag the events of a node and pass them on to the merged stream
+async fn forward(
+    name: String,
+    mut rx: broadcast::Receiver<Arc<Mutex<Message>>>,
+    tx: broadcast::Sender<Arc<Mutex<Message>>>,
+    health: Arc<watch::Sender<Nodes>>,
+    owners: Owners,
+) {
+    loop {
+        let msg = match rx.recv().await {
+            Ok(m) => m,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                warn!("cluster node {} lagged {} messages", name, n);
+                #[cfg(feature = "metrics")]
+                crate::metrics::lagged("cluster", n);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => {
+                debug!("node {} stopped, stop forwarding", name);
+                return;
+            }
+        };
+        // the message is shared with the other readers of the node, tag a copy
+        let mut m = {
+            let m = msg.lock().await;
+            if m.is_reply() || m.event_data.is_none() {
+                continue;
+            }
+            m.clone()
+        };
+        if let Some(ed) = &mut m.event_data {
+            ed.insert(NODE_HEADER.to_string(), Value::String(name.clone()));
+            learn(&name, ed, &health, &owners).await;
+        }
+        let _ = tx.send(Arc::new(Mutex::new(m)));
+    }
+}
+
+// the identity of the node and the channels on it
+async fn learn(
+    name: &str,
+    ed: &EventData,
+    health: &watch::Sender<Nodes>,
+    owners: &Mutex<HashMap<String, Owner>>,
+) {
+    let hostname = ed.get_header("FreeSWITCH-Hostname".to_string());
+    let core_uuid = ed.get_header("Core-UUID".to_string());
+    health.send_if_modified(|nodes| {
+        let h = match nodes.get_mut(name) {
+            Some(h) => h,
+            None => return false,
+        };
+        let mut changed = false;
+        for (v, field) in [(hostname, &mut h.hostname), (core_uuid, &mut h.core_uuid)] {
+            if !v.is_empty() && field.as_deref() != Some(v.as_str()) {
+                *field = Some(v);
+                changed = true;
+            }
+        }
+        changed
+    });
+
+    let uuid = ed.get_header("Unique-ID".to_string());
+    if uuid.is_empty() {
+        return;
+    }
+    match ed.event() {
+        Event::ChannelCreate => {
+            let owner = Owner {
+                node: name.to_string(),
+                expires: None,
+            };
+            owners.lock().await.insert(uuid, owner);
+        }
+        Event::ChannelDestroy => {
+            owners.lock().await.remove(&uuid);
+        }
+        _ => {}
+    }
+}
+
+// subscribe each new session of the node and ask `status` every `interval`
+async fn check(
+    name: String,
+    mut sessions: watch::Receiver<Option<Session>>,
+    events: Option<(FormatType, Vec<String>)>,
+    interval: Duration,
+    health: Arc<watch::Sender<Nodes>>,
+) {
+    let update = |up: bool, channels: Option<u32>, error: Option<String>| {
+        health.send_modify(|nodes| {
+            let h = nodes.entry(name.clone()).or_default();
+            h.up = up;
+            h.checked = Some(Instant::now());
+            if channels.is_some() {
+                h.channels = channels;
+            }
+            h.error = error;
+        });
+    };
+
+    loop {
+        let session = sessions.borrow_and_update().clone();
+        let session = match session {
+            Some(s) => s.with_timeout(Some(interval)),
+            None => {
+                update(false, None, Some("not connected".to_string()));
+                if sessions.changed().await.is_err() {
+                    return;
+                }
+                continue;
+            }
+        };
+
+        if let Some((format, names)) = &events {
+            let names: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+            if let Err(e) = session.event(format.clone(), &names).await {
+                warn!("subscribe on node {} failed: {}", name, e);
+            }
+        }
+        loop {
+            match session.api("status").await {
+                Ok(status) => update(true, parse_channels(&status), None),
+                Err(e) => {
+                    warn!("health check of node {} failed: {}", name, e);
+                    update(false, None, Some(e.to_string()));
+                }
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                r = sessions.changed() => {
+                    if r.is_err() {
+                        update(false, None, Some("stopped".to_string()));
+                        return;
+                    }
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// "3 session(s) - peak 5, last 5min 4" of `status`
+fn parse_channels(status: &str) -> Option<u32> {
+    status
+        .lines()
+        .find(|l| l.contains("session(s) - peak"))
+        .and_then(|l| l.split_whitespace().next())
+        .and_then(|n| n.parse().ok())
+}
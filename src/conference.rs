@@ -0,0 +1,316 @@
+use crate::event::{Event, EventData, EventHandler};
+use crate::message::{Message, MsgError};
+use crate::session::Session;
+use anyhow::Result;
+use serde::Deserialize;
+use std::{collections::HashMap, fmt::Display, sync::Arc};
+use tokio::{
+    sync::{broadcast, Mutex},
+    task::JoinHandle,
+};
+use tracing::{debug, warn};
+
+pub const MAINTENANCE: &str = "conference::maintenance";
+
+/// Which members a command applies to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Id(u32),
+    All,
+    Last,
+    NonModerator,
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Target::Id(id) => write!(f, "{}", id),
+            Target::All => write!(f, "all"),
+            Target::Last => write!(f, "last"),
+            Target::NonModerator => write!(f, "non_moderator"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MemberFlags {
+    pub can_hear: bool,
+    pub can_speak: bool,
+    pub talking: bool,
+    pub has_floor: bool,
+    pub is_moderator: bool,
+    pub hold: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Member {
+    pub id: u32,
+    pub uuid: String,
+    pub caller_id_name: String,
+    pub caller_id_number: String,
+    pub energy: i64,
+    pub volume_in: i64,
+    pub volume_out: i64,
+    pub flags: MemberFlags,
+}
+
+impl From<&EventData> for Member {
+    fn from(ed: &EventData) -> Self {
+        let flag = |k: &str| ed.get_header(k.to_string()) == "true";
+        let number = |k: &str| -> i64 { ed.get_header(k.to_string()).parse().unwrap_or_default() };
+        Member {
+            id: number("Member-ID") as u32,
+            uuid: ed.get_header("Unique-ID".to_string()),
+            caller_id_name: ed.get_header("Caller-Caller-ID-Name".to_string()),
+            caller_id_number: ed.get_header("Caller-Caller-ID-Number".to_string()),
+            energy: number("Energy-Level"),
+            volume_in: number("Volume-In"),
+            volume_out: number("Volume-Out"),
+            flags: MemberFlags {
+                can_hear: flag("Hear"),
+                can_speak: flag("Speak"),
+                talking: flag("Talking"),
+                has_floor: flag("Floor"),
+                is_moderator: ed.get_header("Member-Type".to_string()) == "moderator",
+                hold: flag("Hold"),
+            },
+        }
+    }
+}
+
+// one entry of `conference <name> json_list`
+#[derive(Debug, Deserialize)]
+struct JsonConference {
+    #[serde(default)]
+    members: Vec<Member>,
+}
+
+/// Control handle for one conference room of mod_conference.
+#[derive(Debug, Clone)]
+pub struct Conference {
+    session: Session,
+    name: String,
+    members: Arc<Mutex<HashMap<u32, Member>>>,
+}
+
+impl Conference {
+    pub fn new(session: Session, name: &str) -> Self {
+        Conference {
+            session,
+            name: name.to_string(),
+            members: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// `conference <name> list`, as printed by FreeSWITCH.
+    pub async fn list(&self) -> Result<String> {
+        self.cmd("list").await
+    }
+
+    /// Member state from `conference <name> json_list`.
+    pub async fn members(&self) -> Result<Vec<Member>> {
+        let body = self.cmd("json_list").await?;
+        let confs: Vec<JsonConference> = serde_json::from_str(&body)?;
+        Ok(confs.into_iter().flat_map(|c| c.members).collect())
+    }
+
+    pub async fn mute(&self, target: Target) -> Result<()> {
+        self.exec(&format!("mute {}", target)).await
+    }
+
+    pub async fn unmute(&self, target: Target) -> Result<()> {
+        self.exec(&format!("unmute {}", target)).await
+    }
+
+    pub async fn deaf(&self, target: Target) -> Result<()> {
+        self.exec(&format!("deaf {}", target)).await
+    }
+
+    pub async fn undeaf(&self, target: Target) -> Result<()> {
+        self.exec(&format!("undeaf {}", target)).await
+    }
+
+    pub async fn kick(&self, target: Target) -> Result<()> {
+        self.exec(&format!("kick {}", target)).await
+    }
+
+    pub async fn hup(&self, target: Target) -> Result<()> {
+        self.exec(&format!("hup {}", target)).await
+    }
+
+    pub async fn lock(&self) -> Result<()> {
+        self.exec("lock").await
+    }
+
+    pub async fn unlock(&self) -> Result<()> {
+        self.exec("unlock").await
+    }
+
+    pub async fn record(&self, path: &str) -> Result<()> {
+        self.exec(&format!("record {}", path)).await
+    }
+
+    /// Play a file to the whole conference, or to one member.
+    pub async fn play(&self, file: &str, member: Option<u32>) -> Result<()> {
+        match member {
+            Some(id) => self.exec(&format!("play {} {}", file, id)).await,
+            None => self.exec(&format!("play {}", file)).await,
+        }
+    }
+
+    pub async fn volume_in(&self, target: Target, level: i32) -> Result<()> {
+        self.exec(&format!("volume_in {} {}", target, level)).await
+    }
+
+    pub async fn volume_out(&self, target: Target, level: i32) -> Result<()> {
+        self.exec(&format!("volume_out {} {}", target, level)).await
+    }
+
+    pub async fn energy(&self, target: Target, level: i32) -> Result<()> {
+        self.exec(&format!("energy {} {}", target, level)).await
+    }
+
+    /// Keep a live member list from `conference::maintenance` events until
+    /// the handle is aborted. The events come in the format the session
+    /// already uses, JSON if none.
+    pub async fn track(&self) -> Result<JoinHandle<()>> {
+        let rx = self.session.subscribe();
+        self.session.add_events(&["CUSTOM", MAINTENANCE]).await?;
+
+        let members = match self.members().await {
+            Ok(m) => m,
+            // the room does not exist yet, it will be filled by add-member
+            Err(e) if is_not_found(&e) => vec![],
+            Err(e) => return Err(e),
+        };
+        {
+            let mut live = self.members.lock().await;
+            live.clear();
+            live.extend(members.into_iter().map(|m| (m.id, m)));
+        }
+
+        Ok(tokio::spawn(run(
+            rx,
+            self.name.clone(),
+            self.members.clone(),
+        )))
+    }
+
+    /// Members as seen by `track`.
+    pub async fn live_members(&self) -> Vec<Member> {
+        let mut members: Vec<Member> = self.members.lock().await.values().cloned().collect();
+        members.sort_by_key(|m| m.id);
+        members
+    }
+
+    async fn cmd(&self, cmd: &str) -> Result<String> {
+        let body = self
+            .session
+            .api(&format!("conference {} {}", self.name, cmd))
+            .await?;
+        let b = body.trim();
+        // mod_conference reports some failures without -ERR
+        if let Some(id) = b.strip_prefix("Non-Existant ID ") {
+            return Err(MsgError::MemberNotFound(id.to_string()).into());
+        }
+        match not_found(b) {
+            Some(("Conference", _)) => Err(MsgError::ConferenceNotFound(self.name.clone()).into()),
+            Some((_, id)) => Err(MsgError::MemberNotFound(id.to_string()).into()),
+            None => Ok(b.to_string()),
+        }
+    }
+
+    // a command answered with a plain "OK ..."
+    async fn exec(&self, cmd: &str) -> Result<()> {
+        self.cmd(cmd).await?;
+        Ok(())
+    }
+}
+
+// "Conference <name> not found" or "Member <id> not found", on a line of its
+// own, as the kind and the name or id
+fn not_found(b: &str) -> Option<(&str, &str)> {
+    if b.contains('\n') {
+        return None;
+    }
+    let (kind, rest) = b.split_once(' ')?;
+    let name = rest.strip_suffix(" not found")?;
+    match kind {
+        "Conference" | "Member" => Some((kind, name)),
+        _ => None,
+    }
+}
+
+fn is_not_found(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<MsgError>(),
+        Some(MsgError::ConferenceNotFound(_))
+    )
+}
+
+async fn run(
+    mut rx: broadcast::Receiver<Arc<Mutex<Message>>>,
+    name: String,
+    members: Arc<Mutex<HashMap<u32, Member>>>,
+) {
+    loop {
+        let msg = match rx.recv().await {
+            Ok(m) => m,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                warn!("conference {} lagged {} messages", name, n);
+                #[cfg(feature = "metrics")]
+                crate::metrics::lagged("conference", n);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => {
+                debug!("session closed, stop tracking conference {}", name);
+                return;
+            }
+        };
+        let ed = match &msg.lock().await.event_data {
+            Some(ed) => ed.clone(),
+            None => continue,
+        };
+        if ed.event() != Event::Custom
+            || ed.subclass() != MAINTENANCE
+            || ed.get_header("Conference-Name".to_string()) != name
+        {
+            continue;
+        }
+        apply(&mut *members.lock().await, &ed);
+    }
+}
+
+fn apply(members: &mut HashMap<u32, Member>, ed: &EventData) {
+    let action = ed.get_header("Action".to_string());
+    match action.as_str() {
+        "conference-destroy" => members.clear(),
+        "del-member" | "kick-member" => {
+            let m = Member::from(ed);
+            members.remove(&m.id);
+        }
+        "floor-change" => {
+            let new_id: u32 = ed
+                .get_header("New-ID".to_string())
+                .parse()
+                .unwrap_or_default();
+            for m in members.values_mut() {
+                m.flags.has_floor = m.id == new_id;
+            }
+        }
+        _ => {
+            // every member event carries the full member state
+            if ed.get_header("Member-ID".to_string()).is_empty() {
+                return;
+            }
+            let m = Member::from(ed);
+            members.insert(m.id, m);
+        }
+    }
+}
@@ -6,6 +6,15 @@ pub type EventData = Map<String, Value>;
 pub trait EventHandler {
     fn get_header(&self, k: String) -> String;
     fn set_header(&mut self, k: String, v: Value);
+
+    fn event(&self) -> Event {
+        Event::from(self.get_header("Event-Name".to_string()))
+    }
+
+    // subclass of CUSTOM events, e.g. sofia::register
+    fn subclass(&self) -> String {
+        self.get_header("Event-Subclass".to_string())
+    }
 }
 
 impl EventHandler for EventData {
@@ -28,7 +37,7 @@ impl EventHandler for EventData {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Event {
     AddSchedule,
     Api,
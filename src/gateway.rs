@@ -0,0 +1,208 @@
+use crate::event::{Event, EventData, EventHandler};
+use crate::message::Message;
+use crate::session::Session;
+use crate::sofia::{GatewayState, PingStatus, Sofia};
+use anyhow::Result;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{broadcast, watch, Mutex},
+    task::JoinHandle,
+};
+use tracing::{debug, error, warn};
+
+pub const GATEWAY_STATE: &str = "sofia::gateway_state";
+pub const GATEWAY_ADD: &str = "sofia::gateway_add";
+pub const GATEWAY_DELETE: &str = "sofia::gateway_delete";
+
+#[derive(Debug, Clone)]
+pub struct GatewayHealth {
+    pub name: String,
+    pub profile: String,
+    pub state: GatewayState,
+    pub ping_status: PingStatus,
+    // set while the gateway is down, cleared when it comes back
+    pub down_since: Option<Instant>,
+    // whether the alert hook already fired for the current outage
+    pub alerted: bool,
+}
+
+impl GatewayHealth {
+    fn new(name: String, profile: String) -> Self {
+        GatewayHealth {
+            name,
+            profile,
+            state: GatewayState::Unknown(String::new()),
+            ping_status: PingStatus::Unknown(String::new()),
+            down_since: None,
+            alerted: false,
+        }
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.state.is_up() && self.ping_status != PingStatus::Down
+    }
+
+    fn update(&mut self, state: GatewayState, ping_status: PingStatus) {
+        self.state = state;
+        self.ping_status = ping_status;
+        if self.is_up() {
+            self.down_since = None;
+            self.alerted = false;
+        } else if self.down_since.is_none() {
+            self.down_since = Some(Instant::now());
+        }
+    }
+}
+
+pub type Gateways = HashMap<String, GatewayHealth>;
+
+pub type AlertHook = Arc<dyn Fn(&GatewayHealth) + Send + Sync>;
+
+/// Tracks gateway health from `sofia::gateway_*` events.
+pub struct GatewayMonitor {
+    session: Session,
+    threshold: Duration,
+    alert: Option<AlertHook>,
+}
+
+impl GatewayMonitor {
+    pub fn new(session: Session) -> Self {
+        GatewayMonitor {
+            session,
+            threshold: Duration::from_secs(60),
+            alert: None,
+        }
+    }
+
+    /// How long a gateway may stay down before the alert hook fires.
+    pub fn threshold(mut self, threshold: Duration) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn on_alert<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&GatewayHealth) + Send + Sync + 'static,
+    {
+        self.alert = Some(Arc::new(f));
+        self
+    }
+
+    /// Subscribe to gateway events, seed the state from `sofia xmlstatus gateway`
+    /// and keep it up to date in the background until the handle is aborted.
+    /// The events come in the format the session already uses, JSON if none.
+    pub async fn start(self) -> Result<(watch::Receiver<Gateways>, JoinHandle<()>)> {
+        // subscribe before seeding so no change is lost in between
+        let rx = self.session.subscribe();
+        self.session
+            .add_events(&["CUSTOM", GATEWAY_STATE, GATEWAY_ADD, GATEWAY_DELETE])
+            .await?;
+
+        let mut gateways = Gateways::new();
+        for gw in Sofia::new(self.session.clone()).gateways().await? {
+            let mut health = GatewayHealth::new(gw.name.clone(), gw.profile.clone());
+            health.update(gw.state, gw.status);
+            gateways.insert(gw.name, health);
+        }
+
+        let (tx, watch_rx) = watch::channel(gateways);
+        let task = tokio::spawn(run(rx, tx, self.threshold, self.alert));
+
+        Ok((watch_rx, task))
+    }
+}
+
+async fn run(
+    mut rx: broadcast::Receiver<Arc<Mutex<Message>>>,
+    tx: watch::Sender<Gateways>,
+    threshold: Duration,
+    alert: Option<AlertHook>,
+) {
+    let mut tick = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            msg = rx.recv() => {
+                let msg = match msg {
+                    Ok(m) => m,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("gateway monitor lagged {} messages", n);
+                        #[cfg(feature = "metrics")]
+                        crate::metrics::lagged("gateway", n);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        debug!("session closed, stop gateway monitor");
+                        return;
+                    }
+                };
+                let ed = match &msg.lock().await.event_data {
+                    Some(ed) => ed.clone(),
+                    None => continue,
+                };
+                if ed.event() == Event::Custom {
+                    tx.send_if_modified(|gws| apply(gws, &ed));
+                }
+            }
+            _ = tick.tick() => {
+                if tx.is_closed() {
+                    return;
+                }
+                tx.send_if_modified(|gws| check_down(gws, threshold, &alert));
+            }
+        }
+    }
+}
+
+// apply a sofia::gateway_* event, returns true if anything changed
+fn apply(gws: &mut Gateways, ed: &EventData) -> bool {
+    let name = ed.get_header("Gateway".to_string());
+    if name.is_empty() {
+        return false;
+    }
+    let profile = ed.get_header("profile-name".to_string());
+
+    match ed.subclass().as_str() {
+        GATEWAY_ADD => {
+            gws.entry(name.clone())
+                .or_insert_with(|| GatewayHealth::new(name, profile));
+            true
+        }
+        GATEWAY_DELETE => gws.remove(&name).is_some(),
+        GATEWAY_STATE => {
+            let gw = gws
+                .entry(name.clone())
+                .or_insert_with(|| GatewayHealth::new(name, profile));
+            let state = GatewayState::from(ed.get_header("State".to_string()).as_str());
+            let ping = match ed.get_header("Ping-Status".to_string()).as_str() {
+                "" => gw.ping_status.clone(),
+                s => PingStatus::from(s),
+            };
+            gw.update(state, ping);
+            true
+        }
+        _ => false,
+    }
+}
+
+fn check_down(gws: &mut Gateways, threshold: Duration, alert: &Option<AlertHook>) -> bool {
+    let mut changed = false;
+    for gw in gws.values_mut() {
+        let overdue = match gw.down_since {
+            Some(t) => t.elapsed() >= threshold,
+            None => false,
+        };
+        if overdue && !gw.alerted {
+            error!("gateway {} down for more than {:?}", gw.name, threshold);
+            gw.alerted = true;
+            changed = true;
+            if let Some(f) = alert {
+                f(gw);
+            }
+        }
+    }
+    changed
+}
@@ -0,0 +1,422 @@
+use crate::event::{EventData, EventHandler};
+use crate::xml_curl::Element;
+use anyhow::Result;
+use axum::{
+    async_trait,
+    body::{Body, Bytes},
+    extract::{FromRequest, Multipart, State},
+    http::{header, Request, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Form, Router,
+};
+use futures_util::future::BoxFuture;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    future::Future,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+use tracing::{debug, error};
+
+/// A file uploaded by `<record>`.
+#[derive(Debug, Clone)]
+pub struct Upload {
+    // the form field, the `name` of the record element
+    pub name: String,
+    pub file_name: Option<String>,
+    pub content_type: Option<String>,
+    pub data: Bytes,
+}
+
+/// One POST of mod_httapi.
+#[derive(Debug, Clone, Default)]
+pub struct HttapiRequest {
+    pub session_id: String,
+    // the call is over, the answer is ignored
+    pub exiting: bool,
+    // every posted field, channel data included
+    pub params: EventData,
+    pub uploads: Vec<Upload>,
+}
+
+impl HttapiRequest {
+    pub fn param(&self, k: &str) -> String {
+        self.params.get_header(k.to_string())
+    }
+
+    /// Channel variable `name`, posted as `variable_<name>`.
+    pub fn variable(&self, name: &str) -> String {
+        self.param(&format!("variable_{}", name))
+    }
+
+    pub fn upload(&self, name: &str) -> Option<&Upload> {
+        self.uploads.iter().find(|u| u.name == name)
+    }
+
+    fn insert(&mut self, k: &str, v: &str) {
+        match k {
+            "session_id" => self.session_id = v.to_string(),
+            "exiting" => self.exiting = v == "true",
+            _ => {}
+        }
+        self.params
+            .insert(k.to_string(), Value::String(v.to_string()));
+    }
+}
+
+impl From<HashMap<String, String>> for HttapiRequest {
+    fn from(form: HashMap<String, String>) -> Self {
+        let mut req = HttapiRequest::default();
+        for (k, v) in &form {
+            req.insert(k, v);
+        }
+        req
+    }
+}
+
+// urlencoded, or multipart when a recording is uploaded
+#[async_trait]
+impl<S> FromRequest<S, Body> for HttapiRequest
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request<Body>, state: &S) -> Result<Self, Self::Rejection> {
+        let multipart = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.starts_with("multipart/form-data"))
+            .unwrap_or(false);
+        if !multipart {
+            let Form(form) = Form::<HashMap<String, String>>::from_request(req, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            return Ok(HttapiRequest::from(form));
+        }
+
+        let mut mp = Multipart::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let mut r = HttapiRequest::default();
+        let bad = |e: axum::extract::multipart::MultipartError| {
+            (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+        };
+        while let Some(field) = mp.next_field().await.map_err(bad)? {
+            let name = field.name().unwrap_or_default().to_string();
+            match field.file_name().map(|s| s.to_string()) {
+                Some(file_name) => {
+                    let content_type = field.content_type().map(|s| s.to_string());
+                    r.uploads.push(Upload {
+                        name,
+                        file_name: Some(file_name),
+                        content_type,
+                        data: field.bytes().await.map_err(bad)?,
+                    });
+                }
+                None => {
+                    let v = field.text().await.map_err(bad)?;
+                    r.insert(&name, &v);
+                }
+            }
+        }
+        Ok(r)
+    }
+}
+
+/// One element of `<work>`. The constructors make the element, the setters
+/// add the optional attributes.
+#[derive(Debug, Clone)]
+pub struct Work {
+    element: Element,
+}
+
+impl Work {
+    fn new(name: &str) -> Self {
+        Work {
+            element: Element::new(name),
+        }
+    }
+
+    pub fn playback(file: &str) -> Self {
+        Work::new("playback").attr("file", file)
+    }
+
+    pub fn speak(text: &str) -> Self {
+        let mut w = Work::new("speak");
+        w.element = w.element.text(text);
+        w
+    }
+
+    pub fn record(file: &str) -> Self {
+        Work::new("record").attr("file", file)
+    }
+
+    pub fn get_variable(name: &str) -> Self {
+        Work::new("getVariable").attr("name", name)
+    }
+
+    pub fn execute(application: &str, data: &str) -> Self {
+        let mut w = Work::new("execute").attr("application", application);
+        if !data.is_empty() {
+            w.element = w.element.text(data);
+        }
+        w
+    }
+
+    pub fn hangup(cause: Option<&str>) -> Self {
+        let w = Work::new("hangup");
+        match cause {
+            Some(c) => w.attr("cause", c),
+            None => w,
+        }
+    }
+
+    /// `<continue>`, leave httapi and go on with the dialplan.
+    pub fn continue_dialplan() -> Self {
+        Work::new("continue")
+    }
+
+    pub fn attr(mut self, k: &str, v: &str) -> Self {
+        self.element = self.element.attr(k, v);
+        self
+    }
+
+    /// The variable the digits, recording or value are stored in.
+    pub fn name(self, name: &str) -> Self {
+        self.attr("name", name)
+    }
+
+    /// URL the next request goes to.
+    pub fn action(self, url: &str) -> Self {
+        self.attr("action", url)
+    }
+
+    pub fn error_file(self, file: &str) -> Self {
+        self.attr("error-file", file)
+    }
+
+    pub fn loops(self, n: u32) -> Self {
+        self.attr("loops", &n.to_string())
+    }
+
+    pub fn input_timeout(self, d: Duration) -> Self {
+        self.attr("input-timeout", &d.as_millis().to_string())
+    }
+
+    pub fn digit_timeout(self, d: Duration) -> Self {
+        self.attr("digit-timeout", &d.as_millis().to_string())
+    }
+
+    pub fn terminators(self, t: &str) -> Self {
+        self.attr("terminators", t)
+    }
+
+    pub fn engine(self, engine: &str) -> Self {
+        self.attr("engine", engine)
+    }
+
+    pub fn voice(self, voice: &str) -> Self {
+        self.attr("voice", voice)
+    }
+
+    /// Maximum recording length.
+    pub fn limit(self, d: Duration) -> Self {
+        self.attr("limit", &d.as_secs().to_string())
+    }
+
+    pub fn beep_file(self, file: &str) -> Self {
+        self.attr("beep-file", file)
+    }
+
+    /// Keep a `getVariable` for the following requests.
+    pub fn permanent(self) -> Self {
+        self.attr("permanent", "true")
+    }
+
+    /// `<bind>` of a playback or speak, e.g. `~\d+#` with strip `#`.
+    pub fn bind(mut self, pattern: &str, strip: Option<&str>) -> Self {
+        let mut b = Element::new("bind").text(pattern);
+        if let Some(s) = strip {
+            b = b.attr("strip", s);
+        }
+        self.element = self.element.child(b);
+        self
+    }
+}
+
+impl From<Work> for Element {
+    fn from(w: Work) -> Self {
+        w.element
+    }
+}
+
+/// The `<document type="xml/freeswitch-httapi">` answer.
+#[derive(Debug, Clone, Default)]
+pub struct Document {
+    pub params: Vec<(String, String)>,
+    pub variables: Vec<(String, String)>,
+    pub work: Vec<Work>,
+}
+
+impl Document {
+    pub fn new() -> Self {
+        Document::default()
+    }
+
+    pub fn param(mut self, k: &str, v: &str) -> Self {
+        self.params.push((k.to_string(), v.to_string()));
+        self
+    }
+
+    /// Channel variable to set.
+    pub fn variable(mut self, k: &str, v: &str) -> Self {
+        self.variables.push((k.to_string(), v.to_string()));
+        self
+    }
+
+    pub fn work(mut self, w: Work) -> Self {
+        self.work.push(w);
+        self
+    }
+}
+
+impl Display for Document {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut doc = Element::new("document").attr("type", "xml/freeswitch-httapi");
+        // httapi lists are <name>value</name>
+        for (list, items) in [("params", &self.params), ("variables", &self.variables)] {
+            if items.is_empty() {
+                continue;
+            }
+            let mut e = Element::new(list);
+            for (k, v) in items {
+                e = e.child(Element::new(k).text(v));
+            }
+            doc = doc.child(e);
+        }
+        let mut work = Element::new("work");
+        for w in &self.work {
+            work = work.child(w.clone());
+        }
+        write!(f, "{}", doc.child(work))
+    }
+}
+
+impl IntoResponse for Document {
+    fn into_response(self) -> Response {
+        ([(header::CONTENT_TYPE, "text/xml")], self.to_string()).into_response()
+    }
+}
+
+// state of a call, with the time of its last request
+type Sessions<S> = Arc<Mutex<HashMap<String, (Arc<Mutex<S>>, Instant)>>>;
+
+type Handler<S> =
+    Arc<dyn Fn(HttapiRequest, Arc<Mutex<S>>) -> BoxFuture<'static, Result<Document>> + Send + Sync>;
+
+/// Answers mod_httapi requests with `handler`, which gets the state of the
+/// call kept between requests by `session_id`. The state is dropped when
+/// the call exits, or once no request came for it within `ttl`, in case the
+/// exiting request never arrives.
+pub struct Httapi<S> {
+    handler: Handler<S>,
+    sessions: Sessions<S>,
+    ttl: Duration,
+}
+
+impl<S> Clone for Httapi<S> {
+    fn clone(&self) -> Self {
+        Httapi {
+            handler: self.handler.clone(),
+            sessions: self.sessions.clone(),
+            ttl: self.ttl,
+        }
+    }
+}
+
+impl<S: Default + Send + 'static> Httapi<S> {
+    pub fn new<F, Fut>(handler: F) -> Self
+    where
+        F: Fn(HttapiRequest, Arc<Mutex<S>>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Document>> + Send + 'static,
+    {
+        Httapi {
+            handler: Arc::new(move |req, state| Box::pin(handler(req, state))),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            ttl: Duration::from_secs(3600),
+        }
+    }
+
+    /// Drop the state of a call idle for longer than `ttl`, one hour by
+    /// default.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Number of calls with state.
+    pub async fn sessions(&self) -> usize {
+        self.sessions.lock().await.len()
+    }
+
+    /// Answer a request, for use outside of `router`.
+    pub async fn handle(&self, req: HttapiRequest) -> Result<Document> {
+        let session_id = req.session_id.clone();
+        let exiting = req.exiting;
+        let state = {
+            let mut sessions = self.sessions.lock().await;
+            sessions.retain(|id, (_, seen)| {
+                let alive = seen.elapsed() < self.ttl;
+                if !alive {
+                    debug!("httapi session {} expired", id);
+                }
+                alive
+            });
+            let (state, seen) = sessions
+                .entry(session_id.clone())
+                .or_insert_with(|| (Arc::default(), Instant::now()));
+            *seen = Instant::now();
+            state.clone()
+        };
+
+        let r = (self.handler)(req, state).await;
+        if exiting {
+            debug!("httapi session {} exiting", session_id);
+            self.sessions.lock().await.remove(&session_id);
+        }
+        r
+    }
+
+    /// Answers requests POSTed to `/`, point the `gateway-url` at it.
+    pub fn router(self) -> Router {
+        Router::new().route("/", post(handle::<S>)).with_state(self)
+    }
+
+    pub async fn serve(self, addr: SocketAddr) -> Result<()> {
+        axum::Server::bind(&addr)
+            .serve(self.router().into_make_service())
+            .await?;
+        Ok(())
+    }
+}
+
+async fn handle<S: Default + Send + 'static>(
+    State(h): State<Httapi<S>>,
+    req: HttapiRequest,
+) -> Response {
+    let session_id = req.session_id.clone();
+    match h.handle(req).await {
+        Ok(doc) => doc.into_response(),
+        Err(e) => {
+            error!("httapi session {} failed: {}", session_id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
@@ -0,0 +1,488 @@
+use crate::channel::{api_arg, ChannelHandle};
+use crate::event::{Event, EventData, EventHandler};
+use crate::media::Leg;
+use crate::message::{Message, MsgError};
+use crate::session::Session;
+use axum::{
+    body::Body,
+    extract::{
+        ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::{HeaderMap, Request, StatusCode},
+    middleware::{self, Next},
+    response::{
+        sse::{Event as SseEvent, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post},
+    Json, Router,
+};
+use futures_util::stream::{self, Stream};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::{broadcast, Mutex};
+use tracing::{debug, warn};
+
+// a job nobody asked for is dropped after JOB_TTL, and no more than MAX_JOBS
+// are kept at once
+const JOB_TTL: Duration = Duration::from_secs(3600);
+const MAX_JOBS: usize = 10_000;
+
+struct Job {
+    // None until BACKGROUND_JOB arrives
+    result: Option<String>,
+    started: Instant,
+}
+
+// bgapi jobs by Job-UUID
+type Jobs = Arc<Mutex<HashMap<String, Job>>>;
+
+/// Decides from the request headers whether a request may go through.
+pub type Authorize = Arc<dyn Fn(&HeaderMap) -> bool + Send + Sync>;
+
+#[derive(Clone)]
+struct AppState {
+    session: Session,
+    jobs: Jobs,
+}
+
+/// JSON error body: `{"error": {"code": ..., "message": ...}}`.
+#[derive(Debug)]
+pub struct HttpError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+}
+
+impl From<anyhow::Error> for HttpError {
+    fn from(e: anyhow::Error) -> Self {
+        let (status, code) = match e.downcast_ref::<MsgError>() {
+            Some(MsgError::ChannelNotFound(_)) => (StatusCode::NOT_FOUND, "channel_not_found"),
+            Some(MsgError::ErrResponse(s)) if s.contains("No such channel") => {
+                (StatusCode::NOT_FOUND, "channel_not_found")
+            }
+            Some(MsgError::ErrResponse(_)) => (StatusCode::BAD_REQUEST, "err_response"),
+            Some(MsgError::InvalidArgument(_)) => (StatusCode::BAD_REQUEST, "invalid_argument"),
+            Some(MsgError::ConnectionClosed) => {
+                (StatusCode::SERVICE_UNAVAILABLE, "connection_closed")
+            }
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+        };
+        let message = match e.downcast_ref::<MsgError>() {
+            Some(MsgError::ErrResponse(s)) => s.clone(),
+            _ => e.to_string(),
+        };
+        HttpError {
+            status,
+            code,
+            message,
+        }
+    }
+}
+
+impl IntoResponse for HttpError {
+    fn into_response(self) -> Response {
+        let body = json!({"error": {"code": self.code, "message": self.message}});
+        (self.status, Json(body)).into_response()
+    }
+}
+
+type HttpResult = Result<Json<Value>, HttpError>;
+
+#[derive(Debug, Deserialize)]
+pub struct CommandRequest {
+    pub command: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OriginateRequest {
+    // e.g. sofia/gateway/gw1/1000 or user/1000
+    pub url: String,
+    // extension, or &app(args)
+    pub destination: String,
+    pub dialplan: Option<String>,
+    pub context: Option<String>,
+    pub caller_id_name: Option<String>,
+    pub caller_id_number: Option<String>,
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct HangupRequest {
+    pub cause: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransferRequest {
+    pub dest: String,
+    pub dialplan: Option<String>,
+    pub context: Option<String>,
+    #[serde(default)]
+    pub both: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlaybackRequest {
+    pub file: String,
+}
+
+/// REST endpoints for an authenticated `Session`.
+///
+/// The router checks nobody: `/api` and `/bgapi` run any command, `system`
+/// included. Only expose it through `router_with_auth`, or behind a proxy
+/// that authenticates.
+pub async fn router(session: Session) -> anyhow::Result<Router> {
+    let jobs: Jobs = Arc::new(Mutex::new(HashMap::new()));
+    let rx = session.subscribe();
+    session.add_events(&["BACKGROUND_JOB"]).await?;
+    tokio::spawn(collect_jobs(rx, jobs.clone()));
+
+    let state = AppState { session, jobs };
+    let router = Router::new()
+        .route("/api", post(api))
+        .route("/bgapi", post(bgapi))
+        .route("/bgapi/:job_uuid", get(job))
+        .route("/originate", post(originate))
+        .route("/channels", get(channels))
+        .route("/channels/:uuid/hangup", post(hangup))
+        .route("/channels/:uuid/transfer", post(transfer))
+        .route("/channels/:uuid/playback", post(playback))
+        .route("/registrations", get(registrations))
+        .route("/events", get(events))
+        .route("/ws", get(ws))
+        .with_state(state);
+    Ok(router)
+}
+
+/// `router`, with every request asked `authorize` first. A refused request
+/// gets 401.
+pub async fn router_with_auth(session: Session, authorize: Authorize) -> anyhow::Result<Router> {
+    let router = router(session).await?;
+    let check = move |req: Request<Body>, next: Next<Body>| {
+        let authorize = authorize.clone();
+        async move {
+            if !authorize(req.headers()) {
+                return HttpError {
+                    status: StatusCode::UNAUTHORIZED,
+                    code: "unauthorized",
+                    message: "not authorized".to_string(),
+                }
+                .into_response();
+            }
+            next.run(req).await
+        }
+    };
+    Ok(router.layer(middleware::from_fn(check)))
+}
+
+/// Serve `router_with_auth` on `addr`.
+pub async fn serve(addr: SocketAddr, session: Session, authorize: Authorize) -> anyhow::Result<()> {
+    let router = router_with_auth(session, authorize).await?;
+    axum::Server::bind(&addr)
+        .serve(router.into_make_service())
+        .await?;
+    Ok(())
+}
+
+async fn api(State(s): State<AppState>, Json(req): Json<CommandRequest>) -> HttpResult {
+    let body = s.session.api(&req.command).await?;
+    Ok(Json(json!({ "result": body })))
+}
+
+async fn bgapi(State(s): State<AppState>, Json(req): Json<CommandRequest>) -> HttpResult {
+    // register the job before the command so a fast BACKGROUND_JOB is kept
+    let job_uuid = uuid::Uuid::new_v4().to_string();
+    {
+        let mut jobs = s.jobs.lock().await;
+        jobs.retain(|_, j| j.started.elapsed() < JOB_TTL);
+        if jobs.len() >= MAX_JOBS {
+            return Err(HttpError {
+                status: StatusCode::SERVICE_UNAVAILABLE,
+                code: "too_many_jobs",
+                message: format!("{} jobs are waiting to be fetched", jobs.len()),
+            });
+        }
+        let job = Job {
+            result: None,
+            started: Instant::now(),
+        };
+        jobs.insert(job_uuid.clone(), job);
+    }
+    if let Err(e) = s.session.bgapi_job(&req.command, &job_uuid).await {
+        s.jobs.lock().await.remove(&job_uuid);
+        return Err(e.into());
+    }
+    Ok(Json(json!({ "job_uuid": job_uuid })))
+}
+
+async fn job(State(s): State<AppState>, Path(job_uuid): Path<String>) -> HttpResult {
+    let mut jobs = s.jobs.lock().await;
+    jobs.retain(|_, j| j.started.elapsed() < JOB_TTL);
+    match jobs.get(&job_uuid).map(|j| j.result.is_some()) {
+        Some(false) => Ok(Json(json!({ "status": "pending" }))),
+        Some(true) => {
+            let result = jobs.remove(&job_uuid).and_then(|j| j.result);
+            Ok(Json(json!({ "status": "done", "result": result })))
+        }
+        None => Err(HttpError {
+            status: StatusCode::NOT_FOUND,
+            code: "job_not_found",
+            message: format!("no job {}", job_uuid),
+        }),
+    }
+}
+
+async fn originate(State(s): State<AppState>, Json(req): Json<OriginateRequest>) -> HttpResult {
+    let mut vars = req
+        .variables
+        .iter()
+        .map(|(k, v)| originate_var(k, v))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if let Some(n) = &req.caller_id_name {
+        vars.push(originate_var("origination_caller_id_name", n)?);
+    }
+    if let Some(n) = &req.caller_id_number {
+        vars.push(originate_var("origination_caller_id_number", n)?);
+    }
+    let mut cmd = format!(
+        "originate {{{}}}{} {}",
+        vars.join(","),
+        api_arg(&req.url)?,
+        api_arg(&req.destination)?
+    );
+    if let Some(d) = &req.dialplan {
+        cmd.push_str(&format!(" {}", api_arg(d)?));
+        if let Some(c) = &req.context {
+            cmd.push_str(&format!(" {}", api_arg(c)?));
+        }
+    }
+
+    let body = s.session.api(&cmd).await?;
+    let uuid = body.trim().trim_start_matches("+OK").trim();
+    Ok(Json(json!({ "uuid": uuid })))
+}
+
+// `,` ends a variable of `{...}` and `'` its quoting, neither can be passed
+fn originate_var(k: &str, v: &str) -> anyhow::Result<String> {
+    if k.is_empty() || k.contains(|c: char| c.is_whitespace() || ",'={}".contains(c)) {
+        return Err(MsgError::InvalidArgument(k.to_string()).into());
+    }
+    if v.contains([',', '\'']) {
+        return Err(MsgError::InvalidArgument(v.to_string()).into());
+    }
+    Ok(format!("{}='{}'", k, v))
+}
+
+async fn hangup(
+    State(s): State<AppState>,
+    Path(uuid): Path<String>,
+    req: Option<Json<HangupRequest>>,
+) -> HttpResult {
+    let req = req.map(|Json(r)| r).unwrap_or_default();
+    let ch = ChannelHandle::new(s.session.clone(), &uuid);
+    ch.kill(req.cause.as_deref()).await?;
+    Ok(Json(json!({ "uuid": uuid })))
+}
+
+async fn transfer(
+    State(s): State<AppState>,
+    Path(uuid): Path<String>,
+    Json(req): Json<TransferRequest>,
+) -> HttpResult {
+    let ch = ChannelHandle::new(s.session.clone(), &uuid);
+    let leg = if req.both { Leg::Both } else { Leg::A };
+    ch.transfer(
+        &req.dest,
+        req.dialplan.as_deref(),
+        req.context.as_deref(),
+        leg,
+    )
+    .await?;
+    Ok(Json(json!({ "uuid": uuid })))
+}
+
+async fn playback(
+    State(s): State<AppState>,
+    Path(uuid): Path<String>,
+    Json(req): Json<PlaybackRequest>,
+) -> HttpResult {
+    // does not wait for PLAYBACK_STOP, the request returns once it started
+    s.session
+        .api(&format!(
+            "uuid_broadcast {} {} aleg",
+            api_arg(&uuid)?,
+            api_arg(&req.file)?
+        ))
+        .await?;
+    Ok(Json(json!({ "uuid": uuid })))
+}
+
+async fn channels(State(s): State<AppState>) -> HttpResult {
+    Ok(Json(show(&s.session, "channels").await?))
+}
+
+async fn registrations(State(s): State<AppState>) -> HttpResult {
+    Ok(Json(show(&s.session, "registrations").await?))
+}
+
+// rows of `show <what> as json`
+async fn show(session: &Session, what: &str) -> anyhow::Result<Value> {
+    let body = session.api(&format!("show {} as json", what)).await?;
+    let v: Map<String, Value> = serde_json::from_str(&body)?;
+    Ok(v.get("rows").cloned().unwrap_or(Value::Array(vec![])))
+}
+
+async fn collect_jobs(mut rx: broadcast::Receiver<Arc<Mutex<Message>>>, jobs: Jobs) {
+    loop {
+        let msg = match rx.recv().await {
+            Ok(m) => m,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                warn!("http gateway lagged {} messages", n);
+                #[cfg(feature = "metrics")]
+                crate::metrics::lagged("http", n);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => {
+                debug!("session closed, stop collecting jobs");
+                return;
+            }
+        };
+        let ed = match &msg.lock().await.event_data {
+            Some(ed) => ed.clone(),
+            None => continue,
+        };
+        if ed.event() != Event::BackgroundJob {
+            continue;
+        }
+        let job_uuid = ed.get_header("Job-UUID".to_string());
+        let mut jobs = jobs.lock().await;
+        match jobs.get_mut(&job_uuid) {
+            Some(j) => j.result = Some(ed.get_header("_body".to_string())),
+            // not started through the gateway
+            None => debug!("result for unknown job {}", job_uuid),
+        }
+    }
+}
+
+/// Query of `/events` and `/ws`, each a comma separated list.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EventFilter {
+    pub event: Option<String>,
+    pub subclass: Option<String>,
+    pub uuid: Option<String>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, ed: &EventData) -> bool {
+        let check = |list: &Option<String>, v: String| match list {
+            Some(l) => l.split(',').any(|i| i.trim() == v),
+            None => true,
+        };
+        // the raw name, whatever `Event` makes of it
+        check(&self.event, ed.get_header("Event-Name".to_string()))
+            && check(&self.subclass, ed.subclass())
+            && check(&self.uuid, ed.get_header("Unique-ID".to_string()))
+    }
+}
+
+// what a streaming client gets next
+enum Streamed {
+    Event(EventData),
+    // the client fell behind and missed this many messages
+    Lagged(u64),
+}
+
+// Every client reads its own broadcast receiver, so a slow client only lags
+// itself and never holds up the session read loop.
+async fn next_streamed(
+    rx: &mut broadcast::Receiver<Arc<Mutex<Message>>>,
+    filter: &EventFilter,
+) -> Option<Streamed> {
+    loop {
+        match rx.recv().await {
+            Ok(msg) => {
+                let msg = msg.lock().await;
+                if msg.is_reply() {
+                    continue;
+                }
+                if let Some(ed) = &msg.event_data {
+                    if filter.matches(ed) {
+                        return Some(Streamed::Event(ed.clone()));
+                    }
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                #[cfg(feature = "metrics")]
+                crate::metrics::lagged("http_stream", n);
+                return Some(Streamed::Lagged(n));
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        }
+    }
+}
+
+/// Server-Sent Events of whatever the session is subscribed to.
+async fn events(
+    State(s): State<AppState>,
+    Query(filter): Query<EventFilter>,
+) -> Sse<impl Stream<Item = Result<SseEvent, Infallible>>> {
+    let rx = s.session.subscribe();
+    let stream = stream::unfold((rx, filter), |(mut rx, filter)| async move {
+        let ev = match next_streamed(&mut rx, &filter).await? {
+            Streamed::Event(ed) => SseEvent::default()
+                .event(ed.get_header("Event-Name".to_string()))
+                .data(Value::Object(ed).to_string()),
+            Streamed::Lagged(n) => SseEvent::default().event("lagged").data(n.to_string()),
+        };
+        Some((Ok(ev), (rx, filter)))
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// The same stream over a WebSocket, one JSON text frame per event.
+async fn ws(
+    State(s): State<AppState>,
+    Query(filter): Query<EventFilter>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let rx = s.session.subscribe();
+    upgrade.on_upgrade(move |socket| stream_ws(socket, rx, filter))
+}
+
+async fn stream_ws(
+    mut socket: WebSocket,
+    mut rx: broadcast::Receiver<Arc<Mutex<Message>>>,
+    filter: EventFilter,
+) {
+    loop {
+        tokio::select! {
+            next = next_streamed(&mut rx, &filter) => {
+                let text = match next {
+                    Some(Streamed::Event(ed)) => Value::Object(ed).to_string(),
+                    Some(Streamed::Lagged(n)) => json!({ "lagged": n }).to_string(),
+                    None => break,
+                };
+                if socket.send(WsMessage::Text(text)).await.is_err() {
+                    debug!("websocket client gone");
+                    return;
+                }
+            }
+            incoming = socket.recv() => {
+                match incoming {
+                    Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => return,
+                    _ => {}
+                }
+            }
+        }
+    }
+    let _ = socket.send(WsMessage::Close(None)).await;
+}
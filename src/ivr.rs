@@ -0,0 +1,460 @@
+use crate::channel::{app_arg, next_event, ChannelHandle, CollectEnd, CollectOptions};
+use crate::event::{Event, EventHandler};
+use crate::media::{Leg, MediaEnd};
+use crate::message::MsgError;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+fn default_max_digits() -> usize {
+    1
+}
+
+fn default_terminators() -> String {
+    "#".to_string()
+}
+
+fn default_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+/// A step of the IVR. `next` and the menu targets name other nodes, a node
+/// without `next` ends the flow.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Node {
+    Play {
+        file: String,
+        next: Option<String>,
+    },
+    Menu {
+        prompt: String,
+        invalid_prompt: Option<String>,
+        // digits pressed -> node
+        options: HashMap<String, String>,
+        #[serde(default = "default_max_digits")]
+        max_digits: usize,
+        #[serde(default = "default_terminators")]
+        terminators: String,
+        #[serde(default = "default_timeout_ms")]
+        timeout_ms: u64,
+        #[serde(default = "default_timeout_ms")]
+        digit_timeout_ms: u64,
+        #[serde(default = "default_retries")]
+        retries: u32,
+        // taken once the retries are used up
+        on_failure: Option<String>,
+    },
+    Collect {
+        prompt: String,
+        invalid_prompt: Option<String>,
+        // the digits are stored under this name in `IvrResult::vars`
+        var: String,
+        #[serde(default = "default_max_digits")]
+        min_digits: usize,
+        #[serde(default = "default_max_digits")]
+        max_digits: usize,
+        regex: Option<String>,
+        #[serde(default = "default_terminators")]
+        terminators: String,
+        #[serde(default = "default_timeout_ms")]
+        timeout_ms: u64,
+        #[serde(default = "default_timeout_ms")]
+        digit_timeout_ms: u64,
+        #[serde(default = "default_retries")]
+        retries: u32,
+        next: Option<String>,
+        on_failure: Option<String>,
+    },
+    Execute {
+        app: String,
+        #[serde(default)]
+        arg: String,
+        next: Option<String>,
+    },
+    Transfer {
+        dest: String,
+        dialplan: Option<String>,
+        context: Option<String>,
+    },
+    Hangup {
+        cause: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ivr {
+    pub start: String,
+    pub nodes: HashMap<String, Node>,
+}
+
+impl Ivr {
+    pub fn new(start: &str) -> Self {
+        Ivr {
+            start: start.to_string(),
+            nodes: HashMap::new(),
+        }
+    }
+
+    pub fn node(mut self, name: &str, node: Node) -> Self {
+        self.nodes.insert(name.to_string(), node);
+        self
+    }
+
+    pub fn from_json(s: &str) -> Result<Self> {
+        let ivr: Ivr = serde_json::from_str(s)?;
+        ivr.validate()?;
+        Ok(ivr)
+    }
+
+    pub fn from_toml(s: &str) -> Result<Self> {
+        let ivr: Ivr = toml::from_str(s)?;
+        ivr.validate()?;
+        Ok(ivr)
+    }
+
+    /// Check that the start node and every transition exist, and that the
+    /// prompts and regexes of menus and collects can be passed to
+    /// `play_and_get_digits`.
+    pub fn validate(&self) -> Result<()> {
+        let mut targets = vec![&self.start];
+        for node in self.nodes.values() {
+            match node {
+                Node::Play { next, .. } | Node::Execute { next, .. } => targets.extend(next),
+                Node::Menu {
+                    prompt,
+                    options,
+                    on_failure,
+                    ..
+                } => {
+                    app_arg(prompt)?;
+                    targets.extend(options.values());
+                    targets.extend(on_failure);
+                }
+                Node::Collect {
+                    prompt,
+                    regex,
+                    next,
+                    on_failure,
+                    ..
+                } => {
+                    app_arg(prompt)?;
+                    if let Some(r) = regex {
+                        app_arg(r)?;
+                    }
+                    targets.extend(next);
+                    targets.extend(on_failure);
+                }
+                Node::Transfer { .. } | Node::Hangup { .. } => {}
+            }
+        }
+        for t in targets {
+            if !self.nodes.contains_key(t) {
+                return Err(anyhow::anyhow!("IVR node {} does not exist", t));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeEventKind {
+    Enter,
+    Digits(String),
+    Invalid(String),
+    Timeout,
+    Exit,
+}
+
+/// Emitted for every node visited, for analytics.
+#[derive(Debug, Clone)]
+pub struct NodeEvent {
+    pub uuid: String,
+    pub node: String,
+    pub kind: NodeEventKind,
+    pub at: SystemTime,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Completed,
+    Transferred(String),
+    Hangup,
+    // a menu or collect node ran out of retries without on_failure
+    Failed,
+}
+
+#[derive(Debug, Clone)]
+pub struct IvrResult {
+    pub outcome: Outcome,
+    // last node visited
+    pub node: String,
+    pub vars: HashMap<String, String>,
+}
+
+/// Runs an `Ivr` against one call.
+pub struct IvrRunner {
+    ivr: Ivr,
+    channel: ChannelHandle,
+    events: Option<mpsc::Sender<NodeEvent>>,
+}
+
+// what a node asks the runner to do next
+enum Step {
+    Goto(String),
+    End(Outcome),
+}
+
+impl IvrRunner {
+    pub fn new(ivr: Ivr, channel: ChannelHandle) -> Self {
+        IvrRunner {
+            ivr,
+            channel,
+            events: None,
+        }
+    }
+
+    pub fn events(mut self, tx: mpsc::Sender<NodeEvent>) -> Self {
+        self.events = Some(tx);
+        self
+    }
+
+    /// Walk the nodes from `start` until the flow ends or the caller hangs up.
+    pub async fn run(&self) -> Result<IvrResult> {
+        let mut rx = self.channel.session().subscribe();
+        let uuid = self.channel.uuid().to_string();
+        let mut result = IvrResult {
+            outcome: Outcome::Completed,
+            node: self.ivr.start.clone(),
+            vars: HashMap::new(),
+        };
+
+        // false when the session closed before the hangup
+        let hangup = async {
+            while let Some(ed) = next_event(&mut rx).await {
+                if ed.get_header("Unique-ID".to_string()) == uuid
+                    && ed.event() == Event::ChannelHangup
+                {
+                    return true;
+                }
+            }
+            false
+        };
+        let session = self.channel.session();
+        let closed = session.closed();
+
+        tokio::select! {
+            r = self.walk(&mut result) => {
+                match r {
+                    Ok(outcome) => result.outcome = outcome,
+                    Err(e) => match e.downcast_ref::<MsgError>() {
+                        Some(MsgError::Hangup) => result.outcome = Outcome::Hangup,
+                        _ => return Err(e),
+                    },
+                }
+            }
+            reason = closed => {
+                debug!("session closed in node {}: {:?}", result.node, reason);
+                return Err(MsgError::ConnectionClosed.into());
+            }
+            hung_up = hangup => {
+                if !hung_up {
+                    return Err(MsgError::ConnectionClosed.into());
+                }
+                debug!("channel {} hung up in node {}", uuid, result.node);
+                result.outcome = Outcome::Hangup;
+            }
+        }
+
+        Ok(result)
+    }
+
+    async fn walk(&self, result: &mut IvrResult) -> Result<Outcome> {
+        let mut name = self.ivr.start.clone();
+        loop {
+            let node = self
+                .ivr
+                .nodes
+                .get(&name)
+                .ok_or_else(|| anyhow::anyhow!("IVR node {} does not exist", name))?;
+            result.node = name.clone();
+            self.emit(&name, NodeEventKind::Enter);
+            let step = self.step(&name, node, &mut result.vars).await?;
+            self.emit(&name, NodeEventKind::Exit);
+            match step {
+                Step::Goto(next) => name = next,
+                Step::End(outcome) => return Ok(outcome),
+            }
+        }
+    }
+
+    async fn step(
+        &self,
+        name: &str,
+        node: &Node,
+        vars: &mut HashMap<String, String>,
+    ) -> Result<Step> {
+        match node {
+            Node::Play { file, next } => {
+                let r = self.channel.playback(file).await?;
+                if r.end == MediaEnd::Hangup {
+                    return Ok(Step::End(Outcome::Hangup));
+                }
+                Ok(goto(next))
+            }
+            Node::Execute { app, arg, next } => {
+                self.channel.execute_wait(app, arg).await?;
+                Ok(goto(next))
+            }
+            Node::Transfer {
+                dest,
+                dialplan,
+                context,
+            } => {
+                self.channel
+                    .transfer(dest, dialplan.as_deref(), context.as_deref(), Leg::A)
+                    .await?;
+                Ok(Step::End(Outcome::Transferred(dest.clone())))
+            }
+            Node::Hangup { cause } => {
+                self.channel.kill(cause.as_deref()).await?;
+                Ok(Step::End(Outcome::Completed))
+            }
+            Node::Menu {
+                prompt,
+                invalid_prompt,
+                options,
+                max_digits,
+                terminators,
+                timeout_ms,
+                digit_timeout_ms,
+                retries,
+                on_failure,
+            } => {
+                let opts = CollectOptions {
+                    min_digits: 1,
+                    max_digits: *max_digits,
+                    terminators: terminators.clone(),
+                    first_digit_timeout: Duration::from_millis(*timeout_ms),
+                    inter_digit_timeout: Duration::from_millis(*digit_timeout_ms),
+                    regex: Some("^[0-9*#]+$".to_string()),
+                    prompt: Some(prompt.clone()),
+                    invalid_prompt: None,
+                    tries: 1,
+                };
+                for _ in 0..*retries.max(&1) {
+                    let r = self.channel.collect_dtmf(opts.clone()).await?;
+                    match r.end {
+                        CollectEnd::Hangup => return Ok(Step::End(Outcome::Hangup)),
+                        CollectEnd::Timeout if r.digits.is_empty() => {
+                            self.emit(name, NodeEventKind::Timeout);
+                        }
+                        _ => match options.get(&r.digits) {
+                            Some(next) => {
+                                self.emit(name, NodeEventKind::Digits(r.digits));
+                                return Ok(Step::Goto(next.clone()));
+                            }
+                            None => {
+                                self.emit(name, NodeEventKind::Invalid(r.digits));
+                                self.play_invalid(invalid_prompt).await?;
+                            }
+                        },
+                    }
+                }
+                Ok(failure(on_failure))
+            }
+            Node::Collect {
+                prompt,
+                invalid_prompt,
+                var,
+                min_digits,
+                max_digits,
+                regex,
+                terminators,
+                timeout_ms,
+                digit_timeout_ms,
+                retries,
+                next,
+                on_failure,
+            } => {
+                let opts = CollectOptions {
+                    min_digits: *min_digits,
+                    max_digits: *max_digits,
+                    terminators: terminators.clone(),
+                    first_digit_timeout: Duration::from_millis(*timeout_ms),
+                    inter_digit_timeout: Duration::from_millis(*digit_timeout_ms),
+                    regex: regex.clone(),
+                    prompt: Some(prompt.clone()),
+                    invalid_prompt: None,
+                    tries: 1,
+                };
+                for _ in 0..*retries.max(&1) {
+                    let r = self.channel.collect_dtmf(opts.clone()).await?;
+                    match r.end {
+                        CollectEnd::Hangup => return Ok(Step::End(Outcome::Hangup)),
+                        CollectEnd::Timeout if r.digits.is_empty() => {
+                            self.emit(name, NodeEventKind::Timeout);
+                        }
+                        _ if r.valid && r.digits.len() >= *min_digits => {
+                            self.emit(name, NodeEventKind::Digits(r.digits.clone()));
+                            vars.insert(var.clone(), r.digits);
+                            return Ok(goto(next));
+                        }
+                        _ => {
+                            self.emit(name, NodeEventKind::Invalid(r.digits));
+                            self.play_invalid(invalid_prompt).await?;
+                        }
+                    }
+                }
+                Ok(failure(on_failure))
+            }
+        }
+    }
+
+    async fn play_invalid(&self, prompt: &Option<String>) -> Result<()> {
+        if let Some(p) = prompt {
+            if self.channel.playback(p).await?.end == MediaEnd::Hangup {
+                return Err(MsgError::Hangup.into());
+            }
+        }
+        Ok(())
+    }
+
+    fn emit(&self, node: &str, kind: NodeEventKind) {
+        if let Some(tx) = &self.events {
+            let ev = NodeEvent {
+                uuid: self.channel.uuid().to_string(),
+                node: node.to_string(),
+                kind,
+                at: SystemTime::now(),
+            };
+            // never let a slow consumer hold up the call
+            if let Err(e) = tx.try_send(ev) {
+                warn!("drop IVR node event: {}", e);
+            }
+        }
+    }
+}
+
+fn goto(next: &Option<String>) -> Step {
+    match next {
+        Some(n) => Step::Goto(n.clone()),
+        None => Step::End(Outcome::Completed),
+    }
+}
+
+fn failure(on_failure: &Option<String>) -> Step {
+    match on_failure {
+        Some(n) => Step::Goto(n.clone()),
+        None => Step::End(Outcome::Failed),
+    }
+}
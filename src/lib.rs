@@ -1,5 +1,29 @@
+pub mod callcenter;
+pub mod channel;
 pub mod client;
+pub mod cluster;
+pub mod conference;
 pub mod event;
+pub mod gateway;
+#[cfg(feature = "http")]
+pub mod httapi;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod ivr;
+pub mod liveness;
+pub mod media;
 pub mod message;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod pool;
+pub mod record;
+pub mod registration;
 pub mod server;
 pub mod session;
+pub mod sofia;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod uuid_api;
+pub mod webhook;
+#[cfg(feature = "http")]
+pub mod xml_curl;
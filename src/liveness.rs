@@ -0,0 +1,121 @@
+use crate::message::MsgError;
+use crate::session::{CloseReason, Session, WeakSession};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::{debug, warn};
+
+/// What was seen of a session, see `Session::health`. `last_seen` and
+/// `last_heartbeat` are kept by the session itself, the rest by its
+/// `Liveness`.
+#[derive(Debug, Clone, Default)]
+pub struct Health {
+    // round trip of the last `api status` ping
+    pub latency: Option<Duration>,
+    pub last_seen: Option<Instant>,
+    pub last_heartbeat: Option<Instant>,
+    // true while watched by a `Liveness`, false once declared dead
+    pub alive: bool,
+}
+
+/// Declares a session dead after `silence` without a frame from FreeSWITCH,
+/// which is how a half-open connection shows up. Every `ping` an
+/// `api status` is sent so a quiet connection still answers; HEARTBEAT
+/// events count as well when subscribed to.
+#[derive(Debug, Clone)]
+pub struct Liveness {
+    silence: Duration,
+    ping: Duration,
+}
+
+impl Liveness {
+    pub fn new(silence: Duration) -> Self {
+        Liveness {
+            silence,
+            ping: silence / 3,
+        }
+    }
+
+    /// Interval of the `api status` pings, a third of the silence by default.
+    pub fn ping(mut self, interval: Duration) -> Self {
+        self.ping = interval;
+        self
+    }
+
+    /// Watch `session` until it closes, it is closed as `CloseReason::Dead`
+    /// when it goes silent. Its `Session::health` has the ping latency. The
+    /// watch does not keep the session open, it ends once every other handle
+    /// is dropped.
+    pub fn watch(&self, session: &Session) -> JoinHandle<()> {
+        tokio::spawn(run(self.clone(), session.downgrade()))
+    }
+}
+
+async fn run(l: Liveness, session: WeakSession) {
+    let mut pinging: Option<JoinHandle<()>> = None;
+    watch_session(&l, &session, &mut pinging).await;
+    // a ping holds a handle, it must not outlive the watch
+    if let Some(p) = pinging {
+        p.abort();
+    }
+}
+
+async fn watch_session(l: &Liveness, session: &WeakSession, pinging: &mut Option<JoinHandle<()>>) {
+    let health = session.health().clone();
+    // the session counts as seen when the watch starts
+    let start = tokio::time::Instant::now();
+    health.send_modify(|h| h.alive = true);
+    let mut ping = tokio::time::interval(l.ping);
+
+    loop {
+        let last_seen = health
+            .borrow()
+            .last_seen
+            .map(tokio::time::Instant::from_std)
+            .map_or(start, |t| t.max(start));
+        tokio::select! {
+            _ = ping.tick() => {
+                // a ping still waiting for its answer is enough
+                if pinging.as_ref().map(|p| p.is_finished()).unwrap_or(true) {
+                    let session = match session.upgrade() {
+                        Some(s) => s,
+                        None => return,
+                    };
+                    *pinging = Some(tokio::spawn(send_ping(session, health.clone())));
+                }
+            }
+            _ = tokio::time::sleep_until(last_seen + l.silence) => {
+                // frames read meanwhile move the deadline
+                if health.borrow().last_seen.map(tokio::time::Instant::from_std) > Some(last_seen) {
+                    continue;
+                }
+                warn!("nothing read for {:?}, the session is dead", l.silence);
+                health.send_modify(|h| h.alive = false);
+                session.abort(CloseReason::Dead);
+                return;
+            }
+            reason = session.closed() => {
+                debug!("session closed: {:?}, stop liveness", reason);
+                return;
+            }
+        }
+    }
+}
+
+async fn send_ping(session: Session, health: Arc<watch::Sender<Health>>) {
+    let start = Instant::now();
+    match session.api("status").await {
+        Ok(_) => {}
+        // an error answer is an answer
+        Err(e) if matches!(e.downcast_ref::<MsgError>(), Some(MsgError::ErrResponse(_))) => {}
+        Err(e) => {
+            debug!("ping failed: {}", e);
+            return;
+        }
+    }
+    let latency = start.elapsed();
+    debug!("ping latency {:?}", latency);
+    health.send_modify(|h| h.latency = Some(latency));
+}
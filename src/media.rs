@@ -0,0 +1,224 @@
+use crate::channel::{next_event, ChannelHandle};
+use crate::event::{Event, EventData, EventHandler};
+use crate::message::{Message, MsgError};
+use anyhow::Result;
+use std::{fmt::Display, sync::Arc, time::Duration};
+use tokio::sync::{broadcast, Mutex};
+
+/// Which legs `uuid_broadcast` plays to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Leg {
+    A,
+    B,
+    Both,
+}
+
+impl Display for Leg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Leg::A => write!(f, "aleg"),
+            Leg::B => write!(f, "bleg"),
+            Leg::Both => write!(f, "both"),
+        }
+    }
+}
+
+/// Stop recording after `hits` frames below `threshold` energy.
+#[derive(Debug, Clone)]
+pub struct Silence {
+    pub threshold: u32,
+    pub hits: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MediaEnd {
+    Finished,
+    // interrupted by one of the playback_terminators
+    Dtmf(String),
+    // stopped by uuid_break or another application
+    Stopped,
+    Hangup,
+}
+
+#[derive(Debug, Clone)]
+pub struct MediaResult {
+    pub end: MediaEnd,
+    pub path: String,
+    // recorded length, only set for recordings
+    pub duration: Option<Duration>,
+}
+
+impl ChannelHandle {
+    /// `playback`, resolves when the app completes after its PLAYBACK_STOP.
+    /// Fails with the app's response when it completes without playing, e.g.
+    /// on a missing file.
+    pub async fn playback(&self, file: &str) -> Result<MediaResult> {
+        self.channel_events().await?;
+        let rx = self.session().subscribe();
+        let app_uuid = self.execute("playback", file).await?;
+        wait_stop(rx, self.uuid(), Some(&app_uuid), Event::PlaybackStop, file).await
+    }
+
+    /// `record`, resolves when the app completes after its RECORD_STOP.
+    /// `limit` is the maximum length. Fails with the app's response when it
+    /// completes without recording.
+    pub async fn record(
+        &self,
+        path: &str,
+        limit: Option<Duration>,
+        silence: Option<Silence>,
+    ) -> Result<MediaResult> {
+        let mut arg = path.to_string();
+        if let Some(l) = limit {
+            arg.push_str(&format!(" {}", l.as_secs()));
+            if let Some(s) = silence {
+                arg.push_str(&format!(" {} {}", s.threshold, s.hits));
+            }
+        } else if let Some(s) = silence {
+            arg.push_str(&format!(" 0 {} {}", s.threshold, s.hits));
+        }
+
+        self.channel_events().await?;
+        let rx = self.session().subscribe();
+        let app_uuid = self.execute("record", &arg).await?;
+        wait_stop(rx, self.uuid(), Some(&app_uuid), Event::RecordStop, path).await
+    }
+
+    /// Text to speech, played as `tts://engine|voice|text`.
+    pub async fn speak(&self, engine: &str, voice: &str, text: &str) -> Result<MediaResult> {
+        self.playback(&format!("tts://{}|{}|{}", engine, voice, text))
+            .await
+    }
+
+    /// `uuid_broadcast`, resolves on the PLAYBACK_STOP of channel `uuid`.
+    pub async fn broadcast(&self, uuid: &str, path: &str, leg: Leg) -> Result<MediaResult> {
+        self.channel_events().await?;
+        let rx = self.session().subscribe();
+        self.session()
+            .api(&format!("uuid_broadcast {} {} {}", uuid, path, leg))
+            .await?;
+        wait_stop(rx, uuid, None, Event::PlaybackStop, path).await
+    }
+
+    /// `displace_session`, mixes `path` into the call in the background until
+    /// `limit` or `stop_displace`.
+    pub async fn displace(&self, path: &str, limit: Option<Duration>) -> Result<()> {
+        let arg = match limit {
+            Some(l) => format!("{} m +{}", path, l.as_secs()),
+            None => format!("{} m", path),
+        };
+        self.execute_wait("displace_session", &arg).await?;
+        Ok(())
+    }
+
+    pub async fn stop_displace(&self, path: &str) -> Result<()> {
+        self.session()
+            .api(&format!("uuid_displace {} stop {}", self.uuid(), path))
+            .await?;
+        Ok(())
+    }
+
+    /// Interrupt the current playback; the pending `playback` resolves with
+    /// `MediaEnd::Stopped`.
+    pub async fn stop_playback(&self) -> Result<()> {
+        self.break_media(false).await
+    }
+}
+
+// the stop event comes before the app completes, a completion without it
+// means the app failed. `app_uuid` is None for uuid_broadcast, which is
+// matched on the end of the file path instead as FreeSWITCH may prefix it.
+async fn wait_stop(
+    mut rx: broadcast::Receiver<Arc<Mutex<Message>>>,
+    uuid: &str,
+    app_uuid: Option<&str>,
+    stop: Event,
+    path: &str,
+) -> Result<MediaResult> {
+    let path_header = match stop {
+        Event::RecordStop => "Record-File-Path",
+        _ => "Playback-File-Path",
+    };
+    let mut hangup = false;
+    let mut stopped = None;
+
+    while let Some(ed) = next_event(&mut rx).await {
+        if ed.get_header("Unique-ID".to_string()) != uuid {
+            continue;
+        }
+        match ed.event() {
+            e if e == stop => match app_uuid {
+                Some(_) => stopped = Some(ed),
+                None if ed.get_header(path_header.to_string()).ends_with(path) => {
+                    return Ok(media_result(&ed, path, &stop, hangup));
+                }
+                None => {}
+            },
+            Event::ChannelExecuteComplete
+                if app_uuid.is_some()
+                    && app_uuid == Some(&ed.get_header("Application-UUID".to_string())) =>
+            {
+                if let Some(s) = stopped {
+                    return Ok(media_result(&s, path, &stop, hangup));
+                }
+                if hangup {
+                    return Ok(hung_up(path));
+                }
+                let response = match ed.get_header("Application-Response".to_string()) {
+                    r if r.is_empty() => {
+                        format!("{} failed", ed.get_header("Application".to_string()))
+                    }
+                    r => r,
+                };
+                return Err(MsgError::ErrResponse(response).into());
+            }
+            Event::ChannelHangup => hangup = true,
+            // no stop event will follow any more
+            Event::ChannelHangupComplete => {
+                return Ok(match stopped {
+                    Some(s) => media_result(&s, path, &stop, true),
+                    None => hung_up(path),
+                });
+            }
+            _ => {}
+        }
+    }
+    Err(MsgError::ConnectionClosed.into())
+}
+
+fn hung_up(path: &str) -> MediaResult {
+    MediaResult {
+        end: MediaEnd::Hangup,
+        path: path.to_string(),
+        duration: None,
+    }
+}
+
+fn media_result(ed: &EventData, path: &str, stop: &Event, hangup: bool) -> MediaResult {
+    let terminator = ed.get_header("variable_playback_terminator_used".to_string());
+    // RECORD_STOP has no Playback-Status, a record always "breaks" when it ends
+    let interrupted = match stop {
+        Event::RecordStop => true,
+        _ => ed.get_header("Playback-Status".to_string()) == "break",
+    };
+    let end = match (interrupted, terminator.is_empty()) {
+        _ if hangup => MediaEnd::Hangup,
+        (true, false) => MediaEnd::Dtmf(terminator),
+        (true, true) if *stop == Event::PlaybackStop => MediaEnd::Stopped,
+        _ => MediaEnd::Finished,
+    };
+    let duration = match stop {
+        Event::RecordStop => ed
+            .get_header("variable_record_ms".to_string())
+            .parse()
+            .ok()
+            .map(Duration::from_millis),
+        _ => None,
+    };
+
+    MediaResult {
+        end,
+        path: path.to_string(),
+        duration,
+    }
+}
@@ -1,16 +1,17 @@
-use crate::event::EventData;
 use crate::event::EventHandler;
+use crate::event::{Event, EventData};
 use anyhow::{Error, Result};
-use serde::de;
+use quick_xml::{events::Event as XmlEvent, Reader};
 use serde_json::{Map, Value};
-use std::{collections::HashMap, fmt::Display, str::FromStr};
+use std::{collections::HashMap, fmt::Display, str::FromStr, time::Duration};
 use thiserror;
-use tokio::{
-    io::{AsyncBufReadExt, AsyncReadExt, BufReader},
-    net::tcp::OwnedReadHalf,
-};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
 use tracing::{debug, error, warn};
-use urlencoding::decode;
+use urlencoding::{decode, encode};
+
+// limits of a single frame read by `Message::parse`
+pub const MAX_HEADER_LEN: usize = 1024 * 1024;
+pub const MAX_BODY_LEN: usize = 16 * 1024 * 1024;
 
 #[derive(thiserror::Error, Debug)]
 pub enum MsgError {
@@ -31,12 +32,47 @@ pub enum MsgError {
     #[error("Got -ERR response")]
     ErrResponse(String),
 
+    /// `-ERR No such channel!` from a uuid_* api
+    #[error("No such channel {0}")]
+    ChannelNotFound(String),
+
+    /// `Conference <name> not found` from mod_conference
+    #[error("No such conference {0}")]
+    ConferenceNotFound(String),
+
+    /// `Non-Existant ID <id>` or `Member <id> not found` from mod_conference
+    #[error("No such conference member {0}")]
+    MemberNotFound(String),
+
+    /// the channel hung up before the operation completed
+    #[error("Channel hung up")]
+    Hangup,
+
+    /// header block over MAX_HEADER_LEN, the stream can't be followed any more
+    #[error("Message header too large")]
+    HeaderTooLarge,
+
+    /// Content-Length over MAX_BODY_LEN, the body was skipped
+    #[error("Message body of {0} bytes too large")]
+    BodyTooLarge(usize),
+
+    #[error("Message is not valid UTF-8")]
+    InvalidUtf8,
+
+    /// a value FreeSWITCH would split or misread, e.g. an app argument with a space
+    #[error("Invalid argument {0}")]
+    InvalidArgument(String),
+
+    /// no answer in time, the connection may still be fine
+    #[error("Timed out after {0:?}")]
+    Timeout(Duration),
+
     /// Invalid message encoding
     #[error(transparent)]
     Other(Error),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum FormatType {
     Xml,
     Json,
@@ -66,14 +102,16 @@ impl Display for FormatType {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ContentType {
     TextEventJson,
     TextDisconnectNotice,
     CommandReply,
     ApiResponse,
     TextEventPlain,
+    TextEventXml,
     AuthRequest,
+    LogData,
 }
 
 impl FromStr for ContentType {
@@ -86,29 +124,35 @@ impl FromStr for ContentType {
             "command/reply" => Ok(ContentType::CommandReply),
             "api/response" => Ok(ContentType::ApiResponse),
             "text/event-plain" => Ok(ContentType::TextEventPlain),
+            "text/event-xml" => Ok(ContentType::TextEventXml),
             "auth/request" => Ok(ContentType::AuthRequest),
+            "log/data" => Ok(ContentType::LogData),
             _ => Err(MsgError::Other(anyhow::anyhow!("Invalid ContentType"))),
         }
     }
 }
 
-impl Into<String> for ContentType {
-    fn into(self) -> String {
-        match self {
+impl From<ContentType> for String {
+    fn from(value: ContentType) -> Self {
+        match value {
             ContentType::TextEventJson => "text/event-json".to_string(),
             ContentType::TextDisconnectNotice => "text/disconnect-notice".to_string(),
             ContentType::CommandReply => "command/reply".to_string(),
             ContentType::ApiResponse => "api/response".to_string(),
             ContentType::TextEventPlain => "text/event-plain".to_string(),
+            ContentType::TextEventXml => "text/event-xml".to_string(),
             ContentType::AuthRequest => "auth/request".to_string(),
+            ContentType::LogData => "log/data".to_string(),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Message {
     pub header: Option<HashMap<String, String>>,
     pub event_data: Option<EventData>,
+    // raw body of api/response and log/data messages
+    pub body: Option<String>,
 }
 
 impl Message {
@@ -116,28 +160,43 @@ impl Message {
         Message {
             header: headers,
             event_data: Some(data),
+            body: None,
         }
     }
     // parse text protocol message
-    #[tracing::instrument]
-    pub async fn parse(r: &mut BufReader<OwnedReadHalf>) -> Result<Message> {
-        if r.buffer().len() == 0 {
-            r.fill_buf().await?;
-        }
-
+    #[tracing::instrument(skip(r))]
+    pub async fn parse<R: AsyncBufRead + Unpin>(r: &mut R) -> Result<Message> {
         let mut header = HashMap::new();
         let mut content_length = None;
         let mut event_data: Option<EventData> = None;
+        let mut api_body: Option<String> = None;
+        let mut header_len = 0;
+        let mut utf8_ok = true;
 
         loop {
-            let mut line = String::new();
+            let mut line = Vec::new();
+            let limit = (MAX_HEADER_LEN - header_len) as u64;
 
-            match r.read_line(&mut line).await {
-                Ok(n) if n == 0 => {
+            match (&mut *r).take(limit).read_until(b'\n', &mut line).await {
+                Ok(0) if limit == 0 => return Err(MsgError::HeaderTooLarge.into()),
+                Ok(0) => {
                     error!("connection closed");
                     return Err(MsgError::ConnectionClosed.into());
                 }
-                Ok(_) => {
+                Ok(n) => {
+                    header_len += n;
+                    if line.last() != Some(&b'\n') {
+                        if header_len >= MAX_HEADER_LEN {
+                            return Err(MsgError::HeaderTooLarge.into());
+                        }
+                        return Err(MsgError::ConnectionClosed.into());
+                    }
+                    // still read the header, a Content-Length must be honoured to
+                    // stay in step with the stream
+                    if std::str::from_utf8(&line).is_err() {
+                        utf8_ok = false;
+                    }
+                    let line = String::from_utf8_lossy(&line);
                     if line.trim().is_empty() {
                         break; // end of headers
                     }
@@ -145,9 +204,6 @@ impl Message {
                         header.insert(field.to_string(), value.to_string());
                     }
                 }
-                Err(ref e) if e.kind() == tokio::io::ErrorKind::WouldBlock => {
-                    break;
-                }
                 Err(e) => {
                     error!("failed to read from socket; err = {:?}", e);
                     return Err(MsgError::ReadFailed.into());
@@ -158,22 +214,44 @@ impl Message {
 
         // parse Content-Length
         if let Some(content_length_str) = h.get("Content-Length") {
-            if let Ok(length) = content_length_str.parse::<usize>() {
-                content_length = Some(length);
+            match content_length_str.parse::<usize>() {
+                Ok(length) => content_length = Some(length),
+                // the end of the frame is unknown, the stream can't be trusted any more
+                Err(_) => return Err(MsgError::ReadFailed.into()),
             }
         }
 
-        // read body
+        // read body, growing the buffer only as data arrives
         let mut body = None;
         if let Some(length) = content_length {
-            let mut content = vec![0u8; length];
-            r.read_exact(&mut content).await.unwrap();
-            body = Some(String::from_utf8_lossy(&content).to_string());
+            if length > MAX_BODY_LEN {
+                // skip it to stay in step with the stream
+                tokio::io::copy(&mut (&mut *r).take(length as u64), &mut tokio::io::sink())
+                    .await
+                    .map_err(|_| MsgError::ReadFailed)?;
+                return Err(MsgError::BodyTooLarge(length).into());
+            }
+            let mut content = Vec::with_capacity(length.min(64 * 1024));
+            let n = (&mut *r)
+                .take(length as u64)
+                .read_to_end(&mut content)
+                .await
+                .map_err(|_| MsgError::ReadFailed)?;
+            if n < length {
+                return Err(MsgError::ConnectionClosed.into());
+            }
+            match String::from_utf8(content) {
+                Ok(b) => body = Some(b),
+                Err(_) => utf8_ok = false,
+            }
+        }
+        if !utf8_ok {
+            return Err(MsgError::InvalidUtf8.into());
         }
 
         // get Content-Type
         if let Some(content_type) = h.get("Content-Type") {
-            let msg_type = match ContentType::from_str(&content_type) {
+            let msg_type = match ContentType::from_str(content_type) {
                 Ok(t) => {
                     // debug!("Content-Type: {:?}", t);
                     t
@@ -183,16 +261,22 @@ impl Message {
                     return Err(anyhow::anyhow!("Unsupported Content-Type"));
                 }
             };
-            // decode if value is url encoded and Content-Type is not text/event-json
-            if msg_type != ContentType::TextEventJson {
+            // decode if value is url encoded and Content-Type is not text/event-json, api/response or log/data,
+            // text/event-plain values are decoded one by one, text/event-xml ones are xml escaped
+            if msg_type != ContentType::TextEventJson
+                && msg_type != ContentType::ApiResponse
+                && msg_type != ContentType::LogData
+                && msg_type != ContentType::TextEventPlain
+                && msg_type != ContentType::TextEventXml
+            {
                 if let Some(data) = &body {
-                    if data.contains("-ERR") {
+                    if data.starts_with("-ERR") {
                         error!("Received error json response body {}", data);
                         let mut ed = EventData::new();
                         ed.insert("Reply-Text".to_string(), Value::String(data.to_string()));
                         event_data = Some(ed);
                     } else {
-                        let s: String = decode(&data)?.to_string();
+                        let s: String = decode(data)?.to_string();
                         body = Some(s);
                     }
                 }
@@ -201,14 +285,14 @@ impl Message {
             match msg_type {
                 ContentType::TextEventJson => {
                     if let Some(body) = &body {
-                        if body.contains("-ERR") {
+                        if body.starts_with("-ERR") {
                             // parse error body
                             let mut ed = EventData::new();
                             ed.insert("Reply-Text".to_string(), Value::String(body.to_string()));
                             event_data = Some(ed);
                         } else {
                             // parse normal body
-                            match serde_json::from_str::<EventData>(&body) {
+                            match serde_json::from_str::<EventData>(body) {
                                 Ok(ed) => {
                                     event_data = Some(ed);
                                 }
@@ -221,6 +305,7 @@ impl Message {
                     }
                 }
                 ContentType::ApiResponse => {
+                    api_body = body.clone();
                     if let Some(reply_text) = header.get("Reply-Text") {
                         if let Some(body) = &body {
                             if body.contains("-ERR") {
@@ -234,7 +319,6 @@ impl Message {
                                 event_data = Some(ed);
                             } else {
                                 debug!("Received api response {:?}", body);
-                                // todo: parse api response body
                             }
                         }
                     }
@@ -264,19 +348,45 @@ impl Message {
                     if let Some(body) = &body {
                         let mut ed: EventData = Map::new();
 
-                        if body.contains("-ERR") {
+                        if body.starts_with("-ERR") {
                             ed.insert("Reply-Text".to_string(), Value::String(body.to_string()));
                         } else {
-                            body.split("\n").for_each(|line| {
+                            // headers, then the event body after a blank line
+                            let (headers, event_body) = match body.split_once("\n\n") {
+                                Some((h, b)) => (h, b),
+                                None => (body.as_str(), ""),
+                            };
+                            for line in headers.split('\n') {
                                 if let Some((k, v)) = parse_header_line(line) {
-                                    ed.insert(k.to_string(), Value::String(v.to_string()));
+                                    let v = decode(v)?.to_string();
+                                    ed.insert(k.to_string(), Value::String(v));
                                 }
-                            });
+                            }
+                            if !event_body.is_empty() {
+                                ed.insert(
+                                    "_body".to_string(),
+                                    Value::String(event_body.to_string()),
+                                );
+                            }
                         }
 
                         event_data = Some(ed)
                     }
                 }
+                ContentType::TextEventXml => {
+                    if let Some(body) = &body {
+                        if body.starts_with("-ERR") {
+                            let mut ed = EventData::new();
+                            ed.insert("Reply-Text".to_string(), Value::String(body.to_string()));
+                            event_data = Some(ed);
+                        } else {
+                            event_data = Some(parse_xml_event(body)?);
+                        }
+                    }
+                }
+                ContentType::LogData => {
+                    api_body = body.clone();
+                }
                 _ => {}
             }
         }
@@ -284,9 +394,25 @@ impl Message {
         Ok(Message {
             header: Some(header),
             event_data,
+            body: api_body,
         })
     }
 
+    pub fn content_type(&self) -> Option<ContentType> {
+        self.header
+            .as_ref()
+            .and_then(|h| h.get("Content-Type"))
+            .and_then(|t| ContentType::from_str(t).ok())
+    }
+
+    // command/reply and api/response are answers to commands sent on the session
+    pub fn is_reply(&self) -> bool {
+        matches!(
+            self.content_type(),
+            Some(ContentType::CommandReply) | Some(ContentType::ApiResponse)
+        )
+    }
+
     pub fn get_uuid(&mut self) -> Option<String> {
         if let Some(ed) = &self.event_data {
             return Some(ed.get_header("Unique-ID".to_string()));
@@ -308,14 +434,27 @@ impl Message {
     }
 }
 
+// a frame `Message::parse` reads back, Content-Length is worked out from the body
 impl Display for Message {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let body = match (self.content_type(), &self.event_data) {
+            (Some(ContentType::TextEventPlain), Some(ed)) => Some(generate_plain_event(ed)),
+            (Some(ContentType::TextEventJson), Some(ed)) | (None, Some(ed)) => {
+                Some(serde_json::to_string(ed).map_err(|_| std::fmt::Error)?)
+            }
+            _ => self.body.clone(),
+        };
+
         let mut result = String::new();
         if let Some(h) = &self.header {
             result = generate_headers(h);
         }
-        if let Some(ed) = &self.event_data {
-            result.push_str(&generate_content(&serde_json::to_string(&ed).unwrap()));
+        if let Some(b) = body.as_ref().filter(|b| !b.is_empty()) {
+            result.push_str(&generate_content_length(b));
+            result.push('\n');
+            result.push_str(b);
+        } else {
+            result.push('\n');
         }
         write!(f, "{}", result)
     }
@@ -331,7 +470,59 @@ fn parse_header_line(line: &str) -> Option<(&str, &str)> {
     }
 }
 
+// <event><headers><Name>value</Name>...</headers><body>...</body></event>
+fn parse_xml_event(xml: &str) -> Result<EventData> {
+    let mut reader = Reader::from_str(xml);
+    let mut ed = EventData::new();
+    // names of the open elements
+    let mut path: Vec<String> = vec![];
+    let mut text = String::new();
+
+    loop {
+        let ev = reader.read_event().map_err(|e| {
+            error!("failed to parse xml event; err = {:?}", e);
+            MsgError::BodyParseFailed
+        })?;
+        match ev {
+            XmlEvent::Start(e) => {
+                path.push(String::from_utf8_lossy(e.name().as_ref()).to_string());
+                text.clear();
+            }
+            XmlEvent::Empty(e) if path == ["event", "headers"] => {
+                let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
+                ed.insert(name, Value::String(String::new()));
+            }
+            XmlEvent::Text(t) => {
+                let t = t.unescape().map_err(|_| MsgError::BodyParseFailed)?;
+                text.push_str(&t);
+            }
+            XmlEvent::CData(t) => text.push_str(&String::from_utf8_lossy(&t)),
+            XmlEvent::End(_) => {
+                let name = path.pop().unwrap_or_default();
+                let value = Value::String(std::mem::take(&mut text));
+                match path.as_slice() {
+                    [e, h] if e == "event" && h == "headers" => {
+                        ed.insert(name, value);
+                    }
+                    [e] if e == "event" && name == "body" => {
+                        ed.insert("_body".to_string(), value);
+                    }
+                    _ => {}
+                }
+            }
+            XmlEvent::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(ed)
+}
+
 fn generate_headers(headers: &HashMap<String, String>) -> String {
+    let mut headers: Vec<_> = headers
+        .iter()
+        .filter(|(field, _)| *field != "Content-Length")
+        .collect();
+    headers.sort();
     headers
         .iter()
         .map(|(field, value)| format!("{}: {}\n", field, value))
@@ -342,6 +533,68 @@ fn generate_content_length(content: &str) -> String {
     format!("Content-Length: {}\n", content.len())
 }
 
-fn generate_content(content: &str) -> String {
-    format!("{}\n", content)
+// text/event-plain body: url encoded headers, then `_body` after a blank line
+pub(crate) fn generate_plain_event(ed: &EventData) -> String {
+    let mut result: String = ed
+        .keys()
+        .filter(|k| *k != "_body" && *k != "Content-Length")
+        .map(|k| format!("{}: {}\n", k, encode(&ed.get_header(k.to_string()))))
+        .collect();
+    let body = ed.get_header("_body".to_string());
+    if !body.is_empty() {
+        result.push_str(&generate_content_length(&body));
+        result.push('\n');
+        result.push_str(&body);
+    }
+    result
+}
+
+// header lines of a command frame, `Content-Length` and `_body` are worked out from the body
+fn generate_command_headers(ed: &EventData, url_encode: bool) -> String {
+    ed.keys()
+        .filter(|k| *k != "_body" && *k != "Content-Length")
+        .map(|k| {
+            let v = ed.get_header(k.to_string());
+            if url_encode {
+                format!("{}: {}\n", k, encode(&v))
+            } else {
+                // a raw value can't span lines
+                format!("{}: {}\n", k, v.replace(['\r', '\n'], " "))
+            }
+        })
+        .collect()
+}
+
+// the end of a command frame, the blank line and the body if there is one
+fn generate_command_body(body: Option<&str>) -> String {
+    match body.filter(|b| !b.is_empty()) {
+        Some(b) => format!("{}\n{}", generate_content_length(b), b),
+        None => "\n".to_string(),
+    }
+}
+
+/// A `sendevent` frame firing `event` with the headers of `ed`, url encoded,
+/// and an optional body. A CUSTOM event needs `Event-Subclass` in `ed`.
+pub fn generate_sendevent(event: &Event, ed: &EventData, body: Option<&str>) -> String {
+    format!(
+        "sendevent {}\n{}{}",
+        event,
+        generate_command_headers(ed, true),
+        generate_command_body(body)
+    )
+}
+
+/// A `sendmsg` frame, e.g. with `call-command: execute`. Without `uuid` it
+/// goes to the channel of an outbound session.
+pub fn generate_sendmsg(uuid: Option<&str>, ed: &EventData, body: Option<&str>) -> String {
+    let cmd = match uuid {
+        Some(u) => format!("sendmsg {}\n", u),
+        None => "sendmsg\n".to_string(),
+    };
+    format!(
+        "{}{}{}",
+        cmd,
+        generate_command_headers(ed, false),
+        generate_command_body(body)
+    )
 }
@@ -0,0 +1,233 @@
+use crate::event::{Event, EventData, EventHandler};
+use crate::message::Message;
+use prometheus::{
+    exponential_buckets, histogram_opts, opts, Encoder, HistogramVec, IntCounterVec, IntGauge,
+    Registry, TextEncoder,
+};
+use std::{
+    collections::HashSet,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+use tokio::{
+    sync::{broadcast, Mutex},
+    task::JoinHandle,
+};
+use tracing::error;
+
+/// Counters and gauges of every session in the process, exposed in the
+/// Prometheus text format by `gather`.
+pub struct Metrics {
+    registry: Registry,
+    // by Content-Type
+    pub frames: IntCounterVec,
+    // by Event-Name
+    pub events: IntCounterVec,
+    // by MsgError, the frames that could not be parsed
+    pub parse_failures: IntCounterVec,
+    // by subscriber, messages dropped for a slow broadcast receiver
+    pub lagged: IntCounterVec,
+    // by command, seconds until the reply
+    pub command_latency: HistogramVec,
+    // by address
+    pub reconnects: IntCounterVec,
+    // as seen by `track`
+    pub channels: IntGauge,
+    pub calls: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("esl".to_string()), None)?;
+        let m = Metrics {
+            frames: IntCounterVec::new(
+                opts!("frames_total", "Frames received by content type"),
+                &["content_type"],
+            )?,
+            events: IntCounterVec::new(opts!("events_total", "Events received"), &["event"])?,
+            parse_failures: IntCounterVec::new(
+                opts!("parse_failures_total", "Frames that failed to parse"),
+                &["error"],
+            )?,
+            lagged: IntCounterVec::new(
+                opts!(
+                    "broadcast_lagged_total",
+                    "Messages dropped for a lagging receiver"
+                ),
+                &["receiver"],
+            )?,
+            // 1ms to about 16s
+            command_latency: HistogramVec::new(
+                histogram_opts!(
+                    "command_duration_seconds",
+                    "Time from a command to its reply",
+                    exponential_buckets(0.001, 2.0, 15)?
+                ),
+                &["command"],
+            )?,
+            reconnects: IntCounterVec::new(
+                opts!("reconnects_total", "Connections made again after a loss"),
+                &["addr"],
+            )?,
+            channels: IntGauge::new("channels", "Live channels")?,
+            calls: IntGauge::new("calls", "Bridged calls")?,
+            registry,
+        };
+        m.registry.register(Box::new(m.frames.clone()))?;
+        m.registry.register(Box::new(m.events.clone()))?;
+        m.registry.register(Box::new(m.parse_failures.clone()))?;
+        m.registry.register(Box::new(m.lagged.clone()))?;
+        m.registry.register(Box::new(m.command_latency.clone()))?;
+        m.registry.register(Box::new(m.reconnects.clone()))?;
+        m.registry.register(Box::new(m.channels.clone()))?;
+        m.registry.register(Box::new(m.calls.clone()))?;
+        Ok(m)
+    }
+
+    /// The registry of the metrics, to gather them along with others.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+}
+
+/// The metrics of the process.
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| Metrics::new().expect("valid metric definitions"))
+}
+
+/// Every metric in the Prometheus text format.
+pub fn gather() -> String {
+    let mut buf = vec![];
+    if let Err(e) = TextEncoder::new().encode(&metrics().registry.gather(), &mut buf) {
+        error!("failed to encode metrics: {}", e);
+    }
+    String::from_utf8(buf).unwrap_or_default()
+}
+
+pub(crate) fn frame(msg: &mut Message) {
+    let m = metrics();
+    let content_type = msg.get_header("Content-Type");
+    m.frames.with_label_values(&[&content_type]).inc();
+    if let Some(ed) = &msg.event_data {
+        m.events.with_label_values(&[&ed.event().to_string()]).inc();
+    }
+}
+
+pub(crate) fn parse_failure(error: &str) {
+    metrics().parse_failures.with_label_values(&[error]).inc();
+}
+
+pub(crate) fn lagged(receiver: &str, n: u64) {
+    metrics().lagged.with_label_values(&[receiver]).inc_by(n);
+}
+
+// the first word of a frame, and the api for api and bgapi
+pub(crate) fn command_name(frame: &str) -> Option<String> {
+    let mut words = frame.split_whitespace();
+    match words.next()? {
+        c @ ("api" | "bgapi") => Some(format!("{} {}", c, words.next().unwrap_or_default())),
+        c => Some(c.to_string()),
+    }
+}
+
+pub(crate) fn command(name: Option<String>, elapsed: Duration) {
+    if let Some(name) = name {
+        metrics()
+            .command_latency
+            .with_label_values(&[&name])
+            .observe(elapsed.as_secs_f64());
+    }
+}
+
+// counted before each new attempt after a lost session or a failed connect
+pub(crate) fn reconnect(addr: &str) {
+    metrics().reconnects.with_label_values(&[addr]).inc();
+}
+
+/// Keep the channel and call gauges from the CHANNEL_CREATE, _DESTROY,
+/// _BRIDGE and _UNBRIDGE events of a session, which must be subscribed to
+/// them. What it counted is taken off again when the session is gone, so
+/// several sessions can be tracked at once.
+pub fn track(mut rx: broadcast::Receiver<Arc<Mutex<Message>>>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut tracker = Tracker::default();
+        loop {
+            match rx.recv().await {
+                Ok(msg) => {
+                    if let Some(ed) = &msg.lock().await.event_data {
+                        tracker.update(ed);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => lagged("metrics", n),
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+#[derive(Default)]
+struct Tracker {
+    channels: HashSet<String>,
+    // by the uuid of the leg the bridge was reported on
+    calls: HashSet<String>,
+}
+
+impl Tracker {
+    fn update(&mut self, ed: &EventData) {
+        let uuid = ed.get_header("Unique-ID".to_string());
+        if uuid.is_empty() {
+            return;
+        }
+        let m = metrics();
+        match ed.event() {
+            Event::ChannelCreate => add(&mut self.channels, uuid, &m.channels),
+            Event::ChannelBridge => add(&mut self.calls, uuid, &m.calls),
+            Event::ChannelUnbridge => {
+                let other = ed.get_header("Other-Leg-Unique-ID".to_string());
+                if !remove(&mut self.calls, &uuid, &m.calls) {
+                    remove(&mut self.calls, &other, &m.calls);
+                }
+            }
+            Event::ChannelDestroy => {
+                remove(&mut self.channels, &uuid, &m.channels);
+                remove(&mut self.calls, &uuid, &m.calls);
+            }
+            _ => {}
+        }
+    }
+}
+
+// a gauge follows the size of its set
+fn add(set: &mut HashSet<String>, uuid: String, gauge: &IntGauge) {
+    if set.insert(uuid) {
+        gauge.inc();
+    }
+}
+
+fn remove(set: &mut HashSet<String>, uuid: &str, gauge: &IntGauge) -> bool {
+    let removed = set.remove(uuid);
+    if removed {
+        gauge.dec();
+    }
+    removed
+}
+
+impl Drop for Tracker {
+    fn drop(&mut self) {
+        let m = metrics();
+        m.channels.sub(self.channels.len() as i64);
+        m.calls.sub(self.calls.len() as i64);
+    }
+}
+
+/// `GET /metrics` for Prometheus to scrape, to merge into an app's router.
+#[cfg(feature = "http")]
+pub fn router<S: Clone + Send + Sync + 'static>() -> axum::Router<S> {
+    use axum::{http::header, routing::get};
+
+    axum::Router::new().route(
+        "/metrics",
+        get(|| async { ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], gather()) }),
+    )
+}
@@ -0,0 +1,317 @@
+use crate::client::Client;
+use crate::message::{Message, MsgError};
+use crate::session::{CloseReason, Session};
+use anyhow::Result;
+use std::{
+    collections::VecDeque,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{broadcast, Mutex, OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, warn};
+
+pub struct EslPoolBuilder {
+    client: Client,
+    min: usize,
+    max: usize,
+    idle_timeout: Duration,
+    health_interval: Duration,
+    checkout_timeout: Option<Duration>,
+}
+
+impl EslPoolBuilder {
+    /// Connections kept open even when idle, 1 by default.
+    pub fn min(mut self, min: usize) -> Self {
+        self.min = min;
+        self
+    }
+
+    /// Connections open at most, 10 by default.
+    pub fn max(mut self, max: usize) -> Self {
+        self.max = max;
+        self
+    }
+
+    /// Idle connections over `min` are closed after this long, 5 minutes by
+    /// default.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// How often idle connections are checked with `api status`, 30s by
+    /// default.
+    pub fn health_interval(mut self, interval: Duration) -> Self {
+        self.health_interval = interval;
+        self
+    }
+
+    /// How long `get` waits for a connection, for ever by default.
+    pub fn checkout_timeout(mut self, timeout: Duration) -> Self {
+        self.checkout_timeout = Some(timeout);
+        self
+    }
+
+    /// Open the `min` connections and start the health checks.
+    pub async fn start(self) -> Result<EslPool> {
+        let max = self.max.max(1);
+        let (tx, rx) = broadcast::channel(16);
+        let (shutdown, _) = broadcast::channel(1);
+        let inner = Arc::new(Inner {
+            client: self.client,
+            min: self.min.min(max),
+            idle_timeout: self.idle_timeout,
+            checkout_timeout: self.checkout_timeout,
+            permits: Arc::new(Semaphore::new(max)),
+            idle: std::sync::Mutex::new(VecDeque::new()),
+            size: AtomicUsize::new(0),
+            tx,
+            _rx: rx,
+            shutdown,
+        });
+
+        for _ in 0..inner.min {
+            let session = inner.connect().await?;
+            inner.release(session);
+        }
+        tokio::spawn(maintain(Arc::downgrade(&inner), self.health_interval));
+
+        Ok(EslPool { inner })
+    }
+}
+
+struct Idle {
+    session: Session,
+    since: Instant,
+}
+
+struct Inner {
+    client: Client,
+    min: usize,
+    idle_timeout: Duration,
+    checkout_timeout: Option<Duration>,
+    // one per connection in use or being opened, waiters are served in turn
+    permits: Arc<Semaphore>,
+    // most recently used last; never held across an await, so a dropped
+    // `PooledSession` can go back without a runtime
+    idle: std::sync::Mutex<VecDeque<Idle>>,
+    // open connections, idle or not
+    size: AtomicUsize,
+    // pooled sessions are only for api calls, their events go nowhere
+    tx: broadcast::Sender<Arc<Mutex<Message>>>,
+    _rx: broadcast::Receiver<Arc<Mutex<Message>>>,
+    // the sessions shut down with the pool
+    shutdown: broadcast::Sender<bool>,
+}
+
+impl Inner {
+    async fn connect(&self) -> Result<Session> {
+        let session = self
+            .client
+            .connect(self.tx.clone(), self.shutdown.subscribe())
+            .await?;
+        let size = self.size.fetch_add(1, Ordering::SeqCst) + 1;
+        debug!("pool opened a connection, {} open", size);
+        Ok(session)
+    }
+
+    fn idle(&self) -> std::sync::MutexGuard<'_, VecDeque<Idle>> {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn release(&self, session: Session) {
+        if session.close_reason().is_some() {
+            self.closed();
+            return;
+        }
+        self.idle().push_back(Idle {
+            session,
+            since: Instant::now(),
+        });
+    }
+
+    fn closed(&self) {
+        let size = self.size.fetch_sub(1, Ordering::SeqCst) - 1;
+        debug!("pool lost a connection, {} open", size);
+    }
+
+    // an open idle session, or a new one
+    async fn checkout(&self) -> Result<Session> {
+        loop {
+            let idle = self.idle().pop_back();
+            match idle {
+                Some(i) if i.session.close_reason().is_some() => self.closed(),
+                Some(i) => return Ok(i.session),
+                None => return self.connect().await,
+            }
+        }
+    }
+}
+
+/// A pool of authenticated connections to one FreeSWITCH, so short api
+/// calls don't queue up behind each other on a single connection.
+/// Waiters get a connection in the order they asked.
+#[derive(Clone)]
+pub struct EslPool {
+    inner: Arc<Inner>,
+}
+
+impl EslPool {
+    pub fn builder(client: Client) -> EslPoolBuilder {
+        EslPoolBuilder {
+            client,
+            min: 1,
+            max: 10,
+            idle_timeout: Duration::from_secs(300),
+            health_interval: Duration::from_secs(30),
+            checkout_timeout: None,
+        }
+    }
+
+    /// Open connections, idle or in use.
+    pub fn size(&self) -> usize {
+        self.inner.size.load(Ordering::SeqCst)
+    }
+
+    pub fn idle(&self) -> usize {
+        self.inner.idle().len()
+    }
+
+    /// Check out a connection, it goes back to the pool when dropped.
+    pub async fn get(&self) -> Result<PooledSession> {
+        let acquire = self.inner.permits.clone().acquire_owned();
+        let permit = match self.inner.checkout_timeout {
+            Some(t) => tokio::time::timeout(t, acquire)
+                .await
+                .map_err(|_| MsgError::Timeout(t))??,
+            None => acquire.await?,
+        };
+        let session = self.inner.checkout().await?;
+        Ok(PooledSession {
+            session: Some(session),
+            pool: self.inner.clone(),
+            _permit: permit,
+        })
+    }
+
+    /// Run an api command on the next free connection.
+    pub async fn api(&self, cmd: &str) -> Result<String> {
+        self.get().await?.api(cmd).await
+    }
+
+    /// Run every command at once across the connections, the results are
+    /// in the order of `cmds`.
+    pub async fn api_all<S: AsRef<str>>(&self, cmds: &[S]) -> Vec<Result<String>> {
+        let handles: Vec<_> = cmds
+            .iter()
+            .map(|cmd| {
+                let pool = self.clone();
+                let cmd = cmd.as_ref().to_string();
+                tokio::spawn(async move { pool.api(&cmd).await })
+            })
+            .collect();
+
+        let mut results = Vec::with_capacity(handles.len());
+        for h in handles {
+            results.push(h.await.unwrap_or_else(|e| Err(e.into())));
+        }
+        results
+    }
+}
+
+/// A connection checked out of an `EslPool`.
+pub struct PooledSession {
+    session: Option<Session>,
+    pool: Arc<Inner>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Deref for PooledSession {
+    type Target = Session;
+
+    fn deref(&self) -> &Session {
+        self.session.as_ref().expect("session taken on drop only")
+    }
+}
+
+impl Drop for PooledSession {
+    fn drop(&mut self) {
+        // back in the pool before the permit is freed
+        if let Some(s) = self.session.take() {
+            self.pool.release(s);
+        }
+    }
+}
+
+// evict idle connections over `min`, check the others and open up to `min`
+async fn maintain(pool: std::sync::Weak<Inner>, interval: Duration) {
+    let mut tick = tokio::time::interval(interval);
+    tick.tick().await;
+    loop {
+        tick.tick().await;
+        let pool = match pool.upgrade() {
+            Some(p) => p,
+            None => return,
+        };
+
+        // a permit for each connection checked, so a checkout meanwhile
+        // can't open one too many
+        let (idle, permits): (Vec<Idle>, _) = {
+            let mut idle = pool.idle();
+            match pool
+                .permits
+                .clone()
+                .try_acquire_many_owned(idle.len() as u32)
+            {
+                Ok(p) => (idle.drain(..).collect(), Some(p)),
+                Err(_) => (vec![], None),
+            }
+        };
+        let mut keep = vec![];
+        for i in idle {
+            let over_min = pool.size.load(Ordering::SeqCst) > pool.min;
+            if over_min && i.since.elapsed() >= pool.idle_timeout {
+                debug!("pool closing an idle connection");
+                i.session.close().await;
+                pool.closed();
+                continue;
+            }
+            match i.session.with_timeout(Some(interval)).api("status").await {
+                Ok(_) => keep.push(i),
+                Err(e) => {
+                    warn!("pool connection failed its health check: {}", e);
+                    i.session.abort(CloseReason::Dead);
+                    pool.closed();
+                }
+            }
+        }
+        // in front of those released meanwhile, they were idle longer
+        {
+            let mut idle = pool.idle();
+            for i in keep.into_iter().rev() {
+                idle.push_front(i);
+            }
+        }
+        drop(permits);
+
+        while pool.size.load(Ordering::SeqCst) < pool.min {
+            // only while a permit is free, a checkout opens its own
+            let permit = match pool.permits.clone().try_acquire_owned() {
+                Ok(p) => p,
+                Err(_) => break,
+            };
+            match pool.connect().await {
+                Ok(s) => pool.release(s),
+                Err(e) => {
+                    warn!("pool failed to connect: {}", e);
+                    break;
+                }
+            }
+            drop(permit);
+        }
+    }
+}
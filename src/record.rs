@@ -0,0 +1,304 @@
+use crate::message::{Message, MsgError};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    path::Path,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    fs::File,
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader, BufWriter, ReadBuf},
+    sync::{broadcast, mpsc, Mutex},
+    task::JoinHandle,
+    time::MissedTickBehavior,
+};
+use tracing::{debug, error, warn};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    // read from FreeSWITCH
+    In,
+    // written to FreeSWITCH
+    Out,
+}
+
+/// One raw ESL frame, a line of the recording.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Frame {
+    // microseconds since the unix epoch
+    pub ts: u64,
+    pub dir: Direction,
+    pub data: String,
+    // `data` is hex encoded, the frame was not valid UTF-8
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hex: bool,
+}
+
+impl Frame {
+    pub fn new(ts: u64, dir: Direction, data: &[u8]) -> Self {
+        let (data, hex) = match std::str::from_utf8(data) {
+            Ok(d) => (d.to_string(), false),
+            Err(_) => (hex::encode(data), true),
+        };
+        Frame { ts, dir, data, hex }
+    }
+
+    /// The frame as it went over the wire.
+    pub fn bytes(&self) -> Result<Vec<u8>> {
+        if self.hex {
+            return Ok(hex::decode(&self.data)?);
+        }
+        Ok(self.data.as_bytes().to_vec())
+    }
+
+    /// `Content-Type` of the frame, empty for frames written.
+    pub fn content_type(&self) -> String {
+        let bytes = self.bytes().unwrap_or_default();
+        String::from_utf8_lossy(&bytes)
+            .lines()
+            .take_while(|l| !l.trim().is_empty())
+            .find_map(|l| l.strip_prefix("Content-Type:"))
+            .map(|t| t.trim().to_string())
+            .unwrap_or_default()
+    }
+}
+
+// frames waiting to be written, the ones over it are dropped
+const QUEUE_LEN: usize = 10_000;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Writes the frames of a `Session` to a file, one JSON per line. The file
+/// is flushed every second and once every clone is dropped.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    tx: mpsc::Sender<Frame>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Recorder {
+    pub async fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::create(path).await?;
+        let (tx, mut rx) = mpsc::channel::<Frame>(QUEUE_LEN);
+
+        tokio::spawn(async move {
+            let mut w = BufWriter::new(file);
+            let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+            flush.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let mut dirty = false;
+            loop {
+                let r = tokio::select! {
+                    frame = rx.recv() => match frame {
+                        Some(f) => {
+                            dirty = true;
+                            write_frame(&mut w, &f).await
+                        }
+                        None => break,
+                    },
+                    _ = flush.tick(), if dirty => {
+                        dirty = false;
+                        w.flush().await
+                    }
+                };
+                if let Err(e) = r {
+                    error!("failed to write recording: {}", e);
+                    return;
+                }
+            }
+            if let Err(e) = w.flush().await {
+                error!("failed to write recording: {}", e);
+            }
+            debug!("recording finished");
+        });
+
+        Ok(Recorder {
+            tx,
+            dropped: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// Queue a frame, it is dropped when the writer can't keep up.
+    pub fn record(&self, dir: Direction, data: &[u8]) {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        if self.tx.try_send(Frame::new(ts, dir, data)).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Frames dropped so far, by every clone.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+async fn write_frame(w: &mut BufWriter<File>, frame: &Frame) -> io::Result<()> {
+    let line = match serde_json::to_string(frame) {
+        Ok(l) => l + "\n",
+        Err(e) => {
+            error!("failed to encode frame: {}", e);
+            return Ok(());
+        }
+    };
+    w.write_all(line.as_bytes()).await
+}
+
+// keeps what `Message::parse` consumed so it can be recorded as one frame
+pub(crate) struct TeeReader<R> {
+    inner: BufReader<R>,
+    frame: Option<Vec<u8>>,
+}
+
+impl<R: AsyncRead + Unpin> TeeReader<R> {
+    pub(crate) fn new(r: R, record: bool) -> Self {
+        TeeReader {
+            inner: BufReader::new(r),
+            frame: if record { Some(vec![]) } else { None },
+        }
+    }
+
+    pub(crate) fn take(&mut self) -> Vec<u8> {
+        self.frame.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for TeeReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let r = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Some(f) = &mut this.frame {
+            f.extend_from_slice(&buf.filled()[before..]);
+        }
+        r
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncBufRead for TeeReader<R> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut self.get_mut().inner).poll_fill_buf(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        if let Some(f) = &mut this.frame {
+            f.extend_from_slice(&this.inner.buffer()[..amt]);
+        }
+        Pin::new(&mut this.inner).consume(amt);
+    }
+}
+
+/// How fast a recording is played back.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pace {
+    // keep the gaps between the frames
+    RealTime,
+    Fast,
+}
+
+/// Plays a recording back.
+#[derive(Debug, Clone)]
+pub struct Replayer {
+    frames: Vec<Frame>,
+    pace: Pace,
+}
+
+impl Replayer {
+    pub async fn open(path: impl AsRef<Path>, pace: Pace) -> Result<Self> {
+        let mut lines = BufReader::new(File::open(path).await?).lines();
+        let mut frames = vec![];
+        let mut bad = None;
+        while let Some(line) = lines.next_line().await? {
+            // only the last line may be cut short, by a crash before a flush
+            if let Some(e) = bad.take() {
+                return Err(e);
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(f) => frames.push(f),
+                Err(e) => bad = Some(anyhow::Error::from(e)),
+            }
+        }
+        if let Some(e) = bad {
+            warn!("skipped the last line of the recording: {}", e);
+        }
+        Ok(Replayer::new(frames, pace))
+    }
+
+    pub fn new(frames: Vec<Frame>, pace: Pace) -> Self {
+        Replayer { frames, pace }
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// The frames read from FreeSWITCH.
+    pub fn incoming(&self) -> impl Iterator<Item = &Frame> {
+        self.frames.iter().filter(|f| f.dir == Direction::In)
+    }
+
+    // in real time, sleep for the gap between the previous frame and `frame`
+    pub(crate) async fn pace(&self, last: &mut Option<u64>, frame: &Frame) {
+        if let (Pace::RealTime, Some(last)) = (&self.pace, *last) {
+            let gap = frame.ts.saturating_sub(last);
+            tokio::time::sleep(Duration::from_micros(gap)).await;
+        }
+        *last = Some(frame.ts);
+    }
+
+    /// Parse the frames read from FreeSWITCH with `Message::parse` and send
+    /// them to `tx`, like the messages of `Session::subscribe`.
+    pub fn play(self, tx: broadcast::Sender<Arc<Mutex<Message>>>) -> JoinHandle<Result<()>> {
+        let (mut w, r) = tokio::io::duplex(64 * 1024);
+
+        tokio::spawn(async move {
+            let mut last = None;
+            for frame in self.incoming() {
+                self.pace(&mut last, frame).await;
+                let data = match frame.bytes() {
+                    Ok(d) => d,
+                    Err(e) => {
+                        error!("invalid hex frame in recording: {}", e);
+                        return;
+                    }
+                };
+                if w.write_all(&data).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        tokio::spawn(async move {
+            let mut r = BufReader::new(r);
+            loop {
+                match Message::parse(&mut r).await {
+                    Ok(msg) => {
+                        let _ = tx.send(Arc::new(Mutex::new(msg)));
+                    }
+                    // like the read loop of `Session`, only MsgError ends the stream
+                    Err(e) => match e.downcast_ref::<MsgError>() {
+                        Some(MsgError::ConnectionClosed) => return Ok(()),
+                        Some(_) => return Err(e),
+                        None => warn!("failed to parse replayed frame: {}", e),
+                    },
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,262 @@
+use crate::event::{Event, EventData, EventHandler};
+use crate::message::Message;
+use crate::session::Session;
+use anyhow::Result;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::{broadcast, Mutex},
+    task::JoinHandle,
+};
+use tracing::{debug, warn};
+
+pub const REGISTER: &str = "sofia::register";
+pub const UNREGISTER: &str = "sofia::unregister";
+pub const EXPIRE: &str = "sofia::expire";
+pub const PRE_REGISTER: &str = "sofia::pre_register";
+
+#[derive(Debug, Clone)]
+pub struct RegisteredContact {
+    pub user: String,
+    pub domain: String,
+    pub profile: String,
+    pub call_id: String,
+    pub contact: String,
+    pub user_agent: String,
+    pub network_ip: String,
+    pub network_port: String,
+    // unix time in seconds
+    pub expires_at: u64,
+    pub last_seen: u64,
+}
+
+impl RegisteredContact {
+    pub fn aor(&self) -> String {
+        format!("{}@{}", self.user, self.domain)
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at <= now
+    }
+}
+
+// row of `show registrations as json`
+#[derive(Debug, Deserialize)]
+struct Row {
+    #[serde(default)]
+    reg_user: String,
+    #[serde(default)]
+    realm: String,
+    #[serde(default)]
+    token: String,
+    #[serde(default)]
+    url: String,
+    #[serde(default)]
+    expires: String,
+    #[serde(default)]
+    network_ip: String,
+    #[serde(default)]
+    network_port: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Rows {
+    #[serde(default)]
+    rows: Vec<Row>,
+}
+
+// contacts per user@domain, keyed by call-id
+type Contacts = HashMap<String, HashMap<String, RegisteredContact>>;
+
+/// Directory of registered users fed by `sofia::*register` events.
+#[derive(Debug, Clone)]
+pub struct RegistrationRegistry {
+    session: Session,
+    contacts: Arc<Mutex<Contacts>>,
+}
+
+impl RegistrationRegistry {
+    pub fn new(session: Session) -> Self {
+        RegistrationRegistry {
+            session,
+            contacts: Arc::new(Mutex::new(Contacts::new())),
+        }
+    }
+
+    /// Subscribe to the registration events, load the current registrations
+    /// and keep the registry up to date in the background until the handle
+    /// is aborted. The events come in the format the session already uses,
+    /// JSON if none.
+    pub async fn start(&self) -> Result<JoinHandle<()>> {
+        let rx = self.session.subscribe();
+        self.session
+            .add_events(&["CUSTOM", REGISTER, UNREGISTER, EXPIRE, PRE_REGISTER])
+            .await?;
+        self.resync().await?;
+
+        Ok(tokio::spawn(run(rx, self.contacts.clone())))
+    }
+
+    /// Replace the registry with the output of `show registrations`.
+    pub async fn resync(&self) -> Result<()> {
+        let body = self.session.api("show registrations as json").await?;
+        let rows: Rows = serde_json::from_str(&body)?;
+        let now = now();
+
+        let mut contacts = self.contacts.lock().await;
+        let mut fresh = Contacts::new();
+        for row in rows.rows {
+            let aor = format!("{}@{}", row.reg_user, row.realm);
+            // show registrations has no user agent, keep the one we know
+            let user_agent = contacts
+                .get(&aor)
+                .and_then(|c| c.get(&row.token))
+                .map(|c| c.user_agent.clone())
+                .unwrap_or_default();
+            let c = RegisteredContact {
+                user: row.reg_user,
+                domain: row.realm,
+                profile: String::new(),
+                call_id: row.token.clone(),
+                contact: row.url,
+                user_agent,
+                network_ip: row.network_ip,
+                network_port: row.network_port,
+                expires_at: row.expires.parse().unwrap_or_default(),
+                last_seen: now,
+            };
+            fresh.entry(aor).or_default().insert(row.token, c);
+        }
+        *contacts = fresh;
+
+        Ok(())
+    }
+
+    /// Whether `user@domain` has at least one unexpired registration.
+    pub async fn is_reachable(&self, aor: &str) -> bool {
+        let now = now();
+        match self.contacts.lock().await.get(aor) {
+            Some(c) => c.values().any(|c| !c.is_expired(now)),
+            None => false,
+        }
+    }
+
+    pub async fn contacts(&self, aor: &str) -> Vec<RegisteredContact> {
+        match self.contacts.lock().await.get(aor) {
+            Some(c) => c.values().cloned().collect(),
+            None => vec![],
+        }
+    }
+
+    pub async fn all(&self) -> Vec<RegisteredContact> {
+        let contacts = self.contacts.lock().await;
+        contacts
+            .values()
+            .flat_map(|c| c.values().cloned())
+            .collect()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+async fn run(mut rx: broadcast::Receiver<Arc<Mutex<Message>>>, contacts: Arc<Mutex<Contacts>>) {
+    let mut tick = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            msg = rx.recv() => {
+                let msg = match msg {
+                    Ok(m) => m,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("registration registry lagged {} messages", n);
+                        #[cfg(feature = "metrics")]
+                        crate::metrics::lagged("registration", n);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        debug!("session closed, stop registration registry");
+                        return;
+                    }
+                };
+                let ed = match &msg.lock().await.event_data {
+                    Some(ed) => ed.clone(),
+                    None => continue,
+                };
+                if ed.event() == Event::Custom {
+                    apply(&mut *contacts.lock().await, &ed);
+                }
+            }
+            _ = tick.tick() => {
+                let now = now();
+                let mut contacts = contacts.lock().await;
+                for c in contacts.values_mut() {
+                    c.retain(|_, c| !c.is_expired(now));
+                }
+                contacts.retain(|_, c| !c.is_empty());
+            }
+        }
+    }
+}
+
+// header lookup with a fallback, sofia::expire uses user/host instead of from-user/from-host
+fn header(ed: &EventData, k: &str, fallback: &str) -> String {
+    let v = ed.get_header(k.to_string());
+    if v.is_empty() {
+        ed.get_header(fallback.to_string())
+    } else {
+        v
+    }
+}
+
+fn apply(contacts: &mut Contacts, ed: &EventData) {
+    let user = header(ed, "from-user", "user");
+    let domain = header(ed, "from-host", "host");
+    let call_id = ed.get_header("call-id".to_string());
+    let aor = format!("{}@{}", user, domain);
+    let now = now();
+
+    match ed.subclass().as_str() {
+        REGISTER => {
+            let expires: u64 = ed
+                .get_header("expires".to_string())
+                .parse()
+                .unwrap_or_default();
+            let c = RegisteredContact {
+                user,
+                domain,
+                profile: ed.get_header("profile-name".to_string()),
+                call_id: call_id.clone(),
+                contact: ed.get_header("contact".to_string()),
+                user_agent: ed.get_header("user-agent".to_string()),
+                network_ip: ed.get_header("network-ip".to_string()),
+                network_port: ed.get_header("network-port".to_string()),
+                expires_at: now + expires,
+                last_seen: now,
+            };
+            contacts.entry(aor).or_default().insert(call_id, c);
+        }
+        UNREGISTER | EXPIRE => {
+            if let Some(c) = contacts.get_mut(&aor) {
+                c.remove(&call_id);
+                if c.is_empty() {
+                    contacts.remove(&aor);
+                }
+            }
+        }
+        PRE_REGISTER => {
+            // not authenticated yet, only refresh what we already know
+            if let Some(c) = contacts.get_mut(&aor).and_then(|c| c.get_mut(&call_id)) {
+                c.last_seen = now;
+            }
+        }
+        _ => {}
+    }
+}
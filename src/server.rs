@@ -14,6 +14,15 @@ impl Server {
         Ok(s)
     }
 
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    /// The bound address, with the port picked when `addr` asked for port 0.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
     pub async fn accept(&mut self) -> Result<(TcpStream, SocketAddr)> {
         let (stream, addr) = self.listener.accept().await?;
         Ok((stream, addr))
@@ -1,26 +1,153 @@
+use crate::event::{Event, EventData, EventHandler};
+use crate::liveness::Health;
+use crate::message::ContentType;
+use crate::message::FormatType;
 use crate::message::Message;
 use crate::message::MsgError;
+use crate::message::{generate_sendevent, generate_sendmsg};
+use crate::record::{Direction, Recorder, TeeReader};
 use anyhow::Result;
-use std::sync::Arc;
-use tokio::sync::{broadcast, mpsc};
+use std::collections::VecDeque;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Weak,
+};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
 use tokio::{
-    io::{AsyncWriteExt, BufReader, BufWriter},
+    io::{AsyncWriteExt, BufWriter},
     net::{
         tcp::{OwnedReadHalf, OwnedWriteHalf},
         TcpStream,
     },
     sync::Mutex,
 };
-use tracing::{debug, error, info, trace};
+use tracing::{debug, error, info};
+
+// waiters for command/reply and api/response, in the order the commands were written.
+// `None` marks a command sent without waiting for its reply.
+type Pending = Arc<Mutex<VecDeque<Option<oneshot::Sender<Message>>>>>;
+
+type Reason = Arc<watch::Sender<Option<CloseReason>>>;
+
+/// How long `close` waits for FreeSWITCH to say bye and hang up.
+pub const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Why a session closed, see `Session::closed`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CloseReason {
+    // `close` sent exit and FreeSWITCH hung up
+    Exit,
+    // FreeSWITCH hung up on its own, with a disconnect notice or not
+    RemoteExit,
+    // FreeSWITCH didn't hang up in time after exit
+    LingerTimeout,
+    Io(String),
+    // FreeSWITCH hung up before auth was accepted
+    AuthFailed,
+    // nothing was read for too long, see `Liveness`
+    Dead,
+    // the shutdown signal, or every handle of the session was dropped
+    Shutdown,
+}
+
+// the first reason wins
+fn set_reason(reason: &Reason, r: CloseReason) {
+    reason.send_if_modified(|cur| {
+        if cur.is_some() {
+            return false;
+        }
+        debug!("session closed: {:?}", r);
+        *cur = Some(r);
+        true
+    });
+}
 
+async fn stopped(rx: &mut watch::Receiver<Option<CloseReason>>) -> CloseReason {
+    match rx.wait_for(Option::is_some).await {
+        Ok(r) => r.clone().unwrap_or(CloseReason::Shutdown),
+        Err(_) => CloseReason::Shutdown,
+    }
+}
+
+// the read and write tasks, stopped when the last handle goes away
 #[derive(Debug)]
+struct Tasks {
+    reason: Reason,
+    read: Mutex<Option<JoinHandle<()>>>,
+    write: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Drop for Tasks {
+    fn drop(&mut self) {
+        set_reason(&self.reason, CloseReason::Shutdown);
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Session {
     in_tx: broadcast::Sender<Arc<Mutex<Message>>>,
     out_tx: mpsc::Sender<String>,
+    pending: Pending,
 
-    close_tx: broadcast::Sender<bool>,
-    close_rx: broadcast::Receiver<bool>,
+    // set by `close`, a hang up is then an Exit
+    exiting: Arc<AtomicBool>,
+    tasks: Arc<Tasks>,
     pub is_closed: Arc<Mutex<bool>>,
+    // of every request of this handle
+    timeout: Option<Duration>,
+    // of the events, set by `event` for the whole connection
+    format: Arc<std::sync::Mutex<Option<FormatType>>>,
+    // frames read are noted by the read task, the rest by `Liveness`
+    health: Arc<watch::Sender<Health>>,
+}
+
+/// A `Session` handle that does not keep it open, see `Session::downgrade`.
+/// Once every `Session` handle is dropped the session shuts down.
+#[derive(Debug, Clone)]
+pub struct WeakSession {
+    in_tx: broadcast::Sender<Arc<Mutex<Message>>>,
+    out_tx: mpsc::WeakSender<String>,
+    pending: Pending,
+    exiting: Arc<AtomicBool>,
+    tasks: Weak<Tasks>,
+    reason: Reason,
+    is_closed: Arc<Mutex<bool>>,
+    timeout: Option<Duration>,
+    format: Arc<std::sync::Mutex<Option<FormatType>>>,
+    health: Arc<watch::Sender<Health>>,
+}
+
+impl WeakSession {
+    /// The session, `None` once every `Session` handle was dropped.
+    pub fn upgrade(&self) -> Option<Session> {
+        Some(Session {
+            in_tx: self.in_tx.clone(),
+            tasks: self.tasks.upgrade()?,
+            out_tx: self.out_tx.upgrade()?,
+            pending: self.pending.clone(),
+            exiting: self.exiting.clone(),
+            is_closed: self.is_closed.clone(),
+            timeout: self.timeout,
+            format: self.format.clone(),
+            health: self.health.clone(),
+        })
+    }
+
+    /// Why the session closed, `CloseReason::Shutdown` when its handles were
+    /// dropped.
+    pub async fn closed(&self) -> CloseReason {
+        stopped(&mut self.reason.subscribe()).await
+    }
+
+    pub(crate) fn abort(&self, r: CloseReason) {
+        set_reason(&self.reason, r);
+    }
+
+    pub(crate) fn health(&self) -> &Arc<watch::Sender<Health>> {
+        &self.health
+    }
 }
 
 impl Session {
@@ -30,10 +157,29 @@ impl Session {
         in_tx: broadcast::Sender<Arc<Mutex<Message>>>,
         // shutdown is used to receive a signal to shutdown
         shutdown: broadcast::Receiver<bool>,
+    ) -> Self {
+        Session::start(stream, in_tx, shutdown, None).await
+    }
+
+    /// Like `new`, every frame read and written goes to `recorder` as well.
+    pub async fn recorded(
+        stream: TcpStream,
+        in_tx: broadcast::Sender<Arc<Mutex<Message>>>,
+        shutdown: broadcast::Receiver<bool>,
+        recorder: Recorder,
+    ) -> Self {
+        Session::start(stream, in_tx, shutdown, Some(recorder)).await
+    }
+
+    async fn start(
+        stream: TcpStream,
+        in_tx: broadcast::Sender<Arc<Mutex<Message>>>,
+        shutdown: broadcast::Receiver<bool>,
+        recorder: Option<Recorder>,
     ) -> Self {
         let (r, w) = stream.into_split();
         let writer = BufWriter::new(w);
-        let reader = BufReader::new(r);
+        let reader = TeeReader::new(r, recorder.is_some());
 
         let tx1 = in_tx.clone();
 
@@ -41,98 +187,399 @@ impl Session {
         let w_signal = shutdown.resubscribe();
 
         let (out_tx, out_rx) = mpsc::channel::<String>(1000);
-        let (close_tx, close_rx) = broadcast::channel::<bool>(1);
+        let reason: Reason = Arc::new(watch::channel(None).0);
+        let exiting = Arc::new(AtomicBool::new(false));
         let is_closed = Arc::new(Mutex::new(false));
+        let pending: Pending = Arc::new(Mutex::new(VecDeque::new()));
+        let health = Arc::new(watch::channel(Health::default()).0);
 
-        tokio::spawn(read(
+        let read = tokio::spawn(read(
             is_closed.clone(),
-            close_tx.clone(),
-            close_rx.resubscribe(),
+            reason.clone(),
+            exiting.clone(),
             tx1,
+            pending.clone(),
             reader,
+            recorder.clone(),
+            health.clone(),
             r_signal,
         ));
 
-        tokio::spawn(write(
+        let write = tokio::spawn(write(
             is_closed.clone(),
-            close_tx.clone(),
-            close_rx.resubscribe(),
+            reason.clone(),
             out_rx,
             writer,
+            recorder,
             w_signal,
         ));
 
         Session {
             in_tx,
             out_tx,
-            close_rx,
-            close_tx,
+            pending,
+            exiting,
+            tasks: Arc::new(Tasks {
+                reason,
+                read: Mutex::new(Some(read)),
+                write: Mutex::new(Some(write)),
+            }),
             is_closed,
+            timeout: None,
+            format: Arc::new(std::sync::Mutex::new(None)),
+            health,
+        }
+    }
+
+    /// A handle that does not keep the session open.
+    pub fn downgrade(&self) -> WeakSession {
+        WeakSession {
+            in_tx: self.in_tx.clone(),
+            out_tx: self.out_tx.downgrade(),
+            pending: self.pending.clone(),
+            exiting: self.exiting.clone(),
+            tasks: Arc::downgrade(&self.tasks),
+            reason: self.tasks.reason.clone(),
+            is_closed: self.is_closed.clone(),
+            timeout: self.timeout,
+            format: self.format.clone(),
+            health: self.health.clone(),
+        }
+    }
+
+    /// When this session last read a frame or a HEARTBEAT, and what its
+    /// `Liveness` found.
+    pub fn health(&self) -> watch::Receiver<Health> {
+        self.health.subscribe()
+    }
+
+    /// A handle on the same session whose requests fail with
+    /// `MsgError::Timeout` after `timeout`, `None` waits for ever.
+    pub fn with_timeout(&self, timeout: Option<Duration>) -> Session {
+        Session {
+            timeout,
+            ..self.clone()
         }
     }
 
-    pub async fn sender(self) -> mpsc::Sender<String> {
-        self.out_tx.clone()
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
     }
 
     pub async fn is_closed(&self) -> bool {
-        let c = self.is_closed.lock().await;
-        *c
+        self.tasks.reason.borrow().is_some()
+    }
+
+    /// Why the session closed, `None` while it is open.
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        self.tasks.reason.borrow().clone()
+    }
+
+    /// Resolves with the reason once the session is closed.
+    pub async fn closed(&self) -> CloseReason {
+        stopped(&mut self.tasks.reason.subscribe()).await
+    }
+
+    // close without exit, the connection is gone or given up on
+    pub(crate) fn abort(&self, r: CloseReason) {
+        set_reason(&self.tasks.reason, r);
+    }
+
+    /// Send `exit` and wait for the `+OK bye` and for FreeSWITCH to hang up,
+    /// the frames queued before are written first. Gives up after
+    /// `CLOSE_TIMEOUT` with `LingerTimeout`. The read and write tasks are
+    /// joined before it returns.
+    pub async fn close(&self) -> CloseReason {
+        if !self.is_closed().await {
+            self.exiting.store(true, Ordering::SeqCst);
+            let bye = async {
+                if let Err(e) = self.command("exit").await {
+                    debug!("exit failed: {}", e);
+                }
+                self.closed().await
+            };
+            if tokio::time::timeout(CLOSE_TIMEOUT, bye).await.is_err() {
+                set_reason(&self.tasks.reason, CloseReason::LingerTimeout);
+            }
+        }
+
+        for task in [&self.tasks.read, &self.tasks.write] {
+            if let Some(t) = task.lock().await.take() {
+                let _ = t.await;
+            }
+        }
+        self.closed().await
     }
     pub async fn send(&mut self, data: String) -> Result<()> {
-        let b = format!("{}\n\n", data.trim());
+        let b = format!("{}\n\n", one_line(data.trim())?);
+
+        // room in the queue first, a cancelled send leaves nothing behind
+        let permit = self
+            .out_tx
+            .reserve()
+            .await
+            .map_err(|_| MsgError::ConnectionClosed)?;
+
+        // keep the reply queue in step, nobody waits for this reply
+        let mut pending = self.pending.lock().await;
+        if self.is_closed().await {
+            return Err(MsgError::ConnectionClosed.into());
+        }
+        pending.push_back(None);
+        permit.send(b);
+
+        Ok(())
+    }
+
+    /// Subscribe to every message read from the session.
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Mutex<Message>>> {
+        self.in_tx.subscribe()
+    }
+
+    /// Send a command and wait for its `command/reply` or `api/response`.
+    pub async fn request(&self, data: String) -> Result<Message> {
+        self.request_frame(format!("{}\n\n", one_line(data.trim())?))
+            .await
+    }
+
+    // a whole frame, a body must not be trimmed
+    async fn request_frame(&self, b: String) -> Result<Message> {
+        #[cfg(feature = "metrics")]
+        let (start, cmd) = (std::time::Instant::now(), crate::metrics::command_name(&b));
+        let (tx, rx) = oneshot::channel();
+        let reply = async {
+            // room in the queue first, so the waiter and the frame go in together
+            let permit = match self.out_tx.reserve().await {
+                Ok(p) => p,
+                Err(_) => return Err(MsgError::ConnectionClosed),
+            };
+            {
+                // the read task clears the waiters once it is closed
+                let mut pending = self.pending.lock().await;
+                if self.is_closed().await {
+                    return Err(MsgError::ConnectionClosed);
+                }
+                pending.push_back(Some(tx));
+                permit.send(b);
+            }
+            rx.await.map_err(|_| MsgError::ConnectionClosed)
+        };
+
+        // a waiter that timed out stays in the queue, its reply is dropped
+        // and the later ones still go to the right request
+        let msg = match self.timeout {
+            Some(t) => tokio::time::timeout(t, reply)
+                .await
+                .map_err(|_| MsgError::Timeout(t))?,
+            None => reply.await,
+        }?;
+        #[cfg(feature = "metrics")]
+        crate::metrics::command(cmd, start.elapsed());
+        Ok(msg)
+    }
+
+    /// Send a command and check its `Reply-Text`.
+    pub async fn command(&self, cmd: &str) -> Result<Message> {
+        self.command_frame(format!("{}\n\n", one_line(cmd.trim())?))
+            .await
+    }
+
+    async fn command_frame(&self, b: String) -> Result<Message> {
+        let mut msg = self.request_frame(b).await?;
+        let reply = msg.get_header("Reply-Text");
+        if reply.starts_with("-ERR") {
+            return Err(MsgError::ErrResponse(reply).into());
+        }
+        Ok(msg)
+    }
+
+    pub async fn auth(&self, pwd: &str) -> Result<()> {
+        self.command(&format!("auth {}", pwd)).await?;
+        Ok(())
+    }
+
+    /// Subscribe to events, e.g. `["CUSTOM", "sofia::register"]`. FreeSWITCH
+    /// sends every event of the connection in the last `format` asked for.
+    pub async fn event(&self, format: FormatType, events: &[&str]) -> Result<()> {
+        self.command(&format!("event {} {}", format, events.join(" ")))
+            .await?;
+        *self.format.lock().unwrap_or_else(|e| e.into_inner()) = Some(format);
+        Ok(())
+    }
+
+    // outbound mode: the events of the session's own channel, in `format`
+    pub(crate) async fn myevents(&self, format: FormatType) -> Result<()> {
+        self.command(&format!("myevents {}", format)).await?;
+        *self.format.lock().unwrap_or_else(|e| e.into_inner()) = Some(format);
+        Ok(())
+    }
+
+    /// The format events come in, `None` before the first `event`.
+    pub fn event_format(&self) -> Option<FormatType> {
+        self.format
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    // subscribe more events for a helper, in the format already in use so
+    // the other subscribers of the connection see no change
+    pub(crate) async fn add_events(&self, events: &[&str]) -> Result<()> {
+        let format = self.event_format().unwrap_or(FormatType::Json);
+        self.event(format, events).await
+    }
+
+    /// Fire `event` into FreeSWITCH, e.g. a CUSTOM event with its
+    /// `Event-Subclass` in `ed` to signal other modules. Returns the
+    /// `Event-UUID` FreeSWITCH gave it.
+    pub async fn send_event(
+        &self,
+        event: Event,
+        ed: EventData,
+        body: Option<&str>,
+    ) -> Result<String> {
+        let mut msg = self
+            .command_frame(generate_sendevent(&event, &ed, body))
+            .await?;
+        let reply = msg.get_header("Reply-Text");
+        Ok(reply.trim_start_matches("+OK").trim().to_string())
+    }
+
+    /// Send `sendmsg` to the channel `uuid`, or to the channel of an outbound
+    /// session without one.
+    pub async fn sendmsg(
+        &self,
+        uuid: Option<&str>,
+        ed: EventData,
+        body: Option<&str>,
+    ) -> Result<Message> {
+        self.command_frame(generate_sendmsg(uuid, &ed, body)).await
+    }
 
-        self.out_tx.send(b).await?;
+    /// Run a blocking `api` command and return the response body.
+    pub async fn api(&self, cmd: &str) -> Result<String> {
+        let msg = self.request(format!("api {}", cmd)).await?;
+        let body = msg.body.unwrap_or_default();
+        if body.starts_with("-ERR") || body.starts_with("-USAGE") {
+            return Err(MsgError::ErrResponse(body.trim().to_string()).into());
+        }
+        Ok(body)
+    }
+
+    /// Run a `bgapi` command and return its `Job-UUID`.
+    pub async fn bgapi(&self, cmd: &str) -> Result<String> {
+        let mut msg = self.command(&format!("bgapi {}", cmd)).await?;
+        let mut job_uuid = msg.get_header("Job-UUID");
+        if job_uuid.is_empty() {
+            if let Some(ed) = &msg.event_data {
+                job_uuid = ed.get_header("Job-UUID".to_string());
+            }
+        }
+        Ok(job_uuid)
+    }
 
+    /// `bgapi` under a Job-UUID chosen by the caller, so the BACKGROUND_JOB
+    /// can be waited for before the command is sent.
+    pub async fn bgapi_job(&self, cmd: &str, job_uuid: &str) -> Result<()> {
+        let b = format!(
+            "bgapi {}\nJob-UUID: {}\n\n",
+            one_line(cmd.trim())?,
+            one_line(job_uuid)?
+        );
+        self.command_frame(b).await?;
         Ok(())
     }
 }
 
-pub async fn read(
+// a line break would end the frame and the rest be read as another command,
+// only sendmsg and sendevent build frames of several lines
+fn one_line(cmd: &str) -> Result<&str> {
+    if cmd.contains(['\n', '\r']) {
+        return Err(MsgError::InvalidArgument(cmd.to_string()).into());
+    }
+    Ok(cmd)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn read(
     closed: Arc<Mutex<bool>>,
-    close_tx: broadcast::Sender<bool>,
-    mut close_rx: broadcast::Receiver<bool>,
+    reason: Reason,
+    exiting: Arc<AtomicBool>,
     tx: broadcast::Sender<Arc<Mutex<Message>>>,
-    reader: BufReader<OwnedReadHalf>,
+    pending: Pending,
+    reader: TeeReader<OwnedReadHalf>,
+    recorder: Option<Recorder>,
+    health: Arc<watch::Sender<Health>>,
     mut exit: broadcast::Receiver<bool>,
 ) {
     let mut reader = reader;
-    loop {
-        let c = closed.lock().await;
-        if *c == true {
-            return;
+    let seen = |heartbeat: bool| {
+        health.send_modify(|h| {
+            h.last_seen = Some(Instant::now());
+            if heartbeat {
+                h.last_heartbeat = h.last_seen;
+            }
+        })
+    };
+    let mut stop = reason.subscribe();
+    // Some(false) from auth/request until auth is accepted
+    let mut authed = None;
+    // how a hang up of FreeSWITCH is taken
+    let hung_up = |authed: Option<bool>| {
+        if authed == Some(false) {
+            CloseReason::AuthFailed
+        } else if exiting.load(Ordering::SeqCst) {
+            CloseReason::Exit
+        } else {
+            CloseReason::RemoteExit
         }
+    };
 
+    let r = loop {
         tokio::select! {
             _ = exit.recv() => {
-                break;
+                break CloseReason::Shutdown;
             }
-            _ = close_rx.recv() => {
-                let mut c = closed.lock().await;
-                *c = true;
-                return;
+            _ = stopped(&mut stop) => {
+                return finish(&closed, &pending).await;
             }
             msg = Message::parse(&mut reader) => {
-                let msg = match msg {
+                if let Some(r) = &recorder {
+                    r.record(Direction::In, &reader.take());
+                }
+                let mut msg = match msg {
                     Ok(m) => m,
                     Err(e) => {
                         match e.downcast::<MsgError>() {
+                            // the frame was read whole, the next one can still be parsed
+                            Ok(e @ (MsgError::BodyParseFailed
+                            | MsgError::BodyTooLarge(_)
+                            | MsgError::InvalidUtf8)) => {
+                                seen(false);
+                                error!("Failed to parse message: {:?}", e);
+                                #[cfg(feature = "metrics")]
+                                crate::metrics::parse_failure(match e {
+                                    MsgError::BodyParseFailed => "body_parse_failed",
+                                    MsgError::BodyTooLarge(_) => "body_too_large",
+                                    _ => "invalid_utf8",
+                                });
+                                continue;
+                            }
+                            Ok(MsgError::ConnectionClosed) => {
+                                info!("close session read thread");
+                                break hung_up(authed);
+                            }
                             Ok(e) => {
                                 error!("Failed to parse message: {:?}", e);
+                                #[cfg(feature = "metrics")]
+                                crate::metrics::parse_failure("fatal");
                                 info!("close session read thread");
-                                match close_tx.send(true) {
-                                    Ok(_) => {
-                                        let mut c = closed.lock().await;
-                                        *c = true;
-                                    }
-                                    Err(e) => {
-                                        error!("send signal error {}", e)
-                                    }
-                                }
-                                return ;
+                                break CloseReason::Io(e.to_string());
                             }
                             Err(e) => {
                                 error!("Failed to parse message: {:?}", e);
+                                #[cfg(feature = "metrics")]
+                                crate::metrics::parse_failure("other");
                                 continue;
                             }
                         }
@@ -141,6 +588,27 @@ pub async fn read(
 
                 };
                 debug!("received msg: {:?}", msg);
+                seen(msg.event_data.as_ref().is_some_and(|ed| ed.event() == Event::Heartbeat));
+                #[cfg(feature = "metrics")]
+                crate::metrics::frame(&mut msg);
+                match msg.content_type() {
+                    Some(ContentType::AuthRequest) => authed = Some(false),
+                    Some(ContentType::CommandReply)
+                        if msg.get_header("Reply-Text").starts_with("+OK accepted") =>
+                    {
+                        authed = Some(true)
+                    }
+                    _ => {}
+                }
+                if msg.is_reply() {
+                    if let Some(Some(waiter)) = pending.lock().await.pop_front() {
+                        let _ = waiter.send(msg.clone());
+                    }
+                }
+                // FreeSWITCH hangs up after the notice, unless it lingers
+                // to send the last events of the call
+                let disconnect = msg.content_type() == Some(ContentType::TextDisconnectNotice)
+                    && msg.get_header("Content-Disposition") != "linger";
                 let msg = Arc::new(Mutex::new(msg));
                 match tx.send(msg) {
                     Ok(_) => {
@@ -148,61 +616,72 @@ pub async fn read(
                     }
                     Err(e) => {
                         error!("Failed to send message: {}", e);
-                        continue;
                     }
                 }
-
+                if disconnect {
+                    info!("received disconnect notice, close session read thread");
+                    break hung_up(authed);
+                }
             }
         }
-    }
+    };
+    set_reason(&reason, r);
+    finish(&closed, &pending).await;
 }
 
-pub async fn write(
+// waiters get ConnectionClosed once their sender is dropped
+async fn finish(closed: &Arc<Mutex<bool>>, pending: &Pending) {
+    *closed.lock().await = true;
+    pending.lock().await.clear();
+}
+
+async fn write(
     closed: Arc<Mutex<bool>>,
-    close_tx: broadcast::Sender<bool>,
-    mut close_rx: broadcast::Receiver<bool>,
+    reason: Reason,
     mut rx: mpsc::Receiver<String>,
     writer: BufWriter<OwnedWriteHalf>,
+    recorder: Option<Recorder>,
     mut exit: broadcast::Receiver<bool>,
 ) {
     let mut writer = writer;
+    let mut stop = reason.subscribe();
     loop {
         tokio::select! {
-            Some(msg) = rx.recv() => {
-                let b = format!("{}\n\n", msg.trim());
-                match writer.write_all(b.as_bytes()).await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("write msg to freeswitch error: {}", e);
-                        continue;
-                    }
+            msg = rx.recv() => {
+                let msg = match msg {
+                    Some(m) => m,
+                    None => break,
+                };
+                // sendevent and sendmsg frames carry a body, they go out as they are
+                let b = if msg.contains("\n\n") {
+                    msg
+                } else {
+                    format!("{}\n\n", msg.trim())
+                };
+                if let Some(r) = &recorder {
+                    r.record(Direction::Out, b.as_bytes());
                 }
-                match writer.flush().await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("flush msg to freeswitch error: {}", e);
-                        match close_tx.send(true) {
-                            Ok(_) => {
-                                let mut c = closed.lock().await;
-                                *c = true;
-                            }
-                            Err(e) => {
-                                error!("send signal error {}", e)
-                            }
-                        }
-                        info!("close session write thread");
-                        continue;
-                    }
+                let written = async {
+                    writer.write_all(b.as_bytes()).await?;
+                    writer.flush().await
+                };
+                if let Err(e) = written.await {
+                    error!("write msg to freeswitch error: {}", e);
+                    info!("close session write thread");
+                    set_reason(&reason, CloseReason::Io(e.to_string()));
+                    break;
                 }
             }
-             _ = close_rx.recv() => {
-                let mut c = closed.lock().await;
-                *c = true;
-                return;
+            _ = stopped(&mut stop) => {
+                break;
             }
             _ = exit.recv() => {
+                set_reason(&reason, CloseReason::Shutdown);
                 break;
             }
         }
     }
+    // let FreeSWITCH see the end of the stream
+    let _ = writer.shutdown().await;
+    *closed.lock().await = true;
 }
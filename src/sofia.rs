@@ -0,0 +1,454 @@
+use crate::message::MsgError;
+use crate::session::Session;
+use anyhow::Result;
+use quick_xml::events::Event as XmlEvent;
+use quick_xml::Reader;
+use std::{collections::HashMap, fmt::Display};
+
+// fields of a status record, keyed by lowercase alphanumeric name so that
+// `CallsIN` (sofia status) and `calls-in` (sofia xmlstatus) look the same
+pub type Fields = HashMap<String, String>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryType {
+    Profile,
+    Gateway,
+    Alias,
+    Other(String),
+}
+
+impl From<&str> for EntryType {
+    fn from(value: &str) -> Self {
+        match value {
+            "profile" => EntryType::Profile,
+            "gateway" => EntryType::Gateway,
+            "alias" => EntryType::Alias,
+            _ => EntryType::Other(value.to_string()),
+        }
+    }
+}
+
+/// One row of `sofia status` / `sofia xmlstatus`.
+#[derive(Debug, Clone)]
+pub struct StatusEntry {
+    pub name: String,
+    pub kind: EntryType,
+    pub data: String,
+    pub state: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GatewayState {
+    Unreged,
+    Trying,
+    Register,
+    Reged,
+    Unregister,
+    Failed,
+    FailWait,
+    Expired,
+    Noreg,
+    Down,
+    Timeout,
+    Unknown(String),
+}
+
+impl GatewayState {
+    // a gateway that can carry calls
+    pub fn is_up(&self) -> bool {
+        matches!(self, GatewayState::Reged | GatewayState::Noreg)
+    }
+}
+
+impl From<&str> for GatewayState {
+    fn from(value: &str) -> Self {
+        match value {
+            "UNREGED" => GatewayState::Unreged,
+            "TRYING" => GatewayState::Trying,
+            "REGISTER" => GatewayState::Register,
+            "REGED" => GatewayState::Reged,
+            "UNREGISTER" => GatewayState::Unregister,
+            "FAILED" => GatewayState::Failed,
+            "FAIL_WAIT" => GatewayState::FailWait,
+            "EXPIRED" => GatewayState::Expired,
+            "NOREG" => GatewayState::Noreg,
+            "DOWN" => GatewayState::Down,
+            "TIMEOUT" => GatewayState::Timeout,
+            _ => GatewayState::Unknown(value.to_string()),
+        }
+    }
+}
+
+impl Display for GatewayState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            GatewayState::Unreged => "UNREGED",
+            GatewayState::Trying => "TRYING",
+            GatewayState::Register => "REGISTER",
+            GatewayState::Reged => "REGED",
+            GatewayState::Unregister => "UNREGISTER",
+            GatewayState::Failed => "FAILED",
+            GatewayState::FailWait => "FAIL_WAIT",
+            GatewayState::Expired => "EXPIRED",
+            GatewayState::Noreg => "NOREG",
+            GatewayState::Down => "DOWN",
+            GatewayState::Timeout => "TIMEOUT",
+            GatewayState::Unknown(s) => s,
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Options ping status of a gateway.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PingStatus {
+    Up,
+    Down,
+    Unknown(String),
+}
+
+impl From<&str> for PingStatus {
+    fn from(value: &str) -> Self {
+        match value {
+            "UP" => PingStatus::Up,
+            "DOWN" => PingStatus::Down,
+            _ => PingStatus::Unknown(value.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Gateway {
+    pub name: String,
+    pub profile: String,
+    pub scheme: String,
+    pub realm: String,
+    pub username: String,
+    pub from: String,
+    pub contact: String,
+    pub proxy: String,
+    pub context: String,
+    pub expires: u64,
+    pub freq: u64,
+    pub ping_freq: u64,
+    pub ping_time: f64,
+    pub state: GatewayState,
+    pub status: PingStatus,
+    pub calls_in: u64,
+    pub calls_out: u64,
+    pub failed_calls_in: u64,
+    pub failed_calls_out: u64,
+}
+
+impl From<&Fields> for Gateway {
+    fn from(f: &Fields) -> Self {
+        Gateway {
+            name: text(f, "name"),
+            profile: text(f, "profile"),
+            scheme: text(f, "scheme"),
+            realm: text(f, "realm"),
+            username: text(f, "username"),
+            from: text(f, "from"),
+            contact: text(f, "contact"),
+            proxy: text(f, "proxy"),
+            context: text(f, "context"),
+            expires: number(f, "expires"),
+            freq: number(f, "freq"),
+            ping_freq: number(f, "pingfreq"),
+            ping_time: text(f, "pingtime").parse().unwrap_or_default(),
+            state: GatewayState::from(text(f, "state").as_str()),
+            status: PingStatus::from(text(f, "status").as_str()),
+            calls_in: number(f, "callsin"),
+            calls_out: number(f, "callsout"),
+            failed_calls_in: number(f, "failedcallsin"),
+            failed_calls_out: number(f, "failedcallsout"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProfileStatus {
+    pub name: String,
+    pub domain_name: String,
+    pub dialplan: String,
+    pub context: String,
+    pub sip_ip: String,
+    pub rtp_ip: String,
+    pub url: String,
+    pub bind_url: String,
+    pub calls_in: u64,
+    pub calls_out: u64,
+    pub failed_calls_in: u64,
+    pub failed_calls_out: u64,
+    pub registrations: u64,
+    // every field as printed by FreeSWITCH
+    pub fields: Fields,
+}
+
+impl From<&Fields> for ProfileStatus {
+    fn from(f: &Fields) -> Self {
+        ProfileStatus {
+            name: text(f, "name"),
+            domain_name: text(f, "domainname"),
+            dialplan: text(f, "dialplan"),
+            context: text(f, "context"),
+            sip_ip: text(f, "sipip"),
+            rtp_ip: text(f, "rtpip"),
+            url: text(f, "url"),
+            bind_url: text(f, "bindurl"),
+            calls_in: number(f, "callsin"),
+            calls_out: number(f, "callsout"),
+            failed_calls_in: number(f, "failedcallsin"),
+            failed_calls_out: number(f, "failedcallsout"),
+            registrations: number(f, "registrations"),
+            fields: f.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Registration {
+    pub call_id: String,
+    pub user: String,
+    pub contact: String,
+    pub agent: String,
+    pub status: String,
+    pub ping_status: String,
+    pub ping_time: f64,
+    pub host: String,
+    pub network_ip: String,
+    pub network_port: String,
+    pub sip_auth_user: String,
+    pub sip_auth_realm: String,
+}
+
+impl From<&Fields> for Registration {
+    fn from(f: &Fields) -> Self {
+        Registration {
+            call_id: text(f, "callid"),
+            user: text(f, "user"),
+            contact: text(f, "contact"),
+            agent: text(f, "agent"),
+            status: text(f, "status"),
+            ping_status: text(f, "pingstatus"),
+            ping_time: text(f, "pingtime").parse().unwrap_or_default(),
+            host: text(f, "host"),
+            network_ip: text(f, "networkip"),
+            network_port: text(f, "networkport"),
+            sip_auth_user: text(f, "sipauthuser"),
+            sip_auth_realm: text(f, "sipauthrealm"),
+        }
+    }
+}
+
+/// Typed access to the `sofia` api of mod_sofia.
+#[derive(Debug, Clone)]
+pub struct Sofia {
+    session: Session,
+}
+
+impl Sofia {
+    pub fn new(session: Session) -> Self {
+        Sofia { session }
+    }
+
+    /// `sofia status`
+    pub async fn status(&self) -> Result<Vec<StatusEntry>> {
+        let body = self.session.api("sofia status").await?;
+        Ok(parse_status_table(&body))
+    }
+
+    /// `sofia status profile <p>`
+    pub async fn profile(&self, profile: &str) -> Result<ProfileStatus> {
+        let body = self
+            .session
+            .api(&format!("sofia status profile {}", profile))
+            .await?;
+        check_found(&body)?;
+        Ok(ProfileStatus::from(&parse_key_values(&body)))
+    }
+
+    /// `sofia status gateway <g>`
+    pub async fn gateway(&self, gateway: &str) -> Result<Gateway> {
+        let body = self
+            .session
+            .api(&format!("sofia status gateway {}", gateway))
+            .await?;
+        check_found(&body)?;
+        Ok(Gateway::from(&parse_key_values(&body)))
+    }
+
+    /// `sofia xmlstatus`
+    pub async fn xmlstatus(&self) -> Result<Vec<StatusEntry>> {
+        let body = self.session.api("sofia xmlstatus").await?;
+        let entries = parse_xml_records(&body, &["profile", "gateway", "alias"])?
+            .into_iter()
+            .map(|(tag, f)| StatusEntry {
+                name: text(&f, "name"),
+                kind: EntryType::from(tag.as_str()),
+                data: text(&f, "data"),
+                state: text(&f, "state"),
+            })
+            .collect();
+        Ok(entries)
+    }
+
+    /// `sofia xmlstatus gateway`, every gateway of every profile
+    pub async fn gateways(&self) -> Result<Vec<Gateway>> {
+        let body = self.session.api("sofia xmlstatus gateway").await?;
+        let gateways = parse_xml_records(&body, &["gateway"])?
+            .iter()
+            .map(|(_, f)| Gateway::from(f))
+            .collect();
+        Ok(gateways)
+    }
+
+    /// `sofia xmlstatus profile <p> reg`
+    pub async fn registrations(&self, profile: &str) -> Result<Vec<Registration>> {
+        let body = self
+            .session
+            .api(&format!("sofia xmlstatus profile {} reg", profile))
+            .await?;
+        check_found(&body)?;
+        let regs = parse_xml_records(&body, &["registration"])?
+            .iter()
+            .map(|(_, f)| Registration::from(f))
+            .collect();
+        Ok(regs)
+    }
+
+    pub async fn start(&self, profile: &str) -> Result<String> {
+        self.profile_action(profile, "start").await
+    }
+
+    pub async fn stop(&self, profile: &str) -> Result<String> {
+        self.profile_action(profile, "stop").await
+    }
+
+    pub async fn restart(&self, profile: &str) -> Result<String> {
+        self.profile_action(profile, "restart").await
+    }
+
+    pub async fn rescan(&self, profile: &str) -> Result<String> {
+        self.profile_action(profile, "rescan").await
+    }
+
+    /// `sofia profile <p> killgw <g>`
+    pub async fn kill_gateway(&self, profile: &str, gateway: &str) -> Result<String> {
+        self.profile_action(profile, &format!("killgw {}", gateway))
+            .await
+    }
+
+    async fn profile_action(&self, profile: &str, action: &str) -> Result<String> {
+        let body = self
+            .session
+            .api(&format!("sofia profile {} {}", profile, action))
+            .await?;
+        check_found(&body)?;
+        Ok(body.trim().to_string())
+    }
+}
+
+// mod_sofia answers some lookups with a plain text error instead of -ERR
+fn check_found(body: &str) -> Result<()> {
+    let b = body.trim();
+    if b.starts_with("Invalid Profile") || b.starts_with("Invalid Gateway") {
+        return Err(MsgError::ErrResponse(b.to_string()).into());
+    }
+    Ok(())
+}
+
+fn normalize(k: &str) -> String {
+    k.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn text(f: &Fields, k: &str) -> String {
+    f.get(k).cloned().unwrap_or_default()
+}
+
+fn number(f: &Fields, k: &str) -> u64 {
+    f.get(k).and_then(|v| v.parse().ok()).unwrap_or_default()
+}
+
+fn is_separator(line: &str) -> bool {
+    line.trim_start().starts_with('=')
+}
+
+/// Parse the table printed by `sofia status`.
+pub fn parse_status_table(body: &str) -> Vec<StatusEntry> {
+    body.lines()
+        .filter(|l| !is_separator(l))
+        .filter_map(|l| {
+            let cols: Vec<&str> = l.split('\t').map(|c| c.trim()).collect();
+            if cols.len() < 4 || cols[0] == "Name" {
+                return None;
+            }
+            Some(StatusEntry {
+                name: cols[0].to_string(),
+                kind: EntryType::from(cols[1]),
+                data: cols[2].to_string(),
+                state: cols[3].to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Parse the `Key\tValue` lines printed by `sofia status profile|gateway`.
+pub fn parse_key_values(body: &str) -> Fields {
+    body.lines()
+        .filter(|l| !is_separator(l))
+        .filter_map(|l| l.split_once('\t'))
+        .map(|(k, v)| (normalize(k), v.trim().to_string()))
+        .collect()
+}
+
+/// Collect the child elements of every element named in `tags`, at any depth.
+pub fn parse_xml_records(xml: &str, tags: &[&str]) -> Result<Vec<(String, Fields)>> {
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+
+    let mut records = Vec::new();
+    let mut current: Option<(String, Fields)> = None;
+    let mut depth = 0;
+    let mut field: Option<String> = None;
+
+    loop {
+        match reader.read_event()? {
+            XmlEvent::Start(e) => {
+                let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
+                if current.is_some() {
+                    depth += 1;
+                    field = if depth == 1 { Some(name) } else { None };
+                } else if tags.contains(&name.as_str()) {
+                    current = Some((name, Fields::new()));
+                    depth = 0;
+                }
+            }
+            XmlEvent::Text(t) => {
+                if let (Some((_, f)), Some(k)) = (&mut current, &field) {
+                    f.insert(normalize(k), t.unescape()?.to_string());
+                }
+            }
+            XmlEvent::CData(t) => {
+                if let (Some((_, f)), Some(k)) = (&mut current, &field) {
+                    f.insert(normalize(k), String::from_utf8_lossy(&t).to_string());
+                }
+            }
+            XmlEvent::End(_) if current.is_some() => {
+                if depth == 0 {
+                    records.extend(current.take());
+                } else {
+                    depth -= 1;
+                    field = None;
+                }
+            }
+            XmlEvent::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(records)
+}
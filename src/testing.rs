@@ -0,0 +1,603 @@
+use crate::channel::ChannelHandle;
+use crate::client::Client;
+use crate::event::{EventData, EventHandler};
+use crate::message::{generate_plain_event, FormatType};
+use crate::record::Replayer;
+use crate::session::Session;
+use anyhow::Result;
+use quick_xml::escape::escape;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{tcp::OwnedReadHalf, TcpListener, TcpStream},
+    sync::{broadcast, mpsc, Mutex, Notify},
+};
+use tracing::debug;
+use urlencoding::{decode, encode};
+
+const DISCONNECT_NOTICE: &str =
+    "Disconnected, goodbye.\nSee you at ClueCon! http://www.cluecon.com/\n";
+
+enum Frame {
+    Data(Vec<u8>),
+    Close,
+}
+
+#[derive(Default)]
+struct Shared {
+    password: String,
+    // command or its first word -> api reply
+    apis: Mutex<HashMap<String, String>>,
+    // command or its first word -> wait before the reply
+    delays: Mutex<HashMap<String, Duration>>,
+    commands: Mutex<Vec<String>>,
+    // frames to write on each connection
+    conns: Mutex<Vec<mpsc::UnboundedSender<Frame>>>,
+    connected: Notify,
+    // frames are dropped instead of written
+    muted: AtomicBool,
+    // app name -> headers of its CHANNEL_EXECUTE_COMPLETE
+    apps: Mutex<HashMap<String, EventData>>,
+    // app name -> events fired before its CHANNEL_EXECUTE_COMPLETE
+    app_events: Mutex<HashMap<String, Vec<EventData>>>,
+}
+
+/// A FreeSWITCH stand-in speaking ESL on a local port, for tests.
+///
+/// `api` and `bgapi` answer the replies scripted with `api`, other
+/// commands answer `+OK`. Every command received is kept for `commands`.
+#[derive(Clone)]
+pub struct MockFreeswitch {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+}
+
+impl MockFreeswitch {
+    /// Listen on a free port of 127.0.0.1 for inbound connections.
+    pub async fn start(password: &str) -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            password: password.to_string(),
+            ..Default::default()
+        });
+
+        let s = shared.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, peer)) = listener.accept().await {
+                debug!("mock freeswitch accepted {}", peer);
+                tokio::spawn(serve(s.clone(), stream, None));
+            }
+        });
+
+        Ok(MockFreeswitch { addr, shared })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// An authenticated session with this mock. The shutdown sender has to
+    /// outlive the session.
+    pub async fn session(&self) -> Result<(Session, broadcast::Sender<bool>)> {
+        let (tx, _) = broadcast::channel(100);
+        let (shutdown, signal) = broadcast::channel(1);
+        let client = Client::new(self.addr.to_string(), self.shared.password.clone());
+        Ok((client.connect(tx, signal).await?, shutdown))
+    }
+
+    /// The channel `uuid` on a `session` subscribed to every event in JSON.
+    pub async fn channel(&self, uuid: &str) -> Result<(ChannelHandle, broadcast::Sender<bool>)> {
+        let (session, shutdown) = self.session().await?;
+        session.event(FormatType::Json, &["ALL"]).await?;
+        Ok((ChannelHandle::new(session, uuid), shutdown))
+    }
+
+    /// Answer `api <cmd>` and `bgapi <cmd>` with `reply`. `cmd` is either the
+    /// whole command or its first word.
+    pub async fn api(&self, cmd: &str, reply: &str) {
+        self.shared
+            .apis
+            .lock()
+            .await
+            .insert(cmd.to_string(), reply.to_string());
+    }
+
+    /// Fire CHANNEL_EXECUTE_COMPLETE with `headers` when `app` is executed
+    /// with sendmsg, if subscribed to. Other apps never complete.
+    pub async fn app(&self, app: &str, headers: EventData) {
+        self.shared
+            .apps
+            .lock()
+            .await
+            .insert(app.to_string(), headers);
+    }
+
+    /// Fire `events`, for the channel unless they carry a Unique-ID and if
+    /// subscribed to, before the
+    /// CHANNEL_EXECUTE_COMPLETE of an `app` scripted with `app`.
+    pub async fn app_events(&self, app: &str, events: Vec<EventData>) {
+        self.shared
+            .app_events
+            .lock()
+            .await
+            .insert(app.to_string(), events);
+    }
+
+    /// Answer `api <cmd>` only after `delay`, the commands after it on the
+    /// connection wait as well.
+    pub async fn delay(&self, cmd: &str, delay: Duration) {
+        self.shared
+            .delays
+            .lock()
+            .await
+            .insert(cmd.to_string(), delay);
+    }
+
+    /// The commands received so far, on every connection.
+    pub async fn commands(&self) -> Vec<String> {
+        self.shared.commands.lock().await.clone()
+    }
+
+    /// Number of open connections.
+    pub async fn connections(&self) -> usize {
+        let mut conns = self.shared.conns.lock().await;
+        conns.retain(|c| !c.is_closed());
+        conns.len()
+    }
+
+    /// Wait until at least `n` connections are open.
+    pub async fn connected(&self, n: usize) {
+        loop {
+            let notified = self.shared.connected.notified();
+            if self.connections().await >= n {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Send an event to every connection, whatever they subscribed to.
+    pub async fn push_event(&self, ed: &EventData, format: FormatType) {
+        self.push_raw(&event_frame(ed, &format)).await;
+    }
+
+    /// Send raw bytes to every connection.
+    pub async fn push_raw(&self, data: &[u8]) {
+        for c in self.shared.conns.lock().await.iter() {
+            let _ = c.send(Frame::Data(data.to_vec()));
+        }
+    }
+
+    /// Send `text/disconnect-notice` and close every connection.
+    pub async fn disconnect(&self) {
+        self.push_raw(&disconnect_frame()).await;
+        self.close().await;
+    }
+
+    /// Close every connection without notice.
+    pub async fn close(&self) {
+        for c in self.shared.conns.lock().await.drain(..) {
+            let _ = c.send(Frame::Close);
+        }
+    }
+
+    /// Stop writing anything on every connection, like a connection that
+    /// went half-open, or start again.
+    pub fn mute(&self, muted: bool) {
+        self.shared.muted.store(muted, Ordering::SeqCst);
+    }
+
+    /// Push the frames of a recording read from FreeSWITCH to every
+    /// connection. Replies are left out, the mock answers commands itself.
+    pub async fn replay(&self, replayer: &Replayer) {
+        let mut last = None;
+        for frame in replayer.incoming() {
+            if matches!(
+                frame.content_type().as_str(),
+                "auth/request" | "command/reply" | "api/response"
+            ) {
+                continue;
+            }
+            replayer.pace(&mut last, frame).await;
+            self.push_raw(&frame.bytes().unwrap_or_default()).await;
+        }
+    }
+
+    /// Outbound mode: connect to `addr` (a `Server`) as a call would, the
+    /// `connect` command is answered with `channel_data`.
+    pub async fn outbound(&self, addr: &str, channel_data: EventData) -> Result<()> {
+        let stream = TcpStream::connect(addr).await?;
+        tokio::spawn(serve(self.shared.clone(), stream, Some(channel_data)));
+        Ok(())
+    }
+}
+
+async fn serve(shared: Arc<Shared>, stream: TcpStream, channel_data: Option<EventData>) {
+    let (r, mut w) = stream.into_split();
+    let (tx, mut rx) = mpsc::unbounded_channel::<Frame>();
+    shared.conns.lock().await.push(tx.clone());
+    shared.connected.notify_waiters();
+
+    let s = shared.clone();
+    tokio::spawn(async move {
+        while let Some(Frame::Data(b)) = rx.recv().await {
+            if s.muted.load(Ordering::SeqCst) {
+                continue;
+            }
+            if w.write_all(&b).await.is_err() {
+                break;
+            }
+        }
+        // dropping the write half shuts the socket down
+        rx.close();
+    });
+
+    let outbound = channel_data.is_some();
+    if !outbound {
+        let _ = tx.send(Frame::Data(b"Content-Type: auth/request\n\n".to_vec()));
+    }
+    let mut conn = MockConn {
+        shared,
+        tx,
+        events: None,
+        authed: outbound,
+        channel_data: channel_data.unwrap_or_default(),
+    };
+
+    let mut reader = BufReader::new(r);
+    while let Some(cmd) = read_command(&mut reader).await {
+        if conn.tx.is_closed() {
+            break;
+        }
+        conn.shared.commands.lock().await.push(cmd.clone());
+        if !conn.handle(&cmd).await {
+            break;
+        }
+    }
+    // the client hung up, or the connection was closed
+    let _ = conn.tx.send(Frame::Close);
+}
+
+// a command ends with an empty line, sendmsg may carry a body
+async fn read_command(r: &mut BufReader<OwnedReadHalf>) -> Option<String> {
+    let mut cmd = String::new();
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        match r.read_line(&mut line).await {
+            Ok(0) | Err(_) => return None,
+            Ok(_) => {}
+        }
+        if line.trim().is_empty() {
+            if cmd.is_empty() {
+                continue;
+            }
+            break;
+        }
+        if let Some(l) = line.trim().strip_prefix("Content-Length:") {
+            content_length = l.trim().parse().unwrap_or(0);
+        }
+        cmd.push_str(&line);
+    }
+    if content_length > 0 {
+        let mut body = vec![0u8; content_length];
+        r.read_exact(&mut body).await.ok()?;
+        cmd.push('\n');
+        cmd.push_str(&String::from_utf8_lossy(&body));
+    }
+    Some(cmd.trim().to_string())
+}
+
+struct MockConn {
+    shared: Arc<Shared>,
+    tx: mpsc::UnboundedSender<Frame>,
+    // format and names of the subscribed events
+    events: Option<(FormatType, Vec<String>)>,
+    authed: bool,
+    channel_data: EventData,
+}
+
+impl MockConn {
+    fn send(&self, data: Vec<u8>) {
+        let _ = self.tx.send(Frame::Data(data));
+    }
+
+    fn reply(&self, text: &str) {
+        self.send(format!("Content-Type: command/reply\nReply-Text: {}\n\n", text).into_bytes());
+    }
+
+    fn close(&self) {
+        self.send(disconnect_frame());
+        let _ = self.tx.send(Frame::Close);
+    }
+
+    // false once the connection is done
+    async fn handle(&mut self, cmd: &str) -> bool {
+        let (name, arg) = match cmd.split_once(char::is_whitespace) {
+            Some((n, a)) => (n, a.trim()),
+            None => (cmd, ""),
+        };
+
+        if !self.authed {
+            if name == "auth" && arg == self.shared.password {
+                self.authed = true;
+                self.reply("+OK accepted");
+                return true;
+            }
+            self.reply("-ERR invalid");
+            self.close();
+            return false;
+        }
+
+        match name {
+            "api" => {
+                let delay = {
+                    let delays = self.shared.delays.lock().await;
+                    let first = arg.split_whitespace().next().unwrap_or_default();
+                    delays.get(arg).or_else(|| delays.get(first)).copied()
+                };
+                if let Some(d) = delay {
+                    tokio::time::sleep(d).await;
+                }
+                let body = self.api_reply(arg).await;
+                self.send(
+                    format!(
+                        "Content-Type: api/response\nContent-Length: {}\n\n{}",
+                        body.len(),
+                        body
+                    )
+                    .into_bytes(),
+                );
+            }
+            "bgapi" => {
+                // the command may bring its own Job-UUID header
+                let (arg, job_uuid) = match arg.split_once('\n') {
+                    Some((a, h)) => match h.trim().strip_prefix("Job-UUID:") {
+                        Some(u) => (a.trim(), u.trim().to_string()),
+                        None => (a.trim(), uuid::Uuid::new_v4().to_string()),
+                    },
+                    None => (arg, uuid::Uuid::new_v4().to_string()),
+                };
+                let body = self.api_reply(arg).await;
+                self.send(
+                    format!(
+                        "Content-Type: command/reply\nReply-Text: +OK Job-UUID: {}\nJob-UUID: {}\n\n",
+                        job_uuid, job_uuid
+                    )
+                    .into_bytes(),
+                );
+                self.background_job(&job_uuid, arg, &body).await;
+            }
+            "event" => {
+                let mut words = arg.split_whitespace();
+                let format = words
+                    .next()
+                    .and_then(|f| f.parse::<FormatType>().ok())
+                    .unwrap_or(FormatType::Plain);
+                let reply = format!("+OK event listener enabled {}", format);
+                let mut names = match self.events.take() {
+                    Some((_, names)) => names,
+                    None => vec![],
+                };
+                names.extend(words.map(|w| w.to_string()));
+                self.events = Some((format, names));
+                self.reply(&reply);
+            }
+            "myevents" => {
+                let format = arg.parse::<FormatType>().unwrap_or(FormatType::Plain);
+                self.events = Some((format, vec!["ALL".to_string()]));
+                self.reply("+OK Events Enabled");
+            }
+            "noevents" => {
+                self.events = None;
+                self.reply("+OK no longer listening for events");
+            }
+            "filter" => match arg.split_once(' ') {
+                Some((h, v)) => self.reply(&format!("+OK filter added. [{}]=[{}]", h, v.trim())),
+                None => self.reply("-ERR invalid syntax"),
+            },
+            "connect" => {
+                let mut reply = String::from("Content-Type: command/reply\nReply-Text: +OK\n");
+                for k in self.channel_data.keys() {
+                    let v = self.channel_data.get_header(k.to_string());
+                    reply.push_str(&format!("{}: {}\n", k, encode(&v)));
+                }
+                reply.push('\n');
+                self.send(reply.into_bytes());
+            }
+            "sendevent" => {
+                let event_uuid = uuid::Uuid::new_v4().to_string();
+                self.reply(&format!("+OK {}", event_uuid));
+                self.fire(arg, &event_uuid);
+            }
+            "sendmsg" => {
+                self.reply("+OK");
+                self.execute_complete(arg).await;
+            }
+            "exit" => {
+                self.reply("+OK bye");
+                self.close();
+                return false;
+            }
+            _ => self.reply("+OK"),
+        }
+        true
+    }
+
+    async fn api_reply(&self, cmd: &str) -> String {
+        let apis = self.shared.apis.lock().await;
+        let first = cmd.split_whitespace().next().unwrap_or_default();
+        match apis.get(cmd).or_else(|| apis.get(first)) {
+            Some(r) => r.clone(),
+            None => format!("-ERR {} Command not found!\n", first),
+        }
+    }
+
+    // the format of the event, if it was subscribed to
+    fn subscribed(&self, name: &str, subclass: &str) -> Option<&FormatType> {
+        match &self.events {
+            Some((f, names))
+                if names
+                    .iter()
+                    .any(|n| n == "ALL" || n == name || n == subclass) =>
+            {
+                Some(f)
+            }
+            _ => None,
+        }
+    }
+
+    // an event of `sendevent` goes back to the connection that sent it, if it
+    // was subscribed to
+    fn fire(&self, arg: &str, event_uuid: &str) {
+        let (head, body) = arg.split_once("\n\n").unwrap_or((arg, ""));
+        let mut lines = head.lines();
+        let name = lines.next().unwrap_or_default().trim();
+        let mut ed = EventData::new();
+        ed.insert("Event-Name".to_string(), Value::String(name.to_string()));
+        for (k, v) in lines.filter_map(|l| l.split_once(':')) {
+            let v = decode(v.trim())
+                .map(|v| v.to_string())
+                .unwrap_or(v.trim().to_string());
+            ed.insert(k.trim().to_string(), Value::String(v));
+        }
+        ed.remove("Content-Length");
+        ed.insert(
+            "Event-UUID".to_string(),
+            Value::String(event_uuid.to_string()),
+        );
+        if !body.is_empty() {
+            ed.insert("_body".to_string(), Value::String(body.to_string()));
+        }
+        let subclass = ed.get_header("Event-Subclass".to_string());
+        if let Some(format) = self.subscribed(name, &subclass) {
+            self.send(event_frame(&ed, format));
+        }
+    }
+
+    // CHANNEL_EXECUTE_COMPLETE of an app scripted with `app`
+    async fn execute_complete(&self, arg: &str) {
+        let head = arg.split("\n\n").next().unwrap_or_default();
+        // `sendmsg` without a uuid is for the channel of an outbound session
+        let uuid = match head.lines().next() {
+            Some(l) if !l.contains(':') => l.trim().to_string(),
+            _ => self.channel_data.get_header("Unique-ID".to_string()),
+        };
+        let msg: HashMap<&str, &str> = head
+            .lines()
+            .filter_map(|l| l.split_once(':'))
+            .map(|(k, v)| (k.trim(), v.trim()))
+            .collect();
+        let app = msg.get("execute-app-name").copied().unwrap_or_default();
+        let headers = match self.shared.apps.lock().await.get(app) {
+            Some(h) => h.clone(),
+            None => return,
+        };
+        let events = self.shared.app_events.lock().await.get(app).cloned();
+        for mut ed in events.unwrap_or_default() {
+            let name = ed.get_header("Event-Name".to_string());
+            if let Some(format) = self.subscribed(&name, "") {
+                ed.entry("Unique-ID".to_string())
+                    .or_insert_with(|| Value::String(uuid.clone()));
+                self.send(event_frame(&ed, format));
+            }
+        }
+        let format = match self.subscribed("CHANNEL_EXECUTE_COMPLETE", "") {
+            Some(f) => f,
+            None => return,
+        };
+        let mut ed = EventData::new();
+        for (k, v) in [
+            ("Event-Name", "CHANNEL_EXECUTE_COMPLETE"),
+            ("Unique-ID", uuid.as_str()),
+            ("Application", app),
+            (
+                "Application-Data",
+                msg.get("execute-app-arg").copied().unwrap_or_default(),
+            ),
+            (
+                "Application-UUID",
+                msg.get("Event-UUID").copied().unwrap_or_default(),
+            ),
+        ] {
+            ed.insert(k.to_string(), Value::String(v.to_string()));
+        }
+        ed.extend(headers);
+        self.send(event_frame(&ed, format));
+    }
+
+    // BACKGROUND_JOB, if it was subscribed to
+    async fn background_job(&self, job_uuid: &str, cmd: &str, body: &str) {
+        let format = match self.subscribed("BACKGROUND_JOB", "") {
+            Some(f) => f,
+            None => return,
+        };
+        let (command, arg) = cmd.split_once(' ').unwrap_or((cmd, ""));
+        let mut ed = EventData::new();
+        for (k, v) in [
+            ("Event-Name", "BACKGROUND_JOB"),
+            ("Job-UUID", job_uuid),
+            ("Job-Command", command),
+            ("Job-Command-Arg", arg),
+            ("_body", body),
+        ] {
+            ed.insert(k.to_string(), Value::String(v.to_string()));
+        }
+        self.send(event_frame(&ed, format));
+    }
+}
+
+fn disconnect_frame() -> Vec<u8> {
+    format!(
+        "Content-Type: text/disconnect-notice\nContent-Length: {}\n\n{}",
+        DISCONNECT_NOTICE.len(),
+        DISCONNECT_NOTICE
+    )
+    .into_bytes()
+}
+
+// an event as FreeSWITCH writes it in each format
+fn event_frame(ed: &EventData, format: &FormatType) -> Vec<u8> {
+    let body = ed.get_header("_body".to_string());
+    let headers = ed.iter().filter(|(k, _)| *k != "_body").map(|(k, v)| {
+        let v = match v {
+            Value::String(s) => s.clone(),
+            v => v.to_string(),
+        };
+        (k, v)
+    });
+
+    let (content_type, data) = match format {
+        FormatType::Json => ("text/event-json", Value::Object(ed.clone()).to_string()),
+        FormatType::Plain => ("text/event-plain", generate_plain_event(ed)),
+        FormatType::Xml => {
+            let mut data = String::from("<event>\n  <headers>\n");
+            for (k, v) in headers {
+                data.push_str(&format!("    <{}>{}</{}>\n", k, escape(&v), k));
+            }
+            data.push_str("  </headers>\n");
+            if !body.is_empty() {
+                data.push_str(&format!("  <body>{}</body>\n", escape(&body)));
+            }
+            data.push_str("</event>");
+            ("text/event-xml", data)
+        }
+    };
+    format!(
+        "Content-Length: {}\nContent-Type: {}\n\n{}",
+        data.len(),
+        content_type,
+        data
+    )
+    .into_bytes()
+}
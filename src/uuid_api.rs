@@ -0,0 +1,192 @@
+use crate::channel::{api_arg, ChannelHandle};
+use crate::event::EventData;
+use crate::media::Leg;
+use crate::message::MsgError;
+use anyhow::Result;
+use std::fmt::Display;
+use std::time::Duration;
+
+/// Direction of `uuid_audio` adjustments.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioDirection {
+    Read,
+    Write,
+}
+
+impl Display for AudioDirection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AudioDirection::Read => write!(f, "read"),
+            AudioDirection::Write => write!(f, "write"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioAdjust {
+    Mute(bool),
+    // -4 .. 4
+    Level(i32),
+}
+
+impl Display for AudioAdjust {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AudioAdjust::Mute(m) => write!(f, "mute {}", if *m { 1 } else { 0 }),
+            AudioAdjust::Level(l) => write!(f, "level {}", l),
+        }
+    }
+}
+
+// the uuid_* family of api commands
+impl ChannelHandle {
+    pub async fn kill(&self, cause: Option<&str>) -> Result<()> {
+        let cause = match cause {
+            Some(c) => api_arg(c)?,
+            None => "",
+        };
+        self.uuid_api("uuid_kill", cause).await?;
+        Ok(())
+    }
+
+    /// `uuid_transfer`, `leg` B or Both transfers the bridged leg as well.
+    pub async fn transfer(
+        &self,
+        dest: &str,
+        dialplan: Option<&str>,
+        context: Option<&str>,
+        leg: Leg,
+    ) -> Result<()> {
+        let flag = match leg {
+            Leg::A => "",
+            Leg::B => "-bleg ",
+            Leg::Both => "-both ",
+        };
+        let mut cmd = format!("uuid_transfer {} {}{}", self.uuid(), flag, api_arg(dest)?);
+        if let Some(d) = dialplan {
+            cmd.push_str(&format!(" {}", api_arg(d)?));
+            if let Some(c) = context {
+                cmd.push_str(&format!(" {}", api_arg(c)?));
+            }
+        }
+        self.channel_api(&cmd).await?;
+        Ok(())
+    }
+
+    pub async fn bridge(&self, other: &str) -> Result<()> {
+        self.uuid_api("uuid_bridge", other).await?;
+        Ok(())
+    }
+
+    pub async fn hold(&self) -> Result<()> {
+        self.channel_api(&format!("uuid_hold {}", self.uuid()))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn unhold(&self) -> Result<()> {
+        self.channel_api(&format!("uuid_hold off {}", self.uuid()))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn setvar(&self, name: &str, value: &str) -> Result<()> {
+        self.uuid_api("uuid_setvar", &format!("{} {}", name, value))
+            .await?;
+        Ok(())
+    }
+
+    /// `uuid_getvar`, None if the variable is not set.
+    pub async fn getvar(&self, name: &str) -> Result<Option<String>> {
+        let v = self.uuid_api("uuid_getvar", name).await?;
+        let v = v.trim();
+        if v == "_undef_" {
+            return Ok(None);
+        }
+        Ok(Some(v.to_string()))
+    }
+
+    /// `uuid_dump` as a header map.
+    pub async fn dump(&self) -> Result<EventData> {
+        let body = self.uuid_api("uuid_dump", "json").await?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// `uuid_record start`, recording in the background.
+    pub async fn record_session(&self, path: &str, limit: Option<Duration>) -> Result<()> {
+        let arg = match limit {
+            Some(l) => format!("start {} {}", path, l.as_secs()),
+            None => format!("start {}", path),
+        };
+        self.uuid_api("uuid_record", &arg).await?;
+        Ok(())
+    }
+
+    pub async fn stop_record_session(&self, path: &str) -> Result<()> {
+        self.uuid_api("uuid_record", &format!("stop {}", path))
+            .await?;
+        Ok(())
+    }
+
+    /// `uuid_break`, `all` also flushes the queued applications.
+    pub async fn break_media(&self, all: bool) -> Result<()> {
+        self.uuid_api("uuid_break", if all { "all" } else { "" })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn answer(&self) -> Result<()> {
+        self.uuid_api("uuid_answer", "").await?;
+        Ok(())
+    }
+
+    pub async fn park(&self) -> Result<()> {
+        self.uuid_api("uuid_park", "").await?;
+        Ok(())
+    }
+
+    pub async fn send_dtmf(&self, digits: &str, duration: Option<Duration>) -> Result<()> {
+        let arg = match duration {
+            Some(d) => format!("{}@{}", digits, d.as_millis()),
+            None => digits.to_string(),
+        };
+        self.uuid_api("uuid_send_dtmf", &arg).await?;
+        Ok(())
+    }
+
+    pub async fn exists(&self) -> Result<bool> {
+        let body = self.uuid_api("uuid_exists", "").await?;
+        Ok(body.trim() == "true")
+    }
+
+    pub async fn audio(&self, direction: AudioDirection, adjust: AudioAdjust) -> Result<()> {
+        self.uuid_api("uuid_audio", &format!("start {} {}", direction, adjust))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn audio_stop(&self) -> Result<()> {
+        self.uuid_api("uuid_audio", "stop").await?;
+        Ok(())
+    }
+
+    // `<cmd> <uuid> <arg>`
+    async fn uuid_api(&self, cmd: &str, arg: &str) -> Result<String> {
+        let cmd = format!("{} {} {}", cmd, self.uuid(), arg);
+        self.channel_api(&cmd).await
+    }
+
+    // api call with `-ERR No such channel!` turned into ChannelNotFound
+    async fn channel_api(&self, cmd: &str) -> Result<String> {
+        api_arg(self.uuid())?;
+        match self.session().api(cmd).await {
+            Ok(body) => Ok(body),
+            Err(e) => match e.downcast_ref::<MsgError>() {
+                Some(MsgError::ErrResponse(s)) if s.contains("No such channel") => {
+                    Err(MsgError::ChannelNotFound(self.uuid().to_string()).into())
+                }
+                _ => Err(e),
+            },
+        }
+    }
+}
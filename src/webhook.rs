@@ -0,0 +1,280 @@
+use crate::event::{EventData, EventHandler};
+use crate::message::{Message, MsgError};
+use crate::session::Session;
+use anyhow::Result;
+use hmac::{Hmac, Mac};
+use hyper::{
+    client::HttpConnector,
+    header::{HeaderName, HeaderValue, CONTENT_TYPE},
+    Body, Client, HeaderMap, Request, Uri,
+};
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+use tokio::{
+    fs::OpenOptions,
+    io::AsyncWriteExt,
+    sync::{broadcast, Mutex, Semaphore},
+    task::JoinHandle,
+};
+use tracing::{debug, error, warn};
+
+pub const SIGNATURE_HEADER: &str = "X-Rsesl-Signature";
+
+/// Longest wait between two retries.
+pub const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Which events go to which URL.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Rule {
+    // Event-Name, e.g. CHANNEL_ANSWER
+    pub event: Option<String>,
+    // Event-Subclass of CUSTOM events
+    pub subclass: Option<String>,
+    // event headers that must have exactly these values
+    #[serde(default)]
+    pub when: HashMap<String, String>,
+    pub url: String,
+    // extra HTTP headers, `${Event-Header}` is replaced from the event
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+impl Rule {
+    pub fn matches(&self, ed: &EventData) -> bool {
+        if let Some(e) = &self.event {
+            if ed.get_header("Event-Name".to_string()) != *e {
+                return false;
+            }
+        }
+        if let Some(s) = &self.subclass {
+            if ed.subclass() != *s {
+                return false;
+            }
+        }
+        self.when
+            .iter()
+            .all(|(k, v)| ed.get_header(k.to_string()) == *v)
+    }
+}
+
+/// POSTs matching events as JSON to HTTP endpoints.
+#[derive(Clone)]
+pub struct WebhookSink {
+    rules: Arc<Vec<Rule>>,
+    client: Client<HttpConnector>,
+    permits: Arc<Semaphore>,
+    retries: u32,
+    backoff: Duration,
+    timeout: Duration,
+    secret: Option<Arc<Vec<u8>>>,
+    dead_letter: Option<Arc<Mutex<PathBuf>>>,
+}
+
+impl WebhookSink {
+    /// Fails on a URL that is not `http://`, there is no TLS client.
+    pub fn new(rules: Vec<Rule>) -> Result<Self> {
+        for rule in &rules {
+            let uri: Uri = rule
+                .url
+                .parse()
+                .map_err(|_| MsgError::InvalidArgument(rule.url.clone()))?;
+            if uri.scheme_str() != Some("http") {
+                return Err(MsgError::InvalidArgument(rule.url.clone()).into());
+            }
+        }
+        Ok(WebhookSink {
+            rules: Arc::new(rules),
+            client: Client::new(),
+            permits: Arc::new(Semaphore::new(16)),
+            retries: 3,
+            backoff: Duration::from_millis(500),
+            timeout: Duration::from_secs(10),
+            secret: None,
+            dead_letter: None,
+        })
+    }
+
+    /// Maximum number of requests in flight.
+    pub fn concurrency(mut self, n: usize) -> Self {
+        self.permits = Arc::new(Semaphore::new(n.max(1)));
+        self
+    }
+
+    /// Retry a failed delivery `retries` times, doubling `backoff` each time up
+    /// to `MAX_BACKOFF`.
+    pub fn retries(mut self, retries: u32, backoff: Duration) -> Self {
+        self.retries = retries;
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sign bodies with HMAC-SHA256, sent as `X-Rsesl-Signature: sha256=<hex>`.
+    pub fn secret(mut self, secret: &[u8]) -> Self {
+        self.secret = Some(Arc::new(secret.to_vec()));
+        self
+    }
+
+    /// Append deliveries that failed every retry to this file, one JSON per line.
+    pub fn dead_letter(mut self, path: PathBuf) -> Self {
+        self.dead_letter = Some(Arc::new(Mutex::new(path)));
+        self
+    }
+
+    /// Forward the events of `session` until it closes.
+    pub fn run(self, session: &Session) -> JoinHandle<()> {
+        let rx = session.subscribe();
+        tokio::spawn(self.forward(rx))
+    }
+
+    async fn forward(self, mut rx: broadcast::Receiver<Arc<Mutex<Message>>>) {
+        loop {
+            let msg = match rx.recv().await {
+                Ok(m) => m,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("webhook sink lagged {} messages", n);
+                    #[cfg(feature = "metrics")]
+                    crate::metrics::lagged("webhook", n);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    debug!("session closed, stop webhook sink");
+                    return;
+                }
+            };
+            // waiting for a free slot must not hold up the other readers
+            let ed = {
+                let msg = msg.lock().await;
+                if msg.is_reply() {
+                    continue;
+                }
+                msg.event_data.clone()
+            };
+            if let Some(ed) = ed {
+                self.dispatch(&ed).await;
+            }
+        }
+    }
+
+    /// Start a delivery for every rule matching `ed`, waits only for a free slot.
+    pub async fn dispatch(&self, ed: &EventData) {
+        for rule in self.rules.iter().filter(|r| r.matches(ed)) {
+            let permit = match self.permits.clone().acquire_owned().await {
+                Ok(p) => p,
+                Err(_) => return,
+            };
+            let sink = self.clone();
+            let rule = rule.clone();
+            let ed = ed.clone();
+            tokio::spawn(async move {
+                sink.deliver(&rule, &ed).await;
+                drop(permit);
+            });
+        }
+    }
+
+    async fn deliver(&self, rule: &Rule, ed: &EventData) {
+        let body = Value::Object(ed.clone()).to_string();
+        // a header that is invalid now is invalid on every retry
+        let headers = match self.headers(rule, ed, &body) {
+            Ok(h) => h,
+            Err(e) => {
+                error!("webhook {} has an invalid header: {}", rule.url, e);
+                if let Err(e) = self.write_dead_letter(rule, ed, &e.to_string()).await {
+                    error!("failed to write dead letter: {}", e);
+                }
+                return;
+            }
+        };
+        let mut last_err = String::new();
+
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                let factor = 2u32.saturating_pow(attempt - 1);
+                tokio::time::sleep(self.backoff.saturating_mul(factor).min(MAX_BACKOFF)).await;
+            }
+            match self.post(rule, headers.clone(), &body).await {
+                Ok(()) => return,
+                Err(e) => {
+                    warn!("webhook {} attempt {} failed: {}", rule.url, attempt + 1, e);
+                    last_err = e.to_string();
+                }
+            }
+        }
+
+        error!("webhook {} failed, giving up: {}", rule.url, last_err);
+        if let Err(e) = self.write_dead_letter(rule, ed, &last_err).await {
+            error!("failed to write dead letter: {}", e);
+        }
+    }
+
+    fn headers(&self, rule: &Rule, ed: &EventData, body: &str) -> Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        for (k, v) in &rule.headers {
+            let name = HeaderName::from_bytes(k.as_bytes())?;
+            headers.insert(name, HeaderValue::from_str(&render(v, ed))?);
+        }
+        if let Some(secret) = &self.secret {
+            let signature = sign(secret, body.as_bytes());
+            let name = HeaderName::from_bytes(SIGNATURE_HEADER.as_bytes())?;
+            headers.insert(name, HeaderValue::from_str(&signature)?);
+        }
+        Ok(headers)
+    }
+
+    async fn post(&self, rule: &Rule, headers: HeaderMap, body: &str) -> Result<()> {
+        let mut req = Request::post(&rule.url).body(Body::from(body.to_string()))?;
+        *req.headers_mut() = headers;
+
+        let res = tokio::time::timeout(self.timeout, self.client.request(req)).await??;
+        if !res.status().is_success() {
+            return Err(anyhow::anyhow!("HTTP status {}", res.status()));
+        }
+        Ok(())
+    }
+
+    async fn write_dead_letter(&self, rule: &Rule, ed: &EventData, err: &str) -> Result<()> {
+        let path = match &self.dead_letter {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        // the lock keeps lines from interleaving
+        let path = path.lock().await;
+        let line = json!({ "url": rule.url, "error": err, "event": ed }).to_string() + "\n";
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&*path)
+            .await?;
+        f.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+/// `sha256=<hex>` HMAC of `body`.
+pub fn sign(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC can take a key of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+// replace ${Header-Name} with the event header
+fn render(template: &str, ed: &EventData) -> String {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| Regex::new(r"\$\{([^}]+)\}").unwrap());
+    re.replace_all(template, |c: &Captures| ed.get_header(c[1].to_string()))
+        .to_string()
+}
@@ -0,0 +1,621 @@
+use crate::event::{EventData, EventHandler};
+use anyhow::Result;
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Form, Router,
+};
+use futures_util::future::BoxFuture;
+use quick_xml::escape::escape;
+use serde_json::Value;
+use std::{collections::HashMap, fmt::Display, future::Future, net::SocketAddr, sync::Arc};
+use tracing::{debug, error};
+
+/// The `section` mod_xml_curl asks for.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Section {
+    Directory,
+    Dialplan,
+    Configuration,
+    Phrases,
+    Languages,
+    Chatplan,
+    Channels,
+    Other(String),
+}
+
+impl From<&str> for Section {
+    fn from(s: &str) -> Self {
+        match s {
+            "directory" => Section::Directory,
+            "dialplan" => Section::Dialplan,
+            "configuration" => Section::Configuration,
+            "phrases" => Section::Phrases,
+            "languages" => Section::Languages,
+            "chatplan" => Section::Chatplan,
+            "channels" => Section::Channels,
+            _ => Section::Other(s.to_string()),
+        }
+    }
+}
+
+impl Display for Section {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Section::Directory => write!(f, "directory"),
+            Section::Dialplan => write!(f, "dialplan"),
+            Section::Configuration => write!(f, "configuration"),
+            Section::Phrases => write!(f, "phrases"),
+            Section::Languages => write!(f, "languages"),
+            Section::Chatplan => write!(f, "chatplan"),
+            Section::Channels => write!(f, "channels"),
+            Section::Other(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// One fetch POSTed by mod_xml_curl.
+#[derive(Debug, Clone)]
+pub struct FetchRequest {
+    pub section: Section,
+    pub tag_name: String,
+    pub key_name: String,
+    pub key_value: String,
+    pub hostname: String,
+    // every posted field, including the event headers
+    pub params: EventData,
+}
+
+impl FetchRequest {
+    pub fn param(&self, k: &str) -> String {
+        self.params.get_header(k.to_string())
+    }
+}
+
+impl From<HashMap<String, String>> for FetchRequest {
+    fn from(form: HashMap<String, String>) -> Self {
+        let get = |k: &str| form.get(k).cloned().unwrap_or_default();
+        FetchRequest {
+            section: Section::from(get("section").as_str()),
+            tag_name: get("tag_name"),
+            key_name: get("key_name"),
+            key_value: get("key_value"),
+            hostname: get("hostname"),
+            params: form
+                .iter()
+                .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+                .collect(),
+        }
+    }
+}
+
+/// A generic XML element, what the typed builders turn into.
+#[derive(Debug, Clone, Default)]
+pub struct Element {
+    pub name: String,
+    pub attrs: Vec<(String, String)>,
+    pub text: Option<String>,
+    pub children: Vec<Element>,
+}
+
+impl Element {
+    pub fn new(name: &str) -> Self {
+        Element {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn attr(mut self, k: &str, v: &str) -> Self {
+        self.attrs.push((k.to_string(), v.to_string()));
+        self
+    }
+
+    pub fn text(mut self, t: &str) -> Self {
+        self.text = Some(t.to_string());
+        self
+    }
+
+    pub fn child(mut self, e: impl Into<Element>) -> Self {
+        self.children.push(e.into());
+        self
+    }
+
+    fn write(&self, out: &mut String, depth: usize) {
+        let indent = "  ".repeat(depth);
+        out.push_str(&format!("{}<{}", indent, self.name));
+        for (k, v) in &self.attrs {
+            out.push_str(&format!(" {}=\"{}\"", k, escape(v)));
+        }
+        match (&self.text, self.children.is_empty()) {
+            (None, true) => {
+                out.push_str("/>\n");
+                return;
+            }
+            (Some(t), true) => {
+                out.push_str(&format!(">{}</{}>\n", escape(t), self.name));
+                return;
+            }
+            (Some(t), false) => out.push_str(&format!(">{}\n", escape(t))),
+            (None, false) => out.push_str(">\n"),
+        }
+        for c in &self.children {
+            c.write(out, depth + 1);
+        }
+        out.push_str(&format!("{}</{}>\n", indent, self.name));
+    }
+}
+
+impl Display for Element {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut out = String::new();
+        self.write(&mut out, 0);
+        write!(f, "{}", out)
+    }
+}
+
+// <params> / <variables> lists
+fn pairs(list: &str, item: &str, pairs: &[(String, String)]) -> Option<Element> {
+    if pairs.is_empty() {
+        return None;
+    }
+    let mut e = Element::new(list);
+    for (k, v) in pairs {
+        e = e.child(Element::new(item).attr("name", k).attr("value", v));
+    }
+    Some(e)
+}
+
+/// `<user>` of the directory.
+#[derive(Debug, Clone, Default)]
+pub struct User {
+    pub id: String,
+    pub params: Vec<(String, String)>,
+    pub variables: Vec<(String, String)>,
+}
+
+impl User {
+    pub fn new(id: &str) -> Self {
+        User {
+            id: id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn param(mut self, k: &str, v: &str) -> Self {
+        self.params.push((k.to_string(), v.to_string()));
+        self
+    }
+
+    pub fn variable(mut self, k: &str, v: &str) -> Self {
+        self.variables.push((k.to_string(), v.to_string()));
+        self
+    }
+}
+
+impl From<User> for Element {
+    fn from(u: User) -> Self {
+        let mut e = Element::new("user").attr("id", &u.id);
+        e.children.extend(pairs("params", "param", &u.params));
+        e.children
+            .extend(pairs("variables", "variable", &u.variables));
+        e
+    }
+}
+
+/// `<domain>` of the directory, the users go in one `default` group.
+#[derive(Debug, Clone, Default)]
+pub struct Domain {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+    pub variables: Vec<(String, String)>,
+    pub users: Vec<User>,
+}
+
+impl Domain {
+    pub fn new(name: &str) -> Self {
+        Domain {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn param(mut self, k: &str, v: &str) -> Self {
+        self.params.push((k.to_string(), v.to_string()));
+        self
+    }
+
+    pub fn variable(mut self, k: &str, v: &str) -> Self {
+        self.variables.push((k.to_string(), v.to_string()));
+        self
+    }
+
+    pub fn user(mut self, u: User) -> Self {
+        self.users.push(u);
+        self
+    }
+}
+
+impl From<Domain> for Element {
+    fn from(d: Domain) -> Self {
+        let mut e = Element::new("domain").attr("name", &d.name);
+        e.children.extend(pairs("params", "param", &d.params));
+        e.children
+            .extend(pairs("variables", "variable", &d.variables));
+        if !d.users.is_empty() {
+            let mut users = Element::new("users");
+            for u in d.users {
+                users = users.child(u);
+            }
+            e = e.child(
+                Element::new("groups")
+                    .child(Element::new("group").attr("name", "default").child(users)),
+            );
+        }
+        e
+    }
+}
+
+/// `<action>` or `<anti-action>` of a condition.
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub application: String,
+    pub data: String,
+}
+
+/// `<condition>` of a dialplan extension.
+#[derive(Debug, Clone, Default)]
+pub struct Condition {
+    pub field: Option<String>,
+    pub expression: Option<String>,
+    pub actions: Vec<Action>,
+    pub anti_actions: Vec<Action>,
+}
+
+impl Condition {
+    pub fn new(field: &str, expression: &str) -> Self {
+        Condition {
+            field: Some(field.to_string()),
+            expression: Some(expression.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// A condition that always matches.
+    pub fn always() -> Self {
+        Condition::default()
+    }
+
+    pub fn action(mut self, application: &str, data: &str) -> Self {
+        self.actions.push(Action {
+            application: application.to_string(),
+            data: data.to_string(),
+        });
+        self
+    }
+
+    pub fn anti_action(mut self, application: &str, data: &str) -> Self {
+        self.anti_actions.push(Action {
+            application: application.to_string(),
+            data: data.to_string(),
+        });
+        self
+    }
+}
+
+impl From<Condition> for Element {
+    fn from(c: Condition) -> Self {
+        let mut e = Element::new("condition");
+        if let Some(f) = &c.field {
+            e = e.attr("field", f);
+        }
+        if let Some(x) = &c.expression {
+            e = e.attr("expression", x);
+        }
+        for (tag, list) in [("action", &c.actions), ("anti-action", &c.anti_actions)] {
+            for a in list {
+                let mut ae = Element::new(tag).attr("application", &a.application);
+                if !a.data.is_empty() {
+                    ae = ae.attr("data", &a.data);
+                }
+                e = e.child(ae);
+            }
+        }
+        e
+    }
+}
+
+/// `<extension>` of a dialplan context.
+#[derive(Debug, Clone, Default)]
+pub struct Extension {
+    pub name: String,
+    // keep looking for more extensions after this one matched
+    pub continue_on_match: bool,
+    pub conditions: Vec<Condition>,
+}
+
+impl Extension {
+    pub fn new(name: &str) -> Self {
+        Extension {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn continue_on_match(mut self) -> Self {
+        self.continue_on_match = true;
+        self
+    }
+
+    pub fn condition(mut self, c: Condition) -> Self {
+        self.conditions.push(c);
+        self
+    }
+}
+
+impl From<Extension> for Element {
+    fn from(x: Extension) -> Self {
+        let mut e = Element::new("extension").attr("name", &x.name);
+        if x.continue_on_match {
+            e = e.attr("continue", "true");
+        }
+        for c in x.conditions {
+            e = e.child(c);
+        }
+        e
+    }
+}
+
+/// `<context>` of the dialplan.
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    pub name: String,
+    pub extensions: Vec<Extension>,
+}
+
+impl Context {
+    pub fn new(name: &str) -> Self {
+        Context {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn extension(mut self, x: Extension) -> Self {
+        self.extensions.push(x);
+        self
+    }
+}
+
+impl From<Context> for Element {
+    fn from(c: Context) -> Self {
+        let mut e = Element::new("context").attr("name", &c.name);
+        for x in c.extensions {
+            e = e.child(x);
+        }
+        e
+    }
+}
+
+/// `<configuration name="xxx.conf">`, the body differs per module so it is
+/// made of plain elements.
+#[derive(Debug, Clone, Default)]
+pub struct Configuration {
+    pub name: String,
+    pub description: Option<String>,
+    pub children: Vec<Element>,
+}
+
+impl Configuration {
+    pub fn new(name: &str) -> Self {
+        Configuration {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn description(mut self, d: &str) -> Self {
+        self.description = Some(d.to_string());
+        self
+    }
+
+    pub fn child(mut self, e: impl Into<Element>) -> Self {
+        self.children.push(e.into());
+        self
+    }
+
+    /// `<settings>` made of `<param name= value=>`.
+    pub fn settings(self, params: &[(&str, &str)]) -> Self {
+        let mut e = Element::new("settings");
+        for (k, v) in params {
+            e = e.child(Element::new("param").attr("name", k).attr("value", v));
+        }
+        self.child(e)
+    }
+}
+
+impl From<Configuration> for Element {
+    fn from(c: Configuration) -> Self {
+        let mut e = Element::new("configuration").attr("name", &c.name);
+        if let Some(d) = &c.description {
+            e = e.attr("description", d);
+        }
+        e.children = c.children;
+        e
+    }
+}
+
+/// The `<document type="freeswitch/xml">` answer to a fetch.
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub section: Section,
+    pub children: Vec<Element>,
+}
+
+impl Document {
+    pub fn new(section: Section) -> Self {
+        Document {
+            section,
+            children: vec![],
+        }
+    }
+
+    pub fn child(mut self, e: impl Into<Element>) -> Self {
+        self.children.push(e.into());
+        self
+    }
+
+    pub fn directory(domain: Domain) -> Self {
+        Document::new(Section::Directory).child(domain)
+    }
+
+    pub fn dialplan(context: Context) -> Self {
+        Document::new(Section::Dialplan).child(context)
+    }
+
+    pub fn configuration(configuration: Configuration) -> Self {
+        Document::new(Section::Configuration).child(configuration)
+    }
+
+    /// Makes FreeSWITCH fall back to its local XML.
+    pub fn not_found() -> Self {
+        Document::new(Section::Other("result".to_string()))
+            .child(Element::new("result").attr("status", "not found"))
+    }
+}
+
+impl Display for Document {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut section = Element::new("section").attr("name", &self.section.to_string());
+        section.children = self.children.clone();
+        let doc = Element::new("document")
+            .attr("type", "freeswitch/xml")
+            .child(section);
+        writeln!(
+            f,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>"
+        )?;
+        write!(f, "{}", doc)
+    }
+}
+
+impl IntoResponse for Document {
+    fn into_response(self) -> Response {
+        ([(header::CONTENT_TYPE, "text/xml")], self.to_string()).into_response()
+    }
+}
+
+type Callback =
+    Arc<dyn Fn(FetchRequest) -> BoxFuture<'static, Result<Option<Document>>> + Send + Sync>;
+
+/// What `router` answers when a callback fails.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnError {
+    /// `Document::not_found()`, FreeSWITCH falls back to its local XML.
+    #[default]
+    NotFound,
+    /// HTTP 500, the fetch fails.
+    ServerError,
+}
+
+/// Routes mod_xml_curl fetches to a callback per section. Sections without
+/// a callback and callbacks returning None answer `Document::not_found()`,
+/// failed callbacks answer as set by `on_error`.
+#[derive(Clone, Default)]
+pub struct XmlCurl {
+    callbacks: HashMap<Section, Callback>,
+    on_error: OnError,
+}
+
+impl XmlCurl {
+    pub fn new() -> Self {
+        XmlCurl::default()
+    }
+
+    pub fn on<F, Fut>(mut self, section: Section, f: F) -> Self
+    where
+        F: Fn(FetchRequest) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Option<Document>>> + Send + 'static,
+    {
+        self.callbacks
+            .insert(section, Arc::new(move |req| Box::pin(f(req))));
+        self
+    }
+
+    pub fn directory<F, Fut>(self, f: F) -> Self
+    where
+        F: Fn(FetchRequest) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Option<Document>>> + Send + 'static,
+    {
+        self.on(Section::Directory, f)
+    }
+
+    pub fn dialplan<F, Fut>(self, f: F) -> Self
+    where
+        F: Fn(FetchRequest) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Option<Document>>> + Send + 'static,
+    {
+        self.on(Section::Dialplan, f)
+    }
+
+    pub fn configuration<F, Fut>(self, f: F) -> Self
+    where
+        F: Fn(FetchRequest) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Option<Document>>> + Send + 'static,
+    {
+        self.on(Section::Configuration, f)
+    }
+
+    pub fn on_error(mut self, on_error: OnError) -> Self {
+        self.on_error = on_error;
+        self
+    }
+
+    /// Answer a fetch, for use outside of `router`. A failed callback is
+    /// logged and returned.
+    pub async fn fetch(&self, req: FetchRequest) -> Result<Document> {
+        let cb = match self.callbacks.get(&req.section) {
+            Some(cb) => cb.clone(),
+            None => {
+                debug!("no xml_curl handler for section {}", req.section);
+                return Ok(Document::not_found());
+            }
+        };
+        let section = req.section.clone();
+        match cb(req).await {
+            Ok(Some(doc)) => Ok(doc),
+            Ok(None) => Ok(Document::not_found()),
+            Err(e) => {
+                error!("xml_curl {} handler failed: {}", section, e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Answers fetches POSTed to `/`, point `gateway-url` at it.
+    pub fn router(self) -> Router {
+        Router::new()
+            .route("/", post(fetch))
+            .with_state(Arc::new(self))
+    }
+
+    pub async fn serve(self, addr: SocketAddr) -> Result<()> {
+        axum::Server::bind(&addr)
+            .serve(self.router().into_make_service())
+            .await?;
+        Ok(())
+    }
+}
+
+async fn fetch(
+    State(x): State<Arc<XmlCurl>>,
+    Form(form): Form<HashMap<String, String>>,
+) -> Response {
+    match x.fetch(FetchRequest::from(form)).await {
+        Ok(doc) => doc.into_response(),
+        Err(_) if x.on_error == OnError::NotFound => Document::not_found().into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
@@ -0,0 +1,160 @@
+use rsesl::{
+    callcenter::{
+        parse_table, AgentState, AgentStatus, AgentType, Callcenter, CallcenterEvent, TierField,
+        INFO,
+    },
+    event::EventData,
+    message::{FormatType, MsgError},
+    testing::MockFreeswitch,
+};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+const MEMBERS: &str = "queue|uuid|session_uuid|cid_number|cid_name|system_epoch|joined_epoch|rejoined_epoch|bridge_epoch|abandoned_epoch|base_score|skill_score|serving_agent|serving_system|state|score
+support@default|m-1|s-1|1000|Alice|0|1700000000|0|0|0|0|0||single_box|Waiting|42
++OK
+";
+
+fn info(headers: Value) -> EventData {
+    let mut ed = json!({ "Event-Name": "CUSTOM", "Event-Subclass": INFO })
+        .as_object()
+        .unwrap()
+        .clone();
+    ed.extend(headers.as_object().unwrap().clone());
+    ed
+}
+
+#[test]
+fn parses_tables() {
+    let rows = parse_table(MEMBERS);
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["cid_name"], "Alice");
+    assert_eq!(rows[0]["serving_agent"], "");
+    assert!(parse_table("").is_empty());
+    assert!(parse_table("name|strategy\n+OK\n").is_empty());
+}
+
+#[test]
+fn decodes_events() {
+    let ed = info(json!({
+        "CC-Action": "member-queue-start",
+        "CC-Queue": "support@default",
+        "CC-Member-UUID": "m-1",
+        "CC-Member-Session-UUID": "s-1",
+        "CC-Member-CID-Name": "Alice",
+        "CC-Member-CID-Number": "1000",
+    }));
+    match CallcenterEvent::from(&ed) {
+        CallcenterEvent::MemberQueueStart {
+            queue, cid_name, ..
+        } => {
+            assert_eq!(queue, "support@default");
+            assert_eq!(cid_name, "Alice");
+        }
+        e => panic!("unexpected {:?}", e),
+    }
+
+    let ed = info(json!({
+        "CC-Action": "agent-status-change",
+        "CC-Agent": "1001@default",
+        "CC-Agent-Status": "Available (On Demand)",
+    }));
+    assert!(matches!(
+        CallcenterEvent::from(&ed),
+        CallcenterEvent::AgentStatusChange {
+            status: AgentStatus::AvailableOnDemand,
+            ..
+        }
+    ));
+
+    let ed =
+        info(json!({ "CC-Action": "agent-state-change", "CC-Agent-State": "In a queue call" }));
+    assert!(matches!(
+        CallcenterEvent::from(&ed),
+        CallcenterEvent::AgentStateChange {
+            state: AgentState::InQueueCall,
+            ..
+        }
+    ));
+
+    let ed = info(json!({ "CC-Action": "members-count", "CC-Count": "3" }));
+    assert!(matches!(
+        CallcenterEvent::from(&ed),
+        CallcenterEvent::MembersCount { count: 3, .. }
+    ));
+
+    let ed = info(json!({ "CC-Action": "member-infos" }));
+    assert!(matches!(
+        CallcenterEvent::from(&ed),
+        CallcenterEvent::Other { action, .. } if action == "member-infos"
+    ));
+}
+
+#[tokio::test]
+async fn configures_and_lists() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    fs.api("callcenter_config", "+OK\n").await;
+    fs.api("callcenter_config member list", MEMBERS).await;
+    fs.api("callcenter_config queue count support@default", "3\n")
+        .await;
+    fs.api(
+        "callcenter_config queue count nope",
+        "-ERR Invalid Queue not found!\n",
+    )
+    .await;
+    let (session, _shutdown) = fs.session().await.unwrap();
+    let cc = Callcenter::new(session);
+
+    cc.agent_add("1001@default", AgentType::UuidStandby)
+        .await
+        .unwrap();
+    cc.agent_set_status("1001@default", AgentStatus::OnBreak)
+        .await
+        .unwrap();
+    cc.tier_set("support@default", "1001@default", TierField::Level(2))
+        .await
+        .unwrap();
+    let commands = fs.commands().await;
+    assert!(
+        commands.contains(&"api callcenter_config agent add 1001@default uuid-standby".to_string())
+    );
+    assert!(commands
+        .contains(&"api callcenter_config agent set status 1001@default 'On Break'".to_string()));
+    assert!(commands.contains(
+        &"api callcenter_config tier set level support@default 1001@default 2".to_string()
+    ));
+
+    let members = cc.member_list().await.unwrap();
+    assert_eq!(members[0].joined_epoch, 1700000000);
+    assert_eq!(members[0].score, 42);
+    assert_eq!(cc.queue_count("support@default").await.unwrap(), 3);
+    let err = cc.queue_count("nope").await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<MsgError>(),
+        Some(MsgError::ErrResponse(_))
+    ));
+}
+
+#[tokio::test]
+async fn streams_events() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    let (session, _shutdown) = fs.session().await.unwrap();
+    let cc = Callcenter::new(session);
+    let (mut events, task) = cc.events().await.unwrap();
+
+    let ed = info(
+        json!({ "CC-Action": "members-count", "CC-Queue": "support@default", "CC-Count": "1" }),
+    );
+    fs.push_event(&ed, FormatType::Json).await;
+    let e = tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(matches!(e, CallcenterEvent::MembersCount { count: 1, .. }));
+
+    task.abort();
+    let end = tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .unwrap();
+    assert!(end.is_none());
+}
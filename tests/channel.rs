@@ -0,0 +1,159 @@
+use rsesl::{
+    channel::{ChannelHandle, CollectEnd, CollectOptions},
+    event::EventData,
+    media::Leg,
+    message::{FormatType, MsgError},
+    testing::MockFreeswitch,
+};
+use serde_json::json;
+use std::time::Duration;
+
+fn dtmf(digit: &str) -> EventData {
+    json!({ "Event-Name": "DTMF", "Unique-ID": "abc-123", "DTMF-Digit": digit, "DTMF-Source": "RTP" })
+        .as_object()
+        .unwrap()
+        .clone()
+}
+
+fn opts(min: usize, max: usize) -> CollectOptions {
+    CollectOptions {
+        min_digits: min,
+        max_digits: max,
+        first_digit_timeout: Duration::from_millis(300),
+        inter_digit_timeout: Duration::from_millis(300),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn collects_dtmf_events() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    let (ch, _shutdown) = fs.channel("abc-123").await.unwrap();
+
+    let collect = tokio::spawn({
+        let ch = ch.clone();
+        async move { ch.collect_dtmf(opts(1, 3)).await.unwrap() }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    for d in ["1", "2", "3"] {
+        fs.push_event(&dtmf(d), FormatType::Json).await;
+    }
+    let r = collect.await.unwrap();
+    assert_eq!(r.digits, "123");
+    assert_eq!(r.end, CollectEnd::MaxDigits);
+    assert_eq!(r.source, "RTP");
+    assert!(r.valid);
+
+    // fewer than min_digits before the terminator
+    let collect = tokio::spawn({
+        let ch = ch.clone();
+        async move { ch.collect_dtmf(opts(3, 5)).await.unwrap() }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    for d in ["1", "#"] {
+        fs.push_event(&dtmf(d), FormatType::Json).await;
+    }
+    let r = collect.await.unwrap();
+    assert_eq!(r.end, CollectEnd::Terminator);
+    assert_eq!(r.terminator, Some('#'));
+    assert!(!r.valid);
+
+    // nothing pressed
+    let r = ch.collect_dtmf(opts(1, 3)).await.unwrap();
+    assert_eq!(r.end, CollectEnd::Timeout);
+    assert!(r.digits.is_empty());
+    assert!(!r.valid);
+}
+
+#[tokio::test]
+async fn play_and_get_digits() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    let done = json!({ "variable_rsesl_collected_digits": "12" });
+    fs.app("play_and_get_digits", done.as_object().unwrap().clone())
+        .await;
+    let (ch, _shutdown) = fs.channel("abc-123").await.unwrap();
+
+    // a partial entry ended by the inter-digit timeout
+    let o = CollectOptions {
+        prompt: Some("ivr/enter.wav".to_string()),
+        ..opts(1, 4)
+    };
+    let r = ch.collect_dtmf(o.clone()).await.unwrap();
+    assert_eq!(r.digits, "12");
+    assert_eq!(r.end, CollectEnd::Timeout);
+    assert!(r.valid);
+    let cmd = fs.commands().await.pop().unwrap();
+    assert!(cmd.contains("\nexecute-app-arg: 1 4 1 300 # ivr/enter.wav "));
+
+    // a space would shift every later argument
+    for o in [
+        CollectOptions {
+            prompt: Some("/tmp/enter code.wav".to_string()),
+            ..o.clone()
+        },
+        CollectOptions {
+            regex: Some("^1 2$".to_string()),
+            ..o.clone()
+        },
+    ] {
+        let err = ch.collect_dtmf(o).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MsgError>(),
+            Some(MsgError::InvalidArgument(_))
+        ));
+    }
+}
+
+#[tokio::test]
+async fn rejects_split_api_args() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    let (ch, _shutdown) = fs.channel("abc-123").await.unwrap();
+
+    for err in [
+        ch.kill(Some("NORMAL_CLEARING\n\napi status")).await,
+        ch.transfer("1000 XML", None, None, Leg::A).await,
+        ch.transfer("1000", Some("XML"), Some("de fault"), Leg::A)
+            .await,
+        ChannelHandle::new(ch.session().clone(), "abc 123")
+            .kill(None)
+            .await,
+    ] {
+        match err.unwrap_err().downcast_ref::<MsgError>() {
+            Some(MsgError::InvalidArgument(_)) => {}
+            e => panic!("unexpected {:?}", e),
+        }
+    }
+    assert!(!fs.commands().await.iter().any(|c| c.contains("uuid_")));
+}
+
+#[tokio::test]
+async fn subscribes_what_it_waits_for() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    fs.app("answer", Default::default()).await;
+    let (session, _shutdown) = fs.session().await.unwrap();
+    session
+        .event(FormatType::Plain, &["CHANNEL_CREATE"])
+        .await
+        .unwrap();
+
+    // nothing but CHANNEL_CREATE was subscribed to
+    let ch = ChannelHandle::new(session, "abc-123");
+    tokio::time::timeout(Duration::from_secs(5), ch.execute_wait("answer", ""))
+        .await
+        .unwrap()
+        .unwrap();
+    ch.execute_wait("answer", "").await.unwrap();
+    let events: Vec<String> = fs
+        .commands()
+        .await
+        .into_iter()
+        .filter(|c| c.starts_with("event "))
+        .collect();
+    assert_eq!(
+        events,
+        [
+            "event plain CHANNEL_CREATE",
+            "event plain CHANNEL_EXECUTE_COMPLETE CHANNEL_HANGUP CHANNEL_HANGUP_COMPLETE DTMF PLAYBACK_STOP RECORD_STOP",
+        ]
+    );
+}
@@ -0,0 +1,146 @@
+use rsesl::{
+    client::{Client, ClientConfig},
+    cluster::{Cluster, Nodes, Route, NODE_HEADER},
+    event::{EventData, EventHandler},
+    message::FormatType,
+    testing::MockFreeswitch,
+};
+use serde_json::json;
+use std::time::Duration;
+
+async fn node(name: &str, channels: u32) -> MockFreeswitch {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    let status = format!(
+        "UP 0 years, 0 days\nFreeSWITCH is ready\n{} session(s) - peak 9, last 5min 3\n",
+        channels
+    );
+    fs.api("status", &status).await;
+    fs.api("hostname", name).await;
+    fs
+}
+
+fn client(fs: &MockFreeswitch) -> Client {
+    let config = ClientConfig::new().command_timeout(Some(Duration::from_millis(200)));
+    Client::new(fs.addr().to_string(), "ClueCon".to_string()).config(config)
+}
+
+async fn wait_health(cluster: &Cluster, f: impl Fn(&Nodes) -> bool) {
+    let mut health = cluster.health();
+    tokio::time::timeout(Duration::from_secs(5), health.wait_for(|n| f(n)))
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+fn all_up(nodes: &Nodes) -> bool {
+    nodes.values().all(|h| h.up)
+}
+
+#[tokio::test]
+async fn routes_api_calls() {
+    let (fs1, fs2) = (node("fs1", 5).await, node("fs2", 2).await);
+    let cluster = Cluster::builder()
+        .node("fs1", client(&fs1))
+        .node("fs2", client(&fs2))
+        .health_interval(Duration::from_millis(100))
+        .start();
+    wait_health(&cluster, all_up).await;
+    assert_eq!(cluster.health().borrow()["fs1"].channels, Some(5));
+
+    let node = Route::Node("fs1".to_string());
+    assert_eq!(cluster.api(&node, "hostname").await.unwrap(), "fs1");
+    assert_eq!(
+        cluster
+            .api(&Route::LeastChannels, "hostname")
+            .await
+            .unwrap(),
+        "fs2"
+    );
+
+    let mut seen = vec![];
+    for _ in 0..4 {
+        seen.push(cluster.api(&Route::RoundRobin, "hostname").await.unwrap());
+    }
+    seen.sort();
+    assert_eq!(seen, vec!["fs1", "fs1", "fs2", "fs2"]);
+
+    let unknown = Route::Node("fs3".to_string());
+    assert!(cluster.api(&unknown, "hostname").await.is_err());
+}
+
+#[tokio::test]
+async fn merges_events_and_finds_channels() {
+    let (fs1, fs2) = (node("fs1", 0).await, node("fs2", 0).await);
+    let cluster = Cluster::builder()
+        .node("fs1", client(&fs1))
+        .node("fs2", client(&fs2))
+        .events(FormatType::Json, &["CHANNEL_CREATE", "CHANNEL_DESTROY"])
+        .health_interval(Duration::from_millis(100))
+        .owner_ttl(Duration::from_millis(200))
+        .start();
+    let mut rx = cluster.subscribe();
+    wait_health(&cluster, all_up).await;
+
+    let ed: EventData = json!({
+        "Event-Name": "CHANNEL_CREATE",
+        "Unique-ID": "abc-123",
+        "FreeSWITCH-Hostname": "fs2.example.com",
+        "Core-UUID": "core-2",
+    })
+    .as_object()
+    .unwrap()
+    .clone();
+    fs2.push_event(&ed, FormatType::Json).await;
+
+    let msg = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    let ed = msg.lock().await.event_data.clone().unwrap();
+    assert_eq!(ed.get_header(NODE_HEADER.to_string()), "fs2");
+    assert_eq!(ed.get_header("Unique-ID".to_string()), "abc-123");
+
+    let owner = Route::Uuid("abc-123".to_string());
+    assert_eq!(cluster.api(&owner, "hostname").await.unwrap(), "fs2");
+    let health = cluster.health().borrow()["fs2"].clone();
+    assert_eq!(health.hostname.as_deref(), Some("fs2.example.com"));
+    assert_eq!(health.core_uuid.as_deref(), Some("core-2"));
+
+    // a channel not seen in the events is looked for with uuid_exists
+    fs1.api("uuid_exists", "true").await;
+    fs2.api("uuid_exists", "false").await;
+    let other = Route::Uuid("def-456".to_string());
+    assert_eq!(cluster.api(&other, "hostname").await.unwrap(), "fs1");
+
+    // that answer is asked again after owner_ttl, the one from the events is kept
+    fs1.api("uuid_exists", "false").await;
+    fs2.api("uuid_exists", "true").await;
+    assert_eq!(cluster.api(&other, "hostname").await.unwrap(), "fs1");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(cluster.api(&other, "hostname").await.unwrap(), "fs2");
+    fs2.api("uuid_exists", "false").await;
+    assert_eq!(cluster.api(&owner, "hostname").await.unwrap(), "fs2");
+}
+
+#[tokio::test]
+async fn fails_over_unhealthy_nodes() {
+    let (fs1, fs2) = (node("fs1", 0).await, node("fs2", 0).await);
+    let cluster = Cluster::builder()
+        .node("fs1", client(&fs1))
+        .node("fs2", client(&fs2))
+        .health_interval(Duration::from_secs(60))
+        .start();
+    wait_health(&cluster, all_up).await;
+
+    // fs1 stops answering, the call times out and goes to fs2
+    fs1.mute(true);
+    for _ in 0..3 {
+        assert_eq!(
+            cluster.api(&Route::RoundRobin, "hostname").await.unwrap(),
+            "fs2"
+        );
+    }
+    assert!(!cluster.health().borrow()["fs1"].up);
+    let node = Route::Node("fs1".to_string());
+    assert!(cluster.api(&node, "hostname").await.is_err());
+}
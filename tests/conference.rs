@@ -0,0 +1,138 @@
+use rsesl::{
+    conference::{Conference, Member, Target, MAINTENANCE},
+    event::EventData,
+    message::{FormatType, MsgError},
+    testing::MockFreeswitch,
+};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+fn maintenance(action: &str, headers: Value) -> EventData {
+    let mut ed = json!({
+        "Event-Name": "CUSTOM",
+        "Event-Subclass": MAINTENANCE,
+        "Conference-Name": "3000",
+        "Action": action,
+    })
+    .as_object()
+    .unwrap()
+    .clone();
+    ed.extend(headers.as_object().unwrap().clone());
+    ed
+}
+
+fn member(id: u32, name: &str) -> Value {
+    json!({
+        "Member-ID": id.to_string(),
+        "Unique-ID": format!("uuid-{}", id),
+        "Caller-Caller-ID-Name": name,
+        "Caller-Caller-ID-Number": format!("10{:02}", id),
+        "Speak": "true",
+        "Hear": "true",
+        "Member-Type": if id == 1 { "moderator" } else { "member" },
+    })
+}
+
+async fn members_are(conf: &Conference, f: impl Fn(&[Member]) -> bool) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !f(&conf.live_members().await) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn commands_and_errors() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    fs.api("conference 3000 mute 2", "OK mute 2\n").await;
+    // a caller name is free text
+    fs.api(
+        "conference 3000 list",
+        "2;sofia/internal/1002;uuid-2;Lost and not found;1002;hear|speak;0;0;100\n",
+    )
+    .await;
+    fs.api("conference 3000 kick 9", "Non-Existant ID 9\n")
+        .await;
+    fs.api("conference 4000 list", "Conference 4000 not found\n")
+        .await;
+    fs.api("conference 3000 lock", "-ERR Conference is locked\n")
+        .await;
+    let (session, _shutdown) = fs.session().await.unwrap();
+
+    let conf = Conference::new(session.clone(), "3000");
+    conf.mute(Target::Id(2)).await.unwrap();
+    assert!(conf.list().await.unwrap().contains("Lost and not found"));
+    let err = conf.kick(Target::Id(9)).await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<MsgError>(),
+        Some(MsgError::MemberNotFound(id)) if id == "9"
+    ));
+    let err = conf.lock().await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<MsgError>(),
+        Some(MsgError::ErrResponse(r)) if r == "-ERR Conference is locked"
+    ));
+
+    let err = Conference::new(session, "4000").list().await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<MsgError>(),
+        Some(MsgError::ConferenceNotFound(name)) if name == "4000"
+    ));
+}
+
+#[tokio::test]
+async fn tracks_members() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    let list = json!([{
+        "conference_name": "3000",
+        "members": [{ "id": 1, "uuid": "uuid-1", "caller_id_name": "Alice",
+                      "flags": { "can_speak": true, "is_moderator": true } }],
+    }]);
+    fs.api("conference 3000 json_list", &list.to_string()).await;
+    let (session, _shutdown) = fs.session().await.unwrap();
+
+    let conf = Conference::new(session, "3000");
+    let task = conf.track().await.unwrap();
+    let members = conf.live_members().await;
+    assert_eq!(members.len(), 1);
+    assert!(members[0].flags.is_moderator);
+
+    let add = maintenance("add-member", member(2, "Bob"));
+    fs.push_event(&add, FormatType::Json).await;
+    members_are(&conf, |m| m.len() == 2).await;
+    assert_eq!(conf.live_members().await[1].caller_id_name, "Bob");
+
+    let floor = maintenance("floor-change", json!({ "New-ID": "2" }));
+    fs.push_event(&floor, FormatType::Json).await;
+    members_are(&conf, |m| m[1].flags.has_floor && !m[0].flags.has_floor).await;
+
+    // another room's events are not ours
+    let mut other = maintenance("add-member", member(3, "Carol"));
+    other.insert("Conference-Name".to_string(), json!("4000"));
+    fs.push_event(&other, FormatType::Json).await;
+    let del = maintenance("del-member", member(1, "Alice"));
+    fs.push_event(&del, FormatType::Json).await;
+    members_are(&conf, |m| m.len() == 1 && m[0].id == 2).await;
+
+    let destroy = maintenance("conference-destroy", json!({}));
+    fs.push_event(&destroy, FormatType::Json).await;
+    members_are(&conf, |m| m.is_empty()).await;
+    task.abort();
+}
+
+#[tokio::test]
+async fn tracks_an_empty_room() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    fs.api("conference 3000 json_list", "Conference 3000 not found\n")
+        .await;
+    let (session, _shutdown) = fs.session().await.unwrap();
+
+    let conf = Conference::new(session, "3000");
+    let _task = conf.track().await.unwrap();
+    assert!(conf.live_members().await.is_empty());
+    let add = maintenance("add-member", member(1, "Alice"));
+    fs.push_event(&add, FormatType::Json).await;
+    members_are(&conf, |m| m.len() == 1).await;
+}
@@ -0,0 +1,116 @@
+use rsesl::{
+    event::EventData,
+    gateway::{GatewayMonitor, Gateways, GATEWAY_ADD, GATEWAY_DELETE, GATEWAY_STATE},
+    message::FormatType,
+    sofia::{GatewayState, PingStatus},
+    testing::MockFreeswitch,
+};
+use serde_json::json;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::watch;
+
+const GATEWAYS: &str = r#"<gateways>
+  <gateway>
+    <name>carrier</name>
+    <profile>external</profile>
+    <state>REGED</state>
+    <status>UP</status>
+  </gateway>
+</gateways>
+"#;
+
+fn gateway_event(subclass: &str, name: &str, state: &str, ping: &str) -> EventData {
+    json!({
+        "Event-Name": "CUSTOM",
+        "Event-Subclass": subclass,
+        "Gateway": name,
+        "profile-name": "external",
+        "State": state,
+        "Ping-Status": ping,
+    })
+    .as_object()
+    .unwrap()
+    .clone()
+}
+
+async fn wait_for(rx: &mut watch::Receiver<Gateways>, f: impl Fn(&Gateways) -> bool) {
+    tokio::time::timeout(Duration::from_secs(5), rx.wait_for(|g| f(g)))
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn tracks_gateway_health() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    fs.api("sofia xmlstatus gateway", GATEWAYS).await;
+    let (session, _shutdown) = fs.session().await.unwrap();
+
+    let alerts = Arc::new(Mutex::new(vec![]));
+    let seen = alerts.clone();
+    let (mut rx, task) = GatewayMonitor::new(session.clone())
+        .threshold(Duration::from_millis(100))
+        .on_alert(move |gw| seen.lock().unwrap().push(gw.name.clone()))
+        .start()
+        .await
+        .unwrap();
+    {
+        let gws = rx.borrow();
+        assert_eq!(gws["carrier"].state, GatewayState::Reged);
+        assert!(gws["carrier"].is_up());
+    }
+
+    let down = gateway_event(GATEWAY_STATE, "carrier", "FAIL_WAIT", "DOWN");
+    fs.push_event(&down, FormatType::Json).await;
+    wait_for(&mut rx, |g| g["carrier"].alerted).await;
+    assert!(rx.borrow()["carrier"].down_since.is_some());
+    assert_eq!(*alerts.lock().unwrap(), vec!["carrier"]);
+
+    // an empty Ping-Status keeps the last one
+    let up = gateway_event(GATEWAY_STATE, "carrier", "REGED", "");
+    fs.push_event(&up, FormatType::Json).await;
+    wait_for(&mut rx, |g| g["carrier"].state == GatewayState::Reged).await;
+    assert_eq!(rx.borrow()["carrier"].ping_status, PingStatus::Down);
+    let up = gateway_event(GATEWAY_STATE, "carrier", "REGED", "UP");
+    fs.push_event(&up, FormatType::Json).await;
+    wait_for(&mut rx, |g| g["carrier"].is_up()).await;
+    assert!(!rx.borrow()["carrier"].alerted);
+
+    let add = gateway_event(GATEWAY_ADD, "backup", "", "");
+    fs.push_event(&add, FormatType::Json).await;
+    wait_for(&mut rx, |g| g.contains_key("backup")).await;
+    let delete = gateway_event(GATEWAY_DELETE, "backup", "", "");
+    fs.push_event(&delete, FormatType::Json).await;
+    wait_for(&mut rx, |g| !g.contains_key("backup")).await;
+
+    task.abort();
+    assert!(tokio::time::timeout(Duration::from_secs(5), rx.changed())
+        .await
+        .unwrap()
+        .is_err());
+}
+
+#[tokio::test]
+async fn keeps_the_event_format() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    fs.api("sofia xmlstatus gateway", GATEWAYS).await;
+    let (session, _shutdown) = fs.session().await.unwrap();
+    session
+        .event(FormatType::Plain, &["CHANNEL_CREATE"])
+        .await
+        .unwrap();
+
+    let (mut rx, _task) = GatewayMonitor::new(session.clone()).start().await.unwrap();
+    assert_eq!(session.event_format(), Some(FormatType::Plain));
+    let commands = fs.commands().await;
+    assert!(commands
+        .iter()
+        .any(|c| c.starts_with("event plain CUSTOM sofia::gateway_state")));
+
+    let down = gateway_event(GATEWAY_STATE, "carrier", "DOWN", "DOWN");
+    fs.push_event(&down, FormatType::Plain).await;
+    wait_for(&mut rx, |g| !g["carrier"].is_up()).await;
+}
@@ -0,0 +1,92 @@
+use hyper::{body, Body, Client, Method, Request, StatusCode};
+use rsesl::httapi::{Document, Httapi, HttapiRequest, Work};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::sync::Mutex;
+
+// counts the requests of each call
+fn httapi() -> Httapi<u32> {
+    Httapi::new(|req: HttapiRequest, state: Arc<Mutex<u32>>| async move {
+        if req.param("fail") == "true" {
+            return Err(anyhow::anyhow!("no script"));
+        }
+        let mut n = state.lock().await;
+        *n += 1;
+        let work = Work::playback("ivr/welcome.wav")
+            .name("digits")
+            .action("/next")
+            .bind("~\\d+#", Some("#"));
+        Ok(Document::new()
+            .variable("rsesl_count", &n.to_string())
+            .work(work))
+    })
+}
+
+async fn serve(h: Httapi<u32>) -> SocketAddr {
+    let server =
+        axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(h.router().into_make_service());
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+async fn post(addr: SocketAddr, form: &str) -> (StatusCode, String) {
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(format!("http://{}/", addr))
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(Body::from(form.to_string()))
+        .unwrap();
+    let res = Client::new().request(req).await.unwrap();
+    let status = res.status();
+    let bytes = body::to_bytes(res.into_body()).await.unwrap();
+    (status, String::from_utf8(bytes.to_vec()).unwrap())
+}
+
+fn request(session_id: &str, exiting: bool) -> HttapiRequest {
+    HttapiRequest::from(HashMap::from([
+        ("session_id".to_string(), session_id.to_string()),
+        ("exiting".to_string(), exiting.to_string()),
+    ]))
+}
+
+#[tokio::test]
+async fn keeps_state_per_call() {
+    let h = httapi();
+    let addr = serve(h.clone()).await;
+
+    let (status, xml) = post(addr, "session_id=a&variable_caller_id_number=1000").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(xml.contains("<rsesl_count>1</rsesl_count>"));
+    assert!(xml.contains("<playback file=\"ivr/welcome.wav\" name=\"digits\" action=\"/next\">"));
+    assert!(xml.contains("<bind strip=\"#\">~\\d+#</bind>"));
+
+    let (_, xml) = post(addr, "session_id=a").await;
+    assert!(xml.contains("<rsesl_count>2</rsesl_count>"));
+    let (_, xml) = post(addr, "session_id=b").await;
+    assert!(xml.contains("<rsesl_count>1</rsesl_count>"));
+    assert_eq!(h.sessions().await, 2);
+
+    post(addr, "session_id=a&exiting=true").await;
+    assert_eq!(h.sessions().await, 1);
+
+    let (status, body) = post(addr, "session_id=c&fail=true").await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body, "no script");
+}
+
+#[tokio::test]
+async fn drops_idle_calls() {
+    // the exiting request of `a` never comes
+    let h = httapi().ttl(Duration::from_millis(100));
+    h.handle(request("a", false)).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(150)).await;
+
+    h.handle(request("b", false)).await.unwrap();
+    assert_eq!(h.sessions().await, 1);
+    // a call that comes back starts over
+    let doc = h.handle(request("a", false)).await.unwrap();
+    assert_eq!(
+        doc.variables,
+        vec![("rsesl_count".to_string(), "1".to_string())]
+    );
+}
@@ -0,0 +1,233 @@
+use hyper::{body, Body, Client as HttpClient, Method, Request, StatusCode};
+use rsesl::{
+    event::EventData,
+    http::{router, router_with_auth, Authorize},
+    message::FormatType,
+    testing::MockFreeswitch,
+};
+use serde_json::{json, Value};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+async fn serve(router: axum::Router) -> SocketAddr {
+    let server =
+        axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(router.into_make_service());
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+async fn call(
+    addr: SocketAddr,
+    method: Method,
+    path: &str,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let req = Request::builder()
+        .method(method)
+        .uri(format!("http://{}{}", addr, path))
+        .header("content-type", "application/json")
+        .body(body.map_or_else(Body::empty, |b| Body::from(b.to_string())))
+        .unwrap();
+    let res = HttpClient::new().request(req).await.unwrap();
+    let status = res.status();
+    let bytes = body::to_bytes(res.into_body()).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+}
+
+#[tokio::test]
+async fn runs_commands() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    fs.api("status", "UP 0 years\n").await;
+    fs.api("originate", "+OK 7f4d-1\n").await;
+    let (session, _shutdown) = fs.session().await.unwrap();
+    let addr = serve(router(session).await.unwrap()).await;
+
+    let (status, v) = call(
+        addr,
+        Method::POST,
+        "/api",
+        Some(json!({ "command": "status" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(v["result"], "UP 0 years\n");
+
+    let (status, v) = call(
+        addr,
+        Method::POST,
+        "/api",
+        Some(json!({ "command": "nope" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(v["error"]["code"], "err_response");
+
+    let req = json!({
+        "url": "user/1000",
+        "destination": "9664",
+        "caller_id_name": "Alice",
+    });
+    let (status, v) = call(addr, Method::POST, "/originate", Some(req)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(v["uuid"], "7f4d-1");
+    assert!(fs
+        .commands()
+        .await
+        .contains(&"api originate {origination_caller_id_name='Alice'}user/1000 9664".to_string()));
+
+    // a `,` would start another variable, a `'` end the quoting
+    for vars in [
+        json!({ "a": "x,b=y" }),
+        json!({ "a": "it's" }),
+        json!({ "a,b": "x" }),
+    ] {
+        let req = json!({ "url": "user/1000", "destination": "9664", "variables": vars });
+        let (status, v) = call(addr, Method::POST, "/originate", Some(req)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(v["error"]["code"], "invalid_argument");
+    }
+
+    // whitespace would split the arguments, a line break end the command
+    let before = fs.commands().await.len();
+    for (path, req) in [
+        (
+            "/originate",
+            json!({ "url": "user/1000", "destination": "9664\n\napi status" }),
+        ),
+        (
+            "/originate",
+            json!({ "url": "user/1000", "destination": "9664", "dialplan": "XML", "context": "a b" }),
+        ),
+        (
+            "/channels/abc-123/hangup",
+            json!({ "cause": "NORMAL CLEARING" }),
+        ),
+        ("/channels/abc-123/transfer", json!({ "dest": "1000 XML" })),
+        (
+            "/channels/abc-123/playback",
+            json!({ "file": "/tmp/a b.wav" }),
+        ),
+        (
+            "/channels/abc%20123/playback",
+            json!({ "file": "/tmp/a.wav" }),
+        ),
+    ] {
+        let (status, v) = call(addr, Method::POST, path, Some(req)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", path);
+        assert_eq!(v["error"]["code"], "invalid_argument");
+    }
+    assert_eq!(fs.commands().await.len(), before);
+}
+
+#[tokio::test]
+async fn tracks_background_jobs() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    fs.api("status", "UP 0 years\n").await;
+    let (session, _shutdown) = fs.session().await.unwrap();
+    session
+        .event(FormatType::Plain, &["CHANNEL_CREATE"])
+        .await
+        .unwrap();
+    let addr = serve(router(session).await.unwrap()).await;
+    // the format in use is kept
+    assert!(fs
+        .commands()
+        .await
+        .contains(&"event plain BACKGROUND_JOB".to_string()));
+
+    let (status, v) = call(
+        addr,
+        Method::POST,
+        "/bgapi",
+        Some(json!({ "command": "status" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let job = format!("/bgapi/{}", v["job_uuid"].as_str().unwrap());
+
+    let mut result = Value::Null;
+    for _ in 0..50 {
+        let (status, v) = call(addr, Method::GET, &job, None).await;
+        assert_eq!(status, StatusCode::OK);
+        if v["status"] == "done" {
+            result = v["result"].clone();
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(result, "UP 0 years\n");
+
+    // a result is handed out once
+    let (status, v) = call(addr, Method::GET, &job, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(v["error"]["code"], "job_not_found");
+}
+
+#[tokio::test]
+async fn asks_authorize() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    fs.api("status", "UP 0 years\n").await;
+    let (session, _shutdown) = fs.session().await.unwrap();
+    let authorize: Authorize =
+        Arc::new(|h| h.get("authorization").is_some_and(|v| v == "Bearer secret"));
+    let addr = serve(router_with_auth(session, authorize).await.unwrap()).await;
+
+    let (status, v) = call(
+        addr,
+        Method::POST,
+        "/api",
+        Some(json!({ "command": "status" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(v["error"]["code"], "unauthorized");
+    assert!(!fs.commands().await.contains(&"api status".to_string()));
+
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(format!("http://{}/api", addr))
+        .header("content-type", "application/json")
+        .header("authorization", "Bearer secret")
+        .body(Body::from(json!({ "command": "status" }).to_string()))
+        .unwrap();
+    let res = HttpClient::new().request(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn streams_filtered_events() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    let (session, _shutdown) = fs.session().await.unwrap();
+    session.event(FormatType::Json, &["ALL"]).await.unwrap();
+    let addr = serve(router(session).await.unwrap()).await;
+
+    // a name the crate has no variant for is still matched as sent
+    let uri = format!("http://{}/events?event=XML_PRE_PROCESS", addr);
+    let res = HttpClient::new().get(uri.parse().unwrap()).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let mut body = res.into_body();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    for name in ["HEARTBEAT", "XML_PRE_PROCESS"] {
+        let ed: EventData = json!({ "Event-Name": name, "Unique-ID": "abc-123" })
+            .as_object()
+            .unwrap()
+            .clone();
+        fs.push_event(&ed, FormatType::Json).await;
+    }
+    let chunk = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let chunk = body::HttpBody::data(&mut body).await.unwrap().unwrap();
+            let text = String::from_utf8_lossy(&chunk).to_string();
+            if text.starts_with("event:") {
+                return text;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert!(chunk.starts_with("event:XML_PRE_PROCESS\n"), "{}", chunk);
+}
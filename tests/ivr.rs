@@ -0,0 +1,197 @@
+use rsesl::{
+    ivr::{Ivr, IvrRunner, Node, NodeEventKind, Outcome},
+    message::MsgError,
+    testing::MockFreeswitch,
+};
+use serde_json::json;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const JSON: &str = r#"{
+    "start": "main",
+    "nodes": {
+        "main": {
+            "type": "menu",
+            "prompt": "ivr/menu.wav",
+            "options": { "1": "sales", "2": "pin" },
+            "retries": 2
+        },
+        "sales": { "type": "transfer", "dest": "1000" },
+        "pin": {
+            "type": "collect",
+            "prompt": "ivr/pin.wav",
+            "var": "pin",
+            "min_digits": 4,
+            "max_digits": 4,
+            "next": "bye"
+        },
+        "bye": { "type": "hangup" }
+    }
+}"#;
+
+const TOML: &str = r#"
+start = "main"
+
+[nodes.main]
+type = "menu"
+prompt = "ivr/menu.wav"
+options = { "1" = "sales" }
+on_failure = "bye"
+
+[nodes.sales]
+type = "execute"
+app = "answer"
+next = "bye"
+
+[nodes.bye]
+type = "hangup"
+cause = "NORMAL_CLEARING"
+"#;
+
+async fn digits(fs: &MockFreeswitch, digits: &str) {
+    let done = json!({ "variable_rsesl_collected_digits": digits });
+    fs.app("play_and_get_digits", done.as_object().unwrap().clone())
+        .await;
+}
+
+#[test]
+fn loads_and_validates() {
+    let ivr = Ivr::from_json(JSON).unwrap();
+    assert_eq!(ivr.start, "main");
+    match &ivr.nodes["main"] {
+        Node::Menu {
+            max_digits,
+            terminators,
+            retries,
+            ..
+        } => {
+            assert_eq!(*max_digits, 1);
+            assert_eq!(terminators, "#");
+            assert_eq!(*retries, 2);
+        }
+        n => panic!("unexpected {:?}", n),
+    }
+
+    let ivr = Ivr::from_toml(TOML).unwrap();
+    assert!(matches!(&ivr.nodes["sales"], Node::Execute { app, .. } if app == "answer"));
+
+    // a transition to nowhere
+    let err = Ivr::from_json(&JSON.replace("\"next\": \"bye\"", "\"next\": \"gone\"")).unwrap_err();
+    assert!(err.to_string().contains("gone"));
+    assert!(Ivr::new("main").validate().is_err());
+
+    // play_and_get_digits would split these
+    let ivr = Ivr::new("pin").node(
+        "pin",
+        Node::Collect {
+            prompt: "ivr/pin.wav".to_string(),
+            invalid_prompt: None,
+            var: "pin".to_string(),
+            min_digits: 1,
+            max_digits: 4,
+            regex: Some("^1 2$".to_string()),
+            terminators: "#".to_string(),
+            timeout_ms: 1000,
+            digit_timeout_ms: 1000,
+            retries: 1,
+            next: None,
+            on_failure: None,
+        },
+    );
+    let err = ivr.validate().unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<MsgError>(),
+        Some(MsgError::InvalidArgument(_))
+    ));
+    let err = Ivr::from_json(&JSON.replace("ivr/menu.wav", "ivr/main menu.wav")).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<MsgError>(),
+        Some(MsgError::InvalidArgument(_))
+    ));
+}
+
+#[tokio::test]
+async fn walks_the_nodes() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    fs.api("uuid_transfer", "+OK\n").await;
+    digits(&fs, "1").await;
+    let (ch, _shutdown) = fs.channel("abc-123").await.unwrap();
+
+    let (tx, mut events) = mpsc::channel(100);
+    let runner = IvrRunner::new(Ivr::from_json(JSON).unwrap(), ch).events(tx);
+    let r = runner.run().await.unwrap();
+    assert_eq!(r.outcome, Outcome::Transferred("1000".to_string()));
+    assert_eq!(r.node, "sales");
+    assert!(fs
+        .commands()
+        .await
+        .contains(&"api uuid_transfer abc-123 1000".to_string()));
+
+    let mut kinds = vec![];
+    while let Ok(e) = events.try_recv() {
+        kinds.push((e.node, e.kind));
+    }
+    assert_eq!(
+        kinds,
+        vec![
+            ("main".to_string(), NodeEventKind::Enter),
+            ("main".to_string(), NodeEventKind::Digits("1".to_string())),
+            ("main".to_string(), NodeEventKind::Exit),
+            ("sales".to_string(), NodeEventKind::Enter),
+            ("sales".to_string(), NodeEventKind::Exit),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn runs_out_of_retries() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    digits(&fs, "9").await;
+    fs.app("answer", Default::default()).await;
+    fs.api("uuid_kill", "+OK\n").await;
+    let (ch, _shutdown) = fs.channel("abc-123").await.unwrap();
+
+    // an unknown option on every try, without on_failure
+    let r = IvrRunner::new(Ivr::from_json(JSON).unwrap(), ch.clone())
+        .run()
+        .await
+        .unwrap();
+    assert_eq!(r.outcome, Outcome::Failed);
+    assert_eq!(r.node, "main");
+
+    // with on_failure
+    let r = IvrRunner::new(Ivr::from_toml(TOML).unwrap(), ch)
+        .run()
+        .await
+        .unwrap();
+    assert_eq!(r.outcome, Outcome::Completed);
+    assert_eq!(r.node, "bye");
+    assert!(fs
+        .commands()
+        .await
+        .contains(&"api uuid_kill abc-123 NORMAL_CLEARING".to_string()));
+}
+
+#[tokio::test]
+async fn closed_session_is_not_a_hangup() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    let (ch, _shutdown) = fs.channel("abc-123").await.unwrap();
+
+    // play_and_get_digits never completes, the connection goes away instead
+    let run = tokio::spawn(async move {
+        IvrRunner::new(Ivr::from_json(JSON).unwrap(), ch)
+            .run()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    fs.close().await;
+    let err = tokio::time::timeout(Duration::from_secs(5), run)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<MsgError>(),
+        Some(MsgError::ConnectionClosed)
+    ));
+}
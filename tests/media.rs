@@ -0,0 +1,126 @@
+use rsesl::{
+    channel::ChannelHandle,
+    event::EventData,
+    media::{Leg, MediaEnd},
+    message::{FormatType, MsgError},
+    testing::MockFreeswitch,
+};
+use serde_json::json;
+use std::time::Duration;
+
+fn playback_stop(path: &str) -> EventData {
+    json!({
+        "Event-Name": "PLAYBACK_STOP",
+        "Unique-ID": "abc-123",
+        "Playback-File-Path": path,
+        "Playback-Status": "done",
+    })
+    .as_object()
+    .unwrap()
+    .clone()
+}
+
+#[tokio::test]
+async fn playback_finishes_on_stop() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    let mut other = playback_stop("/tmp/hello.wav");
+    other.insert("Unique-ID".to_string(), json!("def-456"));
+    let mut stop = playback_stop("/usr/share/freeswitch/sounds/hello.wav");
+    stop.insert("Playback-Status".to_string(), json!("break"));
+    // the sound prefix is prepended to relative paths
+    fs.app_events("playback", vec![other, stop]).await;
+    fs.app("playback", Default::default()).await;
+    let (ch, _shutdown) = fs.channel("abc-123").await.unwrap();
+
+    let r = tokio::time::timeout(Duration::from_secs(5), ch.playback("hello.wav"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(r.end, MediaEnd::Stopped);
+    assert_eq!(r.path, "hello.wav");
+}
+
+#[tokio::test]
+async fn broadcast_matches_the_path_suffix() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    fs.api("uuid_broadcast", "+OK Message queued").await;
+    let (ch, _shutdown) = fs.channel("abc-123").await.unwrap();
+
+    let play = tokio::spawn({
+        let ch = ch.clone();
+        async move { ch.broadcast("abc-123", "hello.wav", Leg::A).await }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    fs.push_event(&playback_stop("/tmp/other.wav"), FormatType::Json)
+        .await;
+    fs.push_event(
+        &playback_stop("/usr/share/freeswitch/sounds/hello.wav"),
+        FormatType::Json,
+    )
+    .await;
+    let r = tokio::time::timeout(Duration::from_secs(5), play)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(r.end, MediaEnd::Finished);
+}
+
+#[tokio::test]
+async fn displace_args() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    fs.app("displace_session", Default::default()).await;
+    let (ch, _shutdown) = fs.channel("abc-123").await.unwrap();
+
+    ch.displace("/tmp/music.wav", Some(Duration::from_secs(30)))
+        .await
+        .unwrap();
+    ch.displace("/tmp/music.wav", None).await.unwrap();
+    let args: Vec<String> = fs
+        .commands()
+        .await
+        .iter()
+        .filter_map(|c| {
+            c.lines()
+                .find_map(|l| l.strip_prefix("execute-app-arg: "))
+                .map(str::to_string)
+        })
+        .collect();
+    assert_eq!(args, ["/tmp/music.wav m +30", "/tmp/music.wav m"]);
+}
+
+#[tokio::test]
+async fn failed_playback_is_an_error() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    let failed = json!({ "Application-Response": "FILE NOT FOUND" });
+    fs.app("playback", failed.as_object().unwrap().clone())
+        .await;
+    let (ch, _shutdown) = fs.channel("abc-123").await.unwrap();
+
+    // no PLAYBACK_STOP comes for a missing file
+    let err = tokio::time::timeout(Duration::from_secs(5), ch.playback("/tmp/missing.wav"))
+        .await
+        .unwrap()
+        .unwrap_err();
+    match err.downcast_ref::<MsgError>() {
+        Some(MsgError::ErrResponse(r)) => assert_eq!(r, "FILE NOT FOUND"),
+        e => panic!("unexpected {:?}", e),
+    }
+}
+
+#[tokio::test]
+async fn playback_subscribes_its_events() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    fs.app_events("playback", vec![playback_stop("/tmp/hello.wav")])
+        .await;
+    fs.app("playback", Default::default()).await;
+    let (session, _shutdown) = fs.session().await.unwrap();
+
+    // nothing was subscribed to before
+    let ch = ChannelHandle::new(session, "abc-123");
+    let r = tokio::time::timeout(Duration::from_secs(5), ch.playback("/tmp/hello.wav"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(r.end, MediaEnd::Finished);
+}
@@ -0,0 +1,200 @@
+use proptest::prelude::*;
+use rsesl::{
+    event::EventData,
+    message::{Message, MsgError, MAX_BODY_LEN, MAX_HEADER_LEN},
+};
+use serde_json::Value;
+use std::collections::HashMap;
+
+fn block_on<F: std::future::Future>(f: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+        .block_on(f)
+}
+
+fn parse_one(data: &[u8]) -> anyhow::Result<Message> {
+    let mut r = data;
+    block_on(Message::parse(&mut r))
+}
+
+fn msg_error(r: anyhow::Result<Message>) -> MsgError {
+    r.unwrap_err().downcast::<MsgError>().unwrap()
+}
+
+fn header_name() -> impl Strategy<Value = String> {
+    "[A-Za-z][A-Za-z0-9_-]{0,15}"
+        .prop_filter("reserved", |k| k != "Content-Length" && k != "Content-Type")
+}
+
+// parse_header_line trims values
+fn header_value() -> impl Strategy<Value = String> {
+    "[!-~]([ -~]{0,20}[!-~])?"
+}
+
+fn headers(content_type: &str) -> impl Strategy<Value = HashMap<String, String>> {
+    let content_type = content_type.to_string();
+    prop::collection::hash_map(header_name(), header_value(), 0..8).prop_map(move |mut h| {
+        h.insert("Content-Type".to_string(), content_type.clone());
+        h
+    })
+}
+
+fn event_data(keys: impl Strategy<Value = String>) -> impl Strategy<Value = EventData> {
+    prop::collection::hash_map(keys, any::<String>(), 1..10)
+        .prop_map(|m| m.into_iter().map(|(k, v)| (k, Value::String(v))).collect())
+}
+
+proptest! {
+    #[test]
+    fn parse_never_panics(data in prop::collection::vec(any::<u8>(), 0..2048)) {
+        let mut r = data.as_slice();
+        block_on(async {
+            while !r.is_empty() {
+                let _ = Message::parse(&mut r).await;
+            }
+        });
+    }
+
+    #[test]
+    fn parse_never_panics_on_frames(
+        ct in prop::sample::select(vec![
+            "text/event-json", "text/event-plain", "text/disconnect-notice",
+            "command/reply", "api/response", "auth/request", "log/data", "text/event-xml",
+        ]),
+        len in 0usize..600,
+        body in prop::collection::vec(any::<u8>(), 0..512),
+    ) {
+        let mut frame = format!("Content-Type: {}\nContent-Length: {}\n\n", ct, len).into_bytes();
+        frame.extend(body);
+        let mut r = frame.as_slice();
+        block_on(async {
+            while !r.is_empty() {
+                let _ = Message::parse(&mut r).await;
+            }
+        });
+    }
+
+    #[test]
+    fn raw_body_round_trip(
+        ct in prop::sample::select(vec!["api/response", "log/data"]),
+        body in ".+",
+        h in headers("api/response"),
+    ) {
+        let mut h = h;
+        h.insert("Content-Type".to_string(), ct.to_string());
+        let msg = Message { header: Some(h.clone()), event_data: None, body: Some(body.clone()) };
+
+        let parsed = parse_one(msg.to_string().as_bytes()).unwrap();
+        h.insert("Content-Length".to_string(), body.len().to_string());
+        prop_assert_eq!(parsed.header, Some(h));
+        prop_assert_eq!(parsed.body, Some(body));
+    }
+
+    #[test]
+    fn json_event_round_trip(ed in event_data(".*")) {
+        let h = HashMap::from([("Content-Type".to_string(), "text/event-json".to_string())]);
+        let msg = Message { header: Some(h), event_data: Some(ed.clone()), body: None };
+
+        let parsed = parse_one(msg.to_string().as_bytes()).unwrap();
+        prop_assert_eq!(parsed.event_data, Some(ed));
+    }
+
+    #[test]
+    fn plain_event_round_trip(ed in event_data(header_name()), body in prop::option::of(".+")) {
+        let mut ed = ed;
+        if let Some(b) = &body {
+            ed.insert("_body".to_string(), Value::String(b.clone()));
+        }
+        let h = HashMap::from([("Content-Type".to_string(), "text/event-plain".to_string())]);
+        let msg = Message { header: Some(h), event_data: Some(ed.clone()), body: None };
+
+        let mut parsed = parse_one(msg.to_string().as_bytes()).unwrap().event_data.unwrap();
+        parsed.remove("Content-Length");
+        prop_assert_eq!(parsed, ed);
+    }
+}
+
+#[test]
+fn huge_content_length_is_skipped() {
+    let frame = format!(
+        "Content-Type: api/response\nContent-Length: {}\n\nshort",
+        MAX_BODY_LEN + 1
+    );
+    let e = msg_error(parse_one(frame.as_bytes()));
+    assert!(matches!(e, MsgError::BodyTooLarge(n) if n == MAX_BODY_LEN + 1));
+}
+
+#[test]
+fn truncated_body_is_connection_closed() {
+    let frame = "Content-Type: api/response\nContent-Length: 100\n\nshort";
+    let e = msg_error(parse_one(frame.as_bytes()));
+    assert!(matches!(e, MsgError::ConnectionClosed));
+}
+
+#[test]
+fn endless_header_line() {
+    let frame = vec![b'a'; MAX_HEADER_LEN + 10];
+    let e = msg_error(parse_one(&frame));
+    assert!(matches!(e, MsgError::HeaderTooLarge));
+}
+
+#[test]
+fn invalid_utf8_keeps_stream_in_step() {
+    let mut data = b"Content-Type: log/data\nContent-Length: 2\n\n\xff\xfe".to_vec();
+    data.extend(b"Content-Type: api/response\nContent-Length: 2\n\nok");
+    let mut r = data.as_slice();
+    block_on(async {
+        let e = Message::parse(&mut r).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<MsgError>(),
+            Some(MsgError::InvalidUtf8)
+        ));
+        let msg = Message::parse(&mut r).await.unwrap();
+        assert_eq!(msg.body.as_deref(), Some("ok"));
+    });
+}
+
+#[test]
+fn xml_event() {
+    let body = "<event>\n  <headers>\n    <Event-Name>CUSTOM</Event-Name>\n    <Event-Subclass>sofia::register</Event-Subclass>\n    <from-user>1000</from-user>\n    <user-agent>Zoiper &amp; co &lt;2.0&gt;</user-agent>\n    <contact/>\n  </headers>\n  <body>hello\nworld</body>\n</event>";
+    let frame = format!(
+        "Content-Length: {}\nContent-Type: text/event-xml\n\n{}",
+        body.len(),
+        body
+    );
+    let ed = parse_one(frame.as_bytes()).unwrap().event_data.unwrap();
+    let get = |k: &str| ed.get(k).and_then(|v| v.as_str()).unwrap();
+    assert_eq!(get("Event-Subclass"), "sofia::register");
+    assert_eq!(get("user-agent"), "Zoiper & co <2.0>");
+    assert_eq!(get("contact"), "");
+    assert_eq!(get("_body"), "hello\nworld");
+    assert_eq!(ed.len(), 6);
+
+    let frame = "Content-Length: 12\nContent-Type: text/event-xml\n\n<event><head";
+    let e = msg_error(parse_one(frame.as_bytes()));
+    assert!(matches!(e, MsgError::BodyParseFailed));
+}
+
+#[test]
+fn invalid_utf8_header_keeps_content_length() {
+    // the bad line comes before Content-Length, whose body must still be skipped
+    let mut data =
+        b"X-Name: \xff\xfe\nContent-Type: api/response\nContent-Length: 7\n\n+OK bye".to_vec();
+    data.extend(b"Content-Type: api/response\nContent-Length: 2\n\nok");
+    let mut r = data.as_slice();
+    block_on(async {
+        let e = Message::parse(&mut r).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<MsgError>(),
+            Some(MsgError::InvalidUtf8)
+        ));
+        let msg = Message::parse(&mut r).await.unwrap();
+        assert_eq!(msg.body.as_deref(), Some("ok"));
+    });
+
+    // a Content-Length that can't be read leaves the end of the frame unknown
+    let frame = b"Content-Length: 2\xff\nContent-Type: api/response\n\nno";
+    let e = msg_error(parse_one(frame));
+    assert!(matches!(e, MsgError::ReadFailed));
+}
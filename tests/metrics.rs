@@ -0,0 +1,69 @@
+use rsesl::{
+    client::Client,
+    event::EventData,
+    message::{FormatType, Message},
+    metrics::{gather, metrics, track},
+    testing::MockFreeswitch,
+};
+use serde_json::json;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::{broadcast, Mutex};
+
+fn event(name: &str, uuid: &str) -> EventData {
+    json!({ "Event-Name": name, "Unique-ID": uuid, "Other-Leg-Unique-ID": "b-leg" })
+        .as_object()
+        .unwrap()
+        .clone()
+}
+
+async fn wait_for(f: impl Fn() -> bool) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !f() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn counts_frames_commands_and_calls() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    fs.api("status", "UP 0 years, 0 days\n").await;
+    let (tx, rx) = broadcast::channel::<Arc<Mutex<Message>>>(100);
+    let (_shutdown, signal) = broadcast::channel(1);
+    let client = Client::new(fs.addr().to_string(), "ClueCon".to_string());
+    let session = client.connect(tx, signal).await.unwrap();
+    let tracker = track(rx);
+
+    session.api("status").await.unwrap();
+    let m = metrics();
+    let status = m.command_latency.with_label_values(&["api status"]);
+    assert_eq!(status.get_sample_count(), 1);
+    assert_eq!(m.frames.with_label_values(&["auth/request"]).get(), 1);
+    assert_eq!(m.frames.with_label_values(&["api/response"]).get(), 1);
+
+    let events = ["CHANNEL_CREATE", "CHANNEL_BRIDGE", "CHANNEL_DESTROY"];
+    session.event(FormatType::Json, &events).await.unwrap();
+    fs.push_event(&event("CHANNEL_CREATE", "a-leg"), FormatType::Json)
+        .await;
+    fs.push_event(&event("CHANNEL_CREATE", "b-leg"), FormatType::Json)
+        .await;
+    fs.push_event(&event("CHANNEL_BRIDGE", "a-leg"), FormatType::Json)
+        .await;
+    wait_for(|| m.channels.get() == 2 && m.calls.get() == 1).await;
+
+    fs.push_event(&event("CHANNEL_DESTROY", "a-leg"), FormatType::Json)
+        .await;
+    wait_for(|| m.channels.get() == 1 && m.calls.get() == 0).await;
+    assert_eq!(m.events.with_label_values(&["CHANNEL_CREATE"]).get(), 2);
+
+    let text = gather();
+    assert!(text.contains("esl_frames_total{content_type=\"text/event-json\"} 4"));
+    assert!(text.contains("esl_command_duration_seconds_count{command=\"api status\"} 1"));
+    assert!(text.contains("esl_channels 1"));
+
+    // what a tracker counted goes with it
+    tracker.abort();
+    wait_for(|| m.channels.get() == 0).await;
+}
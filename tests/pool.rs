@@ -0,0 +1,105 @@
+use rsesl::{client::Client, message::MsgError, pool::EslPool, testing::MockFreeswitch};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+async fn mock() -> MockFreeswitch {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    fs.api("status", "UP 0 years, 0 days\n").await;
+    fs.api("uuid_exists", "true").await;
+    fs.delay("uuid_exists", Duration::from_millis(200)).await;
+    fs
+}
+
+fn client(fs: &MockFreeswitch) -> Client {
+    Client::new(fs.addr().to_string(), "ClueCon".to_string())
+}
+
+#[tokio::test]
+async fn spreads_api_calls() {
+    let fs = mock().await;
+    let pool = EslPool::builder(client(&fs))
+        .min(1)
+        .max(4)
+        .start()
+        .await
+        .unwrap();
+    assert_eq!(pool.size(), 1);
+
+    let start = Instant::now();
+    let cmds: Vec<String> = (0..4).map(|i| format!("uuid_exists {}", i)).collect();
+    let results = pool.api_all(&cmds).await;
+    // one connection would take 800ms
+    assert!(start.elapsed() < Duration::from_millis(600));
+    assert!(results.iter().all(|r| r.as_deref().unwrap() == "true"));
+    assert_eq!(pool.size(), 4);
+    assert_eq!(pool.idle(), 4);
+    assert_eq!(fs.connections().await, 4);
+}
+
+#[tokio::test]
+async fn waits_in_turn() {
+    let fs = mock().await;
+    let pool = EslPool::builder(client(&fs))
+        .max(1)
+        .checkout_timeout(Duration::from_millis(100))
+        .start()
+        .await
+        .unwrap();
+
+    let held = pool.get().await.unwrap();
+    let err = pool.get().await.err().unwrap();
+    assert!(matches!(
+        err.downcast_ref::<MsgError>(),
+        Some(MsgError::Timeout(_))
+    ));
+
+    let pool = EslPool::builder(client(&fs)).max(1).start().await.unwrap();
+    let held2 = pool.get().await.unwrap();
+    let order = Arc::new(Mutex::new(vec![]));
+    let mut waiters = vec![];
+    for i in 0..3 {
+        let (pool, order) = (pool.clone(), order.clone());
+        waiters.push(tokio::spawn(async move {
+            let s = pool.get().await.unwrap();
+            order.lock().await.push(i);
+            s.api("status").await.unwrap();
+        }));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    drop(held2);
+    for w in waiters {
+        w.await.unwrap();
+    }
+    assert_eq!(*order.lock().await, vec![0, 1, 2]);
+    assert_eq!(pool.size(), 1);
+    drop(held);
+}
+
+#[tokio::test]
+async fn evicts_idle_and_replaces_dead() {
+    let fs = mock().await;
+    let pool = EslPool::builder(client(&fs))
+        .min(1)
+        .max(3)
+        .idle_timeout(Duration::from_millis(100))
+        .health_interval(Duration::from_millis(50))
+        .start()
+        .await
+        .unwrap();
+
+    let cmds = ["uuid_exists a", "uuid_exists b", "uuid_exists c"];
+    assert!(pool.api_all(&cmds).await.iter().all(|r| r.is_ok()));
+    assert_eq!(pool.size(), 3);
+
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(pool.size(), 1);
+
+    // FreeSWITCH drops every connection, a new one is opened
+    fs.close().await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(pool.api("status").await.unwrap(), "UP 0 years, 0 days\n");
+    assert_eq!(pool.size(), 1);
+}
@@ -0,0 +1,135 @@
+use rsesl::{
+    client::Client,
+    event::EventData,
+    message::{ContentType, FormatType, Message},
+    record::{Direction, Pace, Recorder, Replayer},
+    testing::MockFreeswitch,
+};
+use serde_json::json;
+use std::{path::PathBuf, sync::Arc, time::Duration};
+use tokio::sync::{broadcast, Mutex};
+
+type Rx = broadcast::Receiver<Arc<Mutex<Message>>>;
+
+fn event(name: &str, n: u32) -> EventData {
+    json!({ "Event-Name": name, "Unique-ID": format!("call-{}", n) })
+        .as_object()
+        .unwrap()
+        .clone()
+}
+
+async fn events(rx: &mut Rx, n: usize) -> Vec<EventData> {
+    let mut out = vec![];
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while out.len() < n {
+            let msg = rx.recv().await.unwrap();
+            let msg = msg.lock().await;
+            if msg.content_type() == Some(ContentType::TextEventJson) {
+                out.push(msg.event_data.clone().unwrap());
+            }
+        }
+    })
+    .await
+    .unwrap();
+    out
+}
+
+async fn recording(path: &PathBuf, frames: usize) -> Replayer {
+    for _ in 0..100 {
+        let r = Replayer::open(path, Pace::Fast).await.unwrap();
+        if r.frames().len() >= frames {
+            return r;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("recording incomplete");
+}
+
+#[tokio::test]
+async fn record_and_replay() {
+    let path = std::env::temp_dir().join(format!("rsesl-record-{}.jsonl", std::process::id()));
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    fs.api("status", "UP\n").await;
+
+    let (tx, mut rx) = broadcast::channel(100);
+    let (_shutdown, signal) = broadcast::channel(1);
+    let client = Client::new(fs.addr().to_string(), "ClueCon".to_string())
+        .record(Recorder::create(&path).await.unwrap());
+    let session = client.new_session(tx, signal).await.unwrap();
+    session.auth("ClueCon").await.unwrap();
+    session.api("status").await.unwrap();
+    for n in 0..3 {
+        fs.push_event(&event("CHANNEL_CREATE", n), FormatType::Json)
+            .await;
+    }
+    let live = events(&mut rx, 3).await;
+
+    // auth/request, auth, its reply, api, its response, 3 events
+    let replayer = recording(&path, 8).await;
+    let _ = std::fs::remove_file(&path);
+    let out: Vec<&str> = replayer
+        .frames()
+        .iter()
+        .filter(|f| f.dir == Direction::Out)
+        .map(|f| f.data.as_str())
+        .collect();
+    assert_eq!(out, ["auth ClueCon\n\n", "api status\n\n"]);
+    let types: Vec<String> = replayer.incoming().map(|f| f.content_type()).collect();
+    assert_eq!(
+        types[..3],
+        ["auth/request", "command/reply", "api/response"]
+    );
+
+    // straight through Message::parse
+    let (tx, mut rx) = broadcast::channel(100);
+    let done = replayer.clone().play(tx);
+    assert_eq!(events(&mut rx, 3).await, live);
+    done.await.unwrap().unwrap();
+
+    // through the mock into another session
+    let (tx, mut rx) = broadcast::channel(100);
+    let (_shutdown2, signal) = broadcast::channel(1);
+    let client = Client::new(fs.addr().to_string(), "ClueCon".to_string());
+    let session = client.new_session(tx, signal).await.unwrap();
+    session.auth("ClueCon").await.unwrap();
+    fs.replay(&replayer).await;
+    assert_eq!(events(&mut rx, 3).await, live);
+}
+
+#[tokio::test]
+async fn keeps_invalid_utf8_bytes() {
+    let path = std::env::temp_dir().join(format!("rsesl-record-raw-{}.jsonl", std::process::id()));
+    let raw = b"Content-Type: log/data\nContent-Length: 2\n\n\xff\xfe".to_vec();
+    let recorder = Recorder::create(&path).await.unwrap();
+    recorder.record(Direction::In, b"api status\n\n");
+    recorder.record(Direction::In, &raw);
+
+    let replayer = recording(&path, 2).await;
+    let lines = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let frames = replayer.frames();
+    assert!(!frames[0].hex);
+    assert_eq!(frames[0].data, "api status\n\n");
+    assert!(!lines.lines().next().unwrap().contains("\"hex\""));
+    // byte for byte, not with replacement characters
+    assert!(frames[1].hex);
+    assert_eq!(frames[1].bytes().unwrap(), raw);
+    assert_eq!(frames[1].content_type(), "log/data");
+}
+
+#[tokio::test]
+async fn drops_frames_over_the_queue() {
+    let path = std::env::temp_dir().join(format!("rsesl-record-full-{}.jsonl", std::process::id()));
+    let recorder = Recorder::create(&path).await.unwrap();
+    // the writer gets no chance to run in between
+    for _ in 0..10_005 {
+        recorder.record(Direction::In, b"api status\n\n");
+    }
+    assert_eq!(recorder.dropped(), 5);
+
+    // the rest is written once the recorder is dropped
+    drop(recorder);
+    let replayer = recording(&path, 10_000).await;
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(replayer.frames().len(), 10_000);
+}
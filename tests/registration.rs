@@ -0,0 +1,128 @@
+use rsesl::{
+    event::EventData,
+    message::FormatType,
+    registration::{RegistrationRegistry, EXPIRE, REGISTER, UNREGISTER},
+    testing::MockFreeswitch,
+};
+use serde_json::{json, Value};
+use std::{
+    future::Future,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn event(subclass: &str, headers: Value) -> EventData {
+    let mut ed = json!({ "Event-Name": "CUSTOM", "Event-Subclass": subclass })
+        .as_object()
+        .unwrap()
+        .clone();
+    ed.extend(headers.as_object().unwrap().clone());
+    ed
+}
+
+async fn eventually<F: Future<Output = bool>>(f: impl Fn() -> F) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !f().await {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn follows_registrations() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    let rows = json!({
+        "row_count": 1,
+        "rows": [{
+            "reg_user": "1000",
+            "realm": "example.com",
+            "token": "call-1",
+            "url": "sofia/internal/sip:1000@1.2.3.5:5060",
+            "expires": (now() + 3600).to_string(),
+            "network_ip": "1.2.3.5",
+            "network_port": "5060",
+        }],
+    });
+    fs.api("show registrations as json", &rows.to_string())
+        .await;
+    let (session, _shutdown) = fs.session().await.unwrap();
+
+    let registry = RegistrationRegistry::new(session);
+    let task = registry.start().await.unwrap();
+    assert!(registry.is_reachable("1000@example.com").await);
+    assert!(!registry.is_reachable("1001@example.com").await);
+    assert_eq!(
+        registry.contacts("1000@example.com").await[0].network_port,
+        "5060"
+    );
+
+    let register = event(
+        REGISTER,
+        json!({
+            "from-user": "1001",
+            "from-host": "example.com",
+            "call-id": "call-2",
+            "profile-name": "internal",
+            "contact": "<sip:1001@1.2.3.6:5060>",
+            "user-agent": "Phone/1.0",
+            "expires": "60",
+        }),
+    );
+    fs.push_event(&register, FormatType::Json).await;
+    eventually(|| registry.is_reachable("1001@example.com")).await;
+    let c = registry.contacts("1001@example.com").await.remove(0);
+    assert_eq!(c.aor(), "1001@example.com");
+    assert_eq!(c.user_agent, "Phone/1.0");
+    assert!(c.expires_at >= now() + 59);
+
+    // sofia::expire names the user with user/host
+    let expire = event(
+        EXPIRE,
+        json!({ "user": "1000", "host": "example.com", "call-id": "call-1" }),
+    );
+    fs.push_event(&expire, FormatType::Json).await;
+    eventually(|| async { !registry.is_reachable("1000@example.com").await }).await;
+
+    let unregister = event(
+        UNREGISTER,
+        json!({ "from-user": "1001", "from-host": "example.com", "call-id": "call-2" }),
+    );
+    fs.push_event(&unregister, FormatType::Json).await;
+    eventually(|| async { registry.all().await.is_empty() }).await;
+
+    // resync replaces what the events said
+    registry.resync().await.unwrap();
+    assert_eq!(registry.all().await.len(), 1);
+    task.abort();
+}
+
+#[tokio::test]
+async fn drops_expired_contacts() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    fs.api("show registrations as json", r#"{"row_count":0}"#)
+        .await;
+    let (session, _shutdown) = fs.session().await.unwrap();
+    let registry = RegistrationRegistry::new(session);
+    let _task = registry.start().await.unwrap();
+
+    let register = event(
+        REGISTER,
+        json!({
+            "from-user": "1000",
+            "from-host": "example.com",
+            "call-id": "call-1",
+            "expires": "2",
+        }),
+    );
+    fs.push_event(&register, FormatType::Json).await;
+    eventually(|| registry.is_reachable("1000@example.com")).await;
+    eventually(|| async { registry.all().await.is_empty() }).await;
+}
@@ -0,0 +1,409 @@
+use rsesl::{
+    channel::ChannelHandle,
+    client::{Client, ClientConfig},
+    event::{Event, EventData, EventHandler},
+    liveness::Liveness,
+    message::{ContentType, FormatType, Message, MsgError},
+    server::Server,
+    session::{CloseReason, Session},
+    testing::MockFreeswitch,
+};
+use serde_json::json;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::{broadcast, Mutex};
+
+type Rx = broadcast::Receiver<Arc<Mutex<Message>>>;
+
+// the shutdown sender has to outlive the session
+async fn connect(fs: &MockFreeswitch, pwd: &str) -> (Session, Rx, broadcast::Sender<bool>) {
+    let (tx, rx) = broadcast::channel(100);
+    let (shutdown, signal) = broadcast::channel(1);
+    let client = Client::new(fs.addr().to_string(), pwd.to_string());
+    let session = client.new_session(tx, signal).await.unwrap();
+    (session, rx, shutdown)
+}
+
+async fn next(rx: &mut Rx, ct: ContentType) -> Message {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let msg = rx.recv().await.unwrap();
+            let msg = msg.lock().await;
+            if msg.content_type() == Some(ct.clone()) {
+                return msg.clone();
+            }
+        }
+    })
+    .await
+    .unwrap()
+}
+
+fn event(name: &str) -> EventData {
+    json!({ "Event-Name": name, "Unique-ID": "abc-123", "Caller-Caller-ID-Number": "1000 2" })
+        .as_object()
+        .unwrap()
+        .clone()
+}
+
+#[tokio::test]
+async fn auth_and_api() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    fs.api("status", "UP 0 years, 0 days\n").await;
+    let (session, mut rx, _shutdown) = connect(&fs, "ClueCon").await;
+
+    next(&mut rx, ContentType::AuthRequest).await;
+    session.auth("ClueCon").await.unwrap();
+    assert_eq!(session.api("status").await.unwrap(), "UP 0 years, 0 days\n");
+
+    let err = session.api("nope").await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<MsgError>(),
+        Some(MsgError::ErrResponse(_))
+    ));
+    assert_eq!(
+        fs.commands().await,
+        vec!["auth ClueCon", "api status", "api nope"]
+    );
+}
+
+#[tokio::test]
+async fn rejects_line_breaks() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    let (session, mut rx, _shutdown) = connect(&fs, "ClueCon").await;
+    next(&mut rx, ContentType::AuthRequest).await;
+    session.auth("ClueCon").await.unwrap();
+
+    // the rest would be read as another command
+    let errs = [
+        session.api("status\n\nexit").await.unwrap_err(),
+        session.bgapi("status\r\nexit").await.unwrap_err(),
+        session.bgapi_job("status", "abc\nexit").await.unwrap_err(),
+        session.command("event json ALL\n\nexit").await.unwrap_err(),
+        session.request("api a\nb".to_string()).await.unwrap_err(),
+    ];
+    for err in errs {
+        assert!(matches!(
+            err.downcast_ref::<MsgError>(),
+            Some(MsgError::InvalidArgument(_))
+        ));
+    }
+    assert_eq!(fs.commands().await, vec!["auth ClueCon"]);
+}
+
+#[tokio::test]
+async fn wrong_password() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    let (session, mut rx, _shutdown) = connect(&fs, "wrong").await;
+
+    assert!(session.auth("wrong").await.is_err());
+    next(&mut rx, ContentType::TextDisconnectNotice).await;
+    assert_eq!(session.closed().await, CloseReason::AuthFailed);
+}
+
+#[tokio::test]
+async fn bgapi_background_job() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    fs.api("originate", "+OK 1234\n").await;
+    let (session, mut rx, _shutdown) = connect(&fs, "ClueCon").await;
+    session.auth("ClueCon").await.unwrap();
+    session
+        .event(FormatType::Json, &["BACKGROUND_JOB"])
+        .await
+        .unwrap();
+
+    let job_uuid = session.bgapi("originate user/1000 &park").await.unwrap();
+    let msg = next(&mut rx, ContentType::TextEventJson).await;
+    let ed = msg.event_data.unwrap();
+    assert_eq!(ed.event(), Event::BackgroundJob);
+    assert_eq!(ed.get_header("Job-UUID".to_string()), job_uuid);
+    assert_eq!(ed.get_header("_body".to_string()), "+OK 1234\n");
+}
+
+#[tokio::test]
+async fn push_events() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    let (session, mut rx, _shutdown) = connect(&fs, "ClueCon").await;
+    session.auth("ClueCon").await.unwrap();
+
+    fs.push_event(&event("CHANNEL_ANSWER"), FormatType::Json)
+        .await;
+    let ed = next(&mut rx, ContentType::TextEventJson)
+        .await
+        .event_data
+        .unwrap();
+    assert_eq!(ed, event("CHANNEL_ANSWER"));
+
+    fs.push_event(&event("CHANNEL_HANGUP"), FormatType::Plain)
+        .await;
+    let ed = next(&mut rx, ContentType::TextEventPlain)
+        .await
+        .event_data
+        .unwrap();
+    assert_eq!(ed.event(), Event::ChannelHangup);
+    assert_eq!(
+        ed.get_header("Caller-Caller-ID-Number".to_string()),
+        "1000 2"
+    );
+
+    let mut ed = event("CHANNEL_ANSWER");
+    ed.insert("Variable_sip_h_X-Tag".to_string(), json!("a<b & c>d"));
+    ed.insert("_body".to_string(), json!("line 1\nline 2"));
+    fs.push_event(&ed, FormatType::Xml).await;
+    let got = next(&mut rx, ContentType::TextEventXml)
+        .await
+        .event_data
+        .unwrap();
+    assert_eq!(got, ed);
+}
+
+#[tokio::test]
+async fn disconnect_and_close() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    let (session, mut rx, _shutdown) = connect(&fs, "ClueCon").await;
+    session.auth("ClueCon").await.unwrap();
+
+    fs.disconnect().await;
+    next(&mut rx, ContentType::TextDisconnectNotice).await;
+    assert_eq!(session.closed().await, CloseReason::RemoteExit);
+
+    let (session, _rx, _shutdown2) = connect(&fs, "ClueCon").await;
+    session.auth("ClueCon").await.unwrap();
+    fs.close().await;
+    for _ in 0..50 {
+        if session.is_closed().await {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(session.is_closed().await);
+    assert_eq!(session.close_reason(), Some(CloseReason::RemoteExit));
+    assert!(session.api("status").await.is_err());
+}
+
+#[tokio::test]
+async fn close_with_exit() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    let (session, mut rx, _shutdown) = connect(&fs, "ClueCon").await;
+    session.auth("ClueCon").await.unwrap();
+
+    assert_eq!(session.close().await, CloseReason::Exit);
+    next(&mut rx, ContentType::TextDisconnectNotice).await;
+    assert_eq!(fs.commands().await, vec!["auth ClueCon", "exit"]);
+    // closing again only gives the reason
+    assert_eq!(session.close().await, CloseReason::Exit);
+    assert!(matches!(
+        session
+            .api("status")
+            .await
+            .unwrap_err()
+            .downcast_ref::<MsgError>(),
+        Some(MsgError::ConnectionClosed)
+    ));
+}
+
+#[tokio::test]
+async fn shutdown_signal() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    let (session, _rx, shutdown) = connect(&fs, "ClueCon").await;
+    session.auth("ClueCon").await.unwrap();
+    fs.connected(1).await;
+
+    shutdown.send(true).unwrap();
+    assert_eq!(session.closed().await, CloseReason::Shutdown);
+    // dropping the last handle lets go of the connection
+    drop(session);
+    for _ in 0..50 {
+        if fs.connections().await == 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(fs.connections().await, 0);
+}
+
+#[tokio::test]
+async fn outbound_connect() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    let mut server = Server::new("127.0.0.1:0".to_string()).await.unwrap();
+    let addr = server.local_addr().unwrap();
+
+    fs.outbound(&addr.to_string(), event("CHANNEL_DATA"))
+        .await
+        .unwrap();
+    let (stream, _) = server.accept().await.unwrap();
+    let (tx, _) = broadcast::channel(100);
+    let (_shutdown, signal) = broadcast::channel(1);
+    let session = Session::new(stream, tx, signal).await;
+
+    let (channel, data) = ChannelHandle::connect(session).await.unwrap();
+    assert_eq!(channel.uuid(), "abc-123");
+    assert_eq!(
+        data.get_header("Caller-Caller-ID-Number".to_string()),
+        "1000 2"
+    );
+    assert_eq!(fs.commands().await, vec!["connect", "myevents json"]);
+    assert_eq!(channel.session().event_format(), Some(FormatType::Json));
+
+    channel
+        .execute("playback", "/tmp/hello world.wav")
+        .await
+        .unwrap();
+    let cmd = fs.commands().await.pop().unwrap();
+    assert!(cmd.starts_with("sendmsg abc-123\n"));
+    assert!(cmd.contains("\ncall-command: execute\n"));
+    assert!(cmd.contains("\nexecute-app-arg: /tmp/hello world.wav"));
+}
+
+#[tokio::test]
+async fn send_custom_event() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    let (session, mut rx, _shutdown) = connect(&fs, "ClueCon").await;
+    session.auth("ClueCon").await.unwrap();
+    session
+        .event(FormatType::Plain, &["CUSTOM", "rsesl::test"])
+        .await
+        .unwrap();
+
+    let ed = json!({ "Event-Subclass": "rsesl::test", "X-Note": "a b&c: d" })
+        .as_object()
+        .unwrap()
+        .clone();
+    let event_uuid = session
+        .send_event(Event::Custom, ed, Some("hello\nworld"))
+        .await
+        .unwrap();
+    assert!(!event_uuid.is_empty());
+
+    let cmd = fs.commands().await.pop().unwrap();
+    assert!(cmd.starts_with("sendevent CUSTOM\n"));
+    assert!(cmd.contains("\nX-Note: a%20b%26c%3A%20d\n"));
+
+    let ed = next(&mut rx, ContentType::TextEventPlain)
+        .await
+        .event_data
+        .unwrap();
+    assert_eq!(ed.event(), Event::Custom);
+    assert_eq!(ed.subclass(), "rsesl::test");
+    assert_eq!(ed.get_header("X-Note".to_string()), "a b&c: d");
+    assert_eq!(ed.get_header("Event-UUID".to_string()), event_uuid);
+    assert_eq!(ed.get_header("_body".to_string()), "hello\nworld");
+}
+
+#[tokio::test]
+async fn liveness_declares_dead() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    let other = MockFreeswitch::start("ClueCon").await.unwrap();
+    for f in [&fs, &other] {
+        f.api("status", "UP 0 years, 0 days\n").await;
+    }
+    let liveness = Liveness::new(Duration::from_millis(300)).ping(Duration::from_millis(50));
+    // both sessions send their messages to the same channel
+    let (tx, _) = broadcast::channel(100);
+    let (_shutdown, signal) = broadcast::channel(1);
+    let session = Client::new(fs.addr().to_string(), "ClueCon".to_string())
+        .keepalive(Duration::from_secs(10))
+        .liveness(liveness.clone())
+        .connect(tx.clone(), signal.resubscribe())
+        .await
+        .unwrap();
+    let busy = Client::new(other.addr().to_string(), "ClueCon".to_string())
+        .liveness(liveness)
+        .connect(tx, signal)
+        .await
+        .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(session.health().borrow().latency.is_some());
+    assert!(session.health().borrow().alive);
+
+    // a half-open connection, nothing comes back while the other session
+    // still reads its pings
+    fs.mute(true);
+    let reason = tokio::time::timeout(Duration::from_secs(2), session.closed())
+        .await
+        .unwrap();
+    assert_eq!(reason, CloseReason::Dead);
+    assert!(!session.health().borrow().alive);
+    assert!(busy.health().borrow().alive);
+    assert!(!busy.is_closed().await);
+}
+
+#[tokio::test]
+async fn liveness_after_auth_only() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    fs.api("status", "UP 0 years, 0 days\n").await;
+    let liveness = Liveness::new(Duration::from_millis(300)).ping(Duration::from_millis(20));
+    let client =
+        Client::new(fs.addr().to_string(), "ClueCon".to_string()).liveness(liveness.clone());
+    let (tx, _) = broadcast::channel(100);
+    let (_shutdown, signal) = broadcast::channel(1);
+
+    // not watched before auth, a ping would be refused
+    let session = client.new_session(tx, signal).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(fs.commands().await.is_empty());
+    assert!(session.health().borrow().latency.is_none());
+    session.auth("ClueCon").await.unwrap();
+
+    // the watch alone does not keep the session open
+    let watch = liveness.watch(&session);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(session.health().borrow().latency.is_some());
+    let weak = session.downgrade();
+    drop(session);
+    tokio::time::timeout(Duration::from_secs(2), watch)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(weak.closed().await, CloseReason::Shutdown);
+    assert!(weak.upgrade().is_none());
+}
+
+#[tokio::test]
+async fn keep_connected_reconnects() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    let client = Client::new(fs.addr().to_string(), "ClueCon".to_string());
+    let (tx, _) = broadcast::channel(100);
+    let (_shutdown, signal) = broadcast::channel(1);
+    let mut sessions = client.keep_connected(tx, signal);
+
+    sessions.wait_for(Option::is_some).await.unwrap();
+    fs.close().await;
+    sessions.wait_for(Option::is_none).await.unwrap();
+    let session = tokio::time::timeout(Duration::from_secs(2), sessions.wait_for(Option::is_some))
+        .await
+        .unwrap()
+        .unwrap()
+        .clone()
+        .unwrap();
+    assert_eq!(fs.commands().await, vec!["auth ClueCon", "auth ClueCon"]);
+
+    // closing on purpose is not reconnected
+    assert_eq!(session.close().await, CloseReason::Exit);
+    sessions.changed().await.unwrap();
+    assert!(sessions.borrow().is_none());
+    assert!(sessions.changed().await.is_err());
+}
+
+#[tokio::test]
+async fn timeout_keeps_replies_in_step() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    fs.api("status", "UP 0 years, 0 days\n").await;
+    fs.api("slow", "+OK slow\n").await;
+    fs.delay("slow", Duration::from_millis(300)).await;
+    let config = ClientConfig::new().command_timeout(Some(Duration::from_millis(100)));
+    let client = Client::new(fs.addr().to_string(), "ClueCon".to_string()).config(config);
+    let (tx, _) = broadcast::channel(100);
+    let (_shutdown, signal) = broadcast::channel(1);
+    let session = client.connect(tx, signal).await.unwrap();
+    assert_eq!(session.timeout(), Some(Duration::from_millis(100)));
+
+    let err = session.api("slow").await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<MsgError>(),
+        Some(MsgError::Timeout(_))
+    ));
+    // the late reply of slow is not taken for the reply of status
+    let status = session.with_timeout(None).api("status").await.unwrap();
+    assert_eq!(status, "UP 0 years, 0 days\n");
+    assert!(!session.is_closed().await);
+}
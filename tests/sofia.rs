@@ -0,0 +1,150 @@
+use rsesl::{
+    message::MsgError,
+    sofia::{
+        parse_key_values, parse_status_table, parse_xml_records, EntryType, Gateway, GatewayState,
+        PingStatus, Sofia,
+    },
+    testing::MockFreeswitch,
+};
+use tokio::sync::broadcast;
+
+const STATUS: &str =
+    "                     Name\t   Type\t                                      Data\tState
+=================================================================================================
+            external\tprofile\t          sip:mod_sofia@1.2.3.4:5080\tRUNNING (0)
+    external::carrier\tgateway\t                 sip:carrier.example.com\tREGED
+            1.2.3.4\t  alias\t                                  internal\tALIASED
+            internal\tprofile\t          sip:mod_sofia@1.2.3.4:5060\tRUNNING (2)
+=================================================================================================
+2 profiles 1 alias
+";
+
+const GATEWAY: &str = "=================================================================================================
+Name    \tcarrier
+Profile \texternal
+Scheme  \tDigest
+Realm   \tcarrier.example.com
+Username\t1000
+Expires \t3600
+Freq    \t3600
+PingTime\t12.50
+State   \tREGED
+Status  \tUP
+CallsIN \t3
+CallsOUT\t7
+FailedCallsIN\t0
+FailedCallsOUT\t1
+=================================================================================================
+";
+
+const XMLSTATUS: &str = r#"<?xml version="1.0" encoding="ISO-8859-1" ?>
+<profiles>
+  <profile>
+    <name>external</name>
+    <type>profile</type>
+    <data>sip:mod_sofia@1.2.3.4:5080</data>
+    <state>RUNNING (0)</state>
+  </profile>
+  <gateway>
+    <name>external::carrier</name>
+    <type>gateway</type>
+    <data><![CDATA[sip:carrier.example.com]]></data>
+    <state>REGED</state>
+  </gateway>
+</profiles>
+"#;
+
+const REGISTRATIONS: &str = r#"<profile>
+  <registrations>
+    <registration>
+      <call-id>abc@1.2.3.5</call-id>
+      <user>1000@example.com</user>
+      <contact>"1000" &lt;sip:1000@1.2.3.5:5060&gt;</contact>
+      <agent>Phone/1.0</agent>
+      <ping-time>0.00</ping-time>
+      <network-ip>1.2.3.5</network-ip>
+      <network-port>5060</network-port>
+    </registration>
+  </registrations>
+</profile>
+"#;
+
+// the shutdown sender has to outlive the session
+async fn sofia(fs: &MockFreeswitch) -> (Sofia, broadcast::Sender<bool>) {
+    let (session, shutdown) = fs.session().await.unwrap();
+    (Sofia::new(session), shutdown)
+}
+
+#[test]
+fn parses_status_table() {
+    let entries = parse_status_table(STATUS);
+    assert_eq!(entries.len(), 4);
+    assert_eq!(entries[0].name, "external");
+    assert_eq!(entries[0].kind, EntryType::Profile);
+    assert_eq!(entries[0].state, "RUNNING (0)");
+    assert_eq!(entries[1].kind, EntryType::Gateway);
+    assert_eq!(entries[1].data, "sip:carrier.example.com");
+    assert_eq!(entries[2].kind, EntryType::Alias);
+}
+
+#[test]
+fn parses_key_values() {
+    let gw = Gateway::from(&parse_key_values(GATEWAY));
+    assert_eq!(gw.name, "carrier");
+    assert_eq!(gw.profile, "external");
+    assert_eq!(gw.username, "1000");
+    assert_eq!(gw.ping_time, 12.5);
+    assert_eq!(gw.state, GatewayState::Reged);
+    assert!(gw.state.is_up());
+    assert_eq!(gw.status, PingStatus::Up);
+    assert_eq!((gw.calls_in, gw.calls_out), (3, 7));
+    assert_eq!(gw.failed_calls_out, 1);
+}
+
+#[test]
+fn parses_xml_records() {
+    let records = parse_xml_records(XMLSTATUS, &["profile", "gateway"]).unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].0, "profile");
+    assert_eq!(records[1].1["data"], "sip:carrier.example.com");
+
+    // nested in other elements, field names lose their dashes
+    let regs = parse_xml_records(REGISTRATIONS, &["registration"]).unwrap();
+    assert_eq!(regs.len(), 1);
+    assert_eq!(regs[0].1["callid"], "abc@1.2.3.5");
+    assert_eq!(regs[0].1["contact"], "\"1000\" <sip:1000@1.2.3.5:5060>");
+
+    assert!(parse_xml_records("<a><b></a>", &["b"]).is_err());
+}
+
+#[tokio::test]
+async fn sofia_api() {
+    let fs = MockFreeswitch::start("ClueCon").await.unwrap();
+    fs.api("sofia status", STATUS).await;
+    fs.api("sofia xmlstatus", XMLSTATUS).await;
+    fs.api("sofia status gateway carrier", GATEWAY).await;
+    fs.api("sofia status profile nope", "Invalid Profile!\n")
+        .await;
+    fs.api("sofia xmlstatus profile internal reg", REGISTRATIONS)
+        .await;
+    let (sofia, _shutdown) = sofia(&fs).await;
+
+    assert_eq!(sofia.status().await.unwrap().len(), 4);
+    let entries = sofia.xmlstatus().await.unwrap();
+    assert_eq!(entries[1].name, "external::carrier");
+    assert_eq!(entries[1].kind, EntryType::Gateway);
+    assert_eq!(
+        sofia.gateway("carrier").await.unwrap().realm,
+        "carrier.example.com"
+    );
+
+    let regs = sofia.registrations("internal").await.unwrap();
+    assert_eq!(regs[0].user, "1000@example.com");
+    assert_eq!(regs[0].network_port, "5060");
+
+    let err = sofia.profile("nope").await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<MsgError>(),
+        Some(MsgError::ErrResponse(_))
+    ));
+}
@@ -0,0 +1,214 @@
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use rsesl::event::EventData;
+use rsesl::message::MsgError;
+use rsesl::webhook::{sign, Rule, WebhookSink, SIGNATURE_HEADER};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::mpsc;
+
+struct Received {
+    signature: String,
+    call: String,
+    body: String,
+}
+
+// answers 500 to the first `failures` requests, 200 afterwards
+async fn server(failures: usize) -> (SocketAddr, mpsc::UnboundedReceiver<Received>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let hits = Arc::new(AtomicUsize::new(0));
+
+    let make = make_service_fn(move |_| {
+        let tx = tx.clone();
+        let hits = hits.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let tx = tx.clone();
+                let hits = hits.clone();
+                async move {
+                    let header = |k: &str| {
+                        req.headers()
+                            .get(k)
+                            .and_then(|v| v.to_str().ok())
+                            .unwrap_or("")
+                            .to_string()
+                    };
+                    let signature = header(SIGNATURE_HEADER);
+                    let call = header("X-Call");
+                    let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                    let _ = tx.send(Received {
+                        signature,
+                        call,
+                        body: String::from_utf8(body.to_vec()).unwrap(),
+                    });
+
+                    let status = if hits.fetch_add(1, Ordering::SeqCst) < failures {
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    } else {
+                        StatusCode::OK
+                    };
+                    Ok::<_, Infallible>(
+                        Response::builder()
+                            .status(status)
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                }
+            }))
+        }
+    });
+
+    let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    (addr, rx)
+}
+
+fn answer_event() -> EventData {
+    json!({
+        "Event-Name": "CHANNEL_ANSWER",
+        "Unique-ID": "abc-123",
+        "Call-Direction": "inbound",
+    })
+    .as_object()
+    .unwrap()
+    .clone()
+}
+
+fn rule(addr: SocketAddr) -> Rule {
+    Rule {
+        event: Some("CHANNEL_ANSWER".to_string()),
+        when: HashMap::from([("Call-Direction".to_string(), "inbound".to_string())]),
+        url: format!("http://{}/hook", addr),
+        headers: HashMap::from([("X-Call".to_string(), "call ${Unique-ID}".to_string())]),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn retries_until_delivered() {
+    let (addr, mut rx) = server(2).await;
+    let sink = WebhookSink::new(vec![rule(addr)])
+        .unwrap()
+        .retries(3, Duration::from_millis(10))
+        .secret(b"s3cret");
+
+    sink.dispatch(&answer_event()).await;
+
+    for _ in 0..3 {
+        let r = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(r.call, "call abc-123");
+        assert_eq!(r.signature, sign(b"s3cret", r.body.as_bytes()));
+        let ed: EventData = serde_json::from_str(&r.body).unwrap();
+        assert_eq!(ed, answer_event());
+    }
+    // delivered on the third attempt, nothing more
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn skips_events_not_matching() {
+    let (addr, mut rx) = server(0).await;
+    let sink = WebhookSink::new(vec![rule(addr)]).unwrap();
+
+    let mut ed = answer_event();
+    ed.insert("Call-Direction".to_string(), json!("outbound"));
+    sink.dispatch(&ed).await;
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn dead_letter_after_last_retry() {
+    let (addr, mut rx) = server(usize::MAX).await;
+    let path = std::env::temp_dir().join(format!("rsesl-dead-letter-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let sink = WebhookSink::new(vec![rule(addr)])
+        .unwrap()
+        .retries(1, Duration::from_millis(10))
+        .dead_letter(path.clone());
+
+    sink.dispatch(&answer_event()).await;
+    for _ in 0..2 {
+        tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    let mut lines = String::new();
+    for _ in 0..50 {
+        lines = std::fs::read_to_string(&path).unwrap_or_default();
+        if !lines.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(lines.lines().count(), 1);
+    let dead: serde_json::Value = serde_json::from_str(lines.trim()).unwrap();
+    assert_eq!(dead["url"], format!("http://{}/hook", addr));
+    assert_eq!(dead["event"]["Unique-ID"], "abc-123");
+}
+
+#[test]
+fn rejects_urls_without_http() {
+    for url in ["https://example.com/hook", "example.com/hook", "not a url"] {
+        let r = Rule {
+            url: url.to_string(),
+            ..Default::default()
+        };
+        let err = WebhookSink::new(vec![r]).err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<MsgError>(),
+            Some(MsgError::InvalidArgument(_))
+        ));
+    }
+}
+
+#[tokio::test]
+async fn invalid_header_goes_to_dead_letter() {
+    let (addr, mut rx) = server(0).await;
+    let path = std::env::temp_dir().join(format!("rsesl-dead-header-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let sink = WebhookSink::new(vec![rule(addr)])
+        .unwrap()
+        .retries(3, Duration::from_secs(60))
+        .dead_letter(path.clone());
+
+    // a newline can never be sent in a header
+    let mut ed = answer_event();
+    ed.insert("Unique-ID".to_string(), json!("abc\n123"));
+    sink.dispatch(&ed).await;
+
+    let mut lines = String::new();
+    for _ in 0..50 {
+        lines = std::fs::read_to_string(&path).unwrap_or_default();
+        if !lines.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let _ = std::fs::remove_file(&path);
+
+    // written at once, without a request or a retry
+    assert_eq!(lines.lines().count(), 1);
+    assert!(rx.try_recv().is_err());
+}
@@ -0,0 +1,96 @@
+use hyper::{body, Body, Client, Method, Request, StatusCode};
+use rsesl::xml_curl::{
+    Condition, Context, Document, Domain, Extension, FetchRequest, OnError, Section, User, XmlCurl,
+};
+use std::{collections::HashMap, net::SocketAddr};
+
+async fn serve(x: XmlCurl) -> SocketAddr {
+    let server =
+        axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(x.router().into_make_service());
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    addr
+}
+
+async fn post(addr: SocketAddr, form: &str) -> (StatusCode, String) {
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(format!("http://{}/", addr))
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(Body::from(form.to_string()))
+        .unwrap();
+    let res = Client::new().request(req).await.unwrap();
+    let status = res.status();
+    let bytes = body::to_bytes(res.into_body()).await.unwrap();
+    (status, String::from_utf8(bytes.to_vec()).unwrap())
+}
+
+fn xml_curl() -> XmlCurl {
+    XmlCurl::new()
+        .directory(|req: FetchRequest| async move {
+            if req.param("user") != "1000" {
+                return Ok(None);
+            }
+            let user = User::new("1000").param("password", "p&ss");
+            Ok(Some(Document::directory(
+                Domain::new(&req.param("domain")).user(user),
+            )))
+        })
+        .dialplan(|_| async { Err(anyhow::anyhow!("database down")) })
+}
+
+#[test]
+fn renders_documents() {
+    let ctx = Context::new("default").extension(
+        Extension::new("echo")
+            .continue_on_match()
+            .condition(Condition::new("destination_number", "^9196$").action("echo", "")),
+    );
+    let xml = Document::dialplan(ctx).to_string();
+    assert!(xml.starts_with("<?xml version=\"1.0\""));
+    assert!(xml.contains("<section name=\"dialplan\">"));
+    assert!(xml.contains("<extension name=\"echo\" continue=\"true\">"));
+    assert!(xml.contains("<condition field=\"destination_number\" expression=\"^9196$\">"));
+    assert!(xml.contains("<action application=\"echo\"/>"));
+
+    let xml = Document::not_found().to_string();
+    assert!(xml.contains("<result status=\"not found\"/>"));
+}
+
+#[tokio::test]
+async fn answers_fetches() {
+    let addr = serve(xml_curl()).await;
+
+    let (status, xml) = post(addr, "section=directory&user=1000&domain=example.com").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(xml.contains("<domain name=\"example.com\">"));
+    assert!(xml.contains("<param name=\"password\" value=\"p&amp;ss\"/>"));
+
+    // unknown user, section without a handler
+    for form in ["section=directory&user=2000", "section=phrases"] {
+        let (status, xml) = post(addr, form).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(xml.contains("status=\"not found\""));
+    }
+}
+
+#[tokio::test]
+async fn failed_handler() {
+    // falls back to the local XML by default
+    let addr = serve(xml_curl()).await;
+    let (status, xml) = post(addr, "section=dialplan").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(xml.contains("status=\"not found\""));
+
+    let addr = serve(xml_curl().on_error(OnError::ServerError)).await;
+    let (status, _) = post(addr, "section=dialplan").await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+
+    let req = FetchRequest::from(HashMap::from([(
+        "section".to_string(),
+        "dialplan".to_string(),
+    )]));
+    assert_eq!(req.section, Section::Dialplan);
+    let err = xml_curl().fetch(req).await.unwrap_err();
+    assert_eq!(err.to_string(), "database down");
+}